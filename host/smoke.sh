@@ -0,0 +1,113 @@
+#!/bin/sh
+# End-to-end check of rfid-host against its device emulator; needs no hardware.
+#
+#   host/smoke.sh [path/to/rfid-host]
+
+set -eu
+
+BIN=$(realpath "${1:-target/x86_64-unknown-linux-gnu/debug/rfid-host}")
+DIR=$(mktemp -d)
+EMU=
+trap '[ -n "$EMU" ] && kill "$EMU"; rm -rf "$DIR"' EXIT
+cd "$DIR"
+
+fail() {
+    echo "FAIL: $*" >&2
+    exit 1
+}
+
+# ST25TB512-AT tag as a Proxmark .eml, UID from the file name; 16 blocks and
+# the system block last
+TAG=hf-14b-D00230123456789A-dump.eml
+{
+    echo FFFFFFFF
+    echo FFFFFFFF
+    echo FFFFFFFF
+    echo FFFFFFFF
+    echo FFFFFFFF
+    echo FEFFFFFF
+    echo FFFFFFFF
+    for i in 7 8 9 10 11 12 13 14 15; do printf '0000000%X\n' "$i"; done
+    echo FFFFFFFF
+} >"$TAG"
+
+"$BIN" emulate "$TAG" >port.txt &
+EMU=$!
+for _ in 1 2 3 4 5 6 7 8 9 10; do
+    [ -s port.txt ] && break
+    sleep 0.2
+done
+PORT=$(cat port.txt)
+[ -n "$PORT" ] || fail "emulator did not start"
+export RFID_PORT="$PORT"
+
+"$BIN" info | grep -q emulator || fail "info"
+
+"$BIN" dump read.dmp
+"$BIN" diff "$TAG" read.dmp || fail "dump differs from the tag"
+
+for ext in nfc bin eml json txt; do
+    "$BIN" convert read.dmp "conv.$ext"
+    "$BIN" convert "conv.$ext" back.dmp
+    # .bin and .eml carry no UID
+    case $ext in
+    bin | eml) "$BIN" diff read.dmp back.dmp | grep -q '^UID' || fail "$ext UID" ;;
+    *) "$BIN" diff read.dmp back.dmp || fail "$ext round trip" ;;
+    esac
+done
+
+"$BIN" convert read.dmp edit.txt
+sed 's/^B010: .*/B010: 12 34 56 78/' edit.txt >edited.txt
+"$BIN" write edited.txt
+"$BIN" dump after.dmp
+"$BIN" diff read.dmp after.dmp >diff.txt && fail "write had no effect"
+head -n1 diff.txt | grep -qx 'B010: 00 00 00 0A -> 12 34 56 78  ^ 12 34 56 72  +12 -1' ||
+    fail "diff: $(cat diff.txt)"
+tail -n1 diff.txt | grep -qx '1 blocks, 4 bytes, 13 bits differ' || fail "diff: $(cat diff.txt)"
+"$BIN" diff --json read.dmp after.dmp | grep -q '"block":10,"old":"0000000A","new":"12345678","bytes":\[0,1,2,3\]' ||
+    fail "diff --json"
+"$BIN" analyze read.dmp after.dmp | grep -qx 'B010       \.\. \.\. \.\. \.\.  \^ \^ \^ \^  counter, u32 little endian' ||
+    fail "analyze: $("$BIN" analyze read.dmp after.dmp)"
+"$BIN" analyze --profile read.dmp after.dmp >seed.ini
+"$BIN" decode after.dmp seed.ini | grep -q '^  counter_10 ' || fail "seeded profile: $(cat seed.ini)"
+# B012:0 holds the sum of B011
+for sum in '01 02 03 04:0A' '10 20 30 40:A0' '11 11 11 11:44'; do
+    sed -e "s/^B011: .*/B011: ${sum%:*}/" -e "s/^B012: ../B012: ${sum#*:}/" edit.txt >"sum-${sum#*:}.txt"
+done
+"$BIN" checksum sum-*.txt | grep -qx 'sum8  B011:0-B011:3 (4 bytes) -> B012:0, 3 values' ||
+    fail "checksum: $("$BIN" checksum sum-*.txt)"
+printf '[profile]\nname = Sum\n[field sum]\nblock = 12\ntype = checksum\nalgorithm = sum8\ncovers = B011:0-B011:3\n' >sum.ini
+"$BIN" decode sum-0A.txt sum.ini | grep -q '^  sum  0A  *B012$' || fail "checksum field: $("$BIN" decode sum-0A.txt sum.ini)"
+"$BIN" decode edit.txt sum.ini | grep -q '^  sum  00  *B012  BAD, expected 0B$' || fail "bad checksum: $("$BIN" decode edit.txt sum.ini)"
+
+"$BIN" patch read.dmp after.dmp >edit.patch
+grep -qx 'B010: 00 00 00 0A -> 12 34 56 78' edit.patch || fail "patch: $(cat edit.patch)"
+"$BIN" apply edit.patch read.dmp patched.dmp | grep -q '1 blocks changed' || fail "apply"
+"$BIN" diff after.dmp patched.dmp || fail "patched dump differs"
+"$BIN" apply edit.patch after.dmp again.dmp | grep -q '0 blocks changed' || fail "apply twice"
+sed 's/^B010: .*/B010: 00 00 FF 0A/' edit.txt >other.txt
+"$BIN" apply edit.patch other.txt other.dmp 2>conflict.txt && fail "conflict not detected"
+grep -q '^conflict B010: want 00 00 00 0A found 00 00 FF 0A' conflict.txt || fail "conflict: $(cat conflict.txt)"
+[ -e other.dmp ] && fail "conflicting patch wrote a file"
+"$BIN" apply --force edit.patch other.txt other.dmp 2>/dev/null | grep -q '1 blocks changed' || fail "apply --force"
+# Checksum fields of a --profile profile follow the patched bytes
+sed 's/^B011: .*/B011: 10 20 30 40/' sum-0A.txt >sum-stale.txt
+"$BIN" patch sum-0A.txt sum-stale.txt >sum.patch
+"$BIN" apply --profile sum.ini sum.patch sum-0A.txt summed.dmp >/dev/null
+"$BIN" decode summed.dmp sum.ini | grep -q '^  sum  A0  *B012$' || fail "apply --profile: $("$BIN" decode summed.dmp sum.ini)"
+
+"$BIN" put after.dmp
+"$BIN" put read.dmp original.dmp
+"$BIN" list >list.txt
+grep -q '^D00230123456789A-001.DMP' list.txt || fail "list: $(cat list.txt)"
+grep -q '^original.dmp' list.txt || fail "list: $(cat list.txt)"
+"$BIN" get original.dmp got.dmp
+"$BIN" diff read.dmp got.dmp || fail "get"
+"$BIN" delete original.dmp
+"$BIN" get original.dmp got.dmp 2>/dev/null && fail "delete"
+
+"$BIN" decode after.dmp | grep -q 'ST25TB512-AT, 16 blocks' || fail "decode"
+"$BIN" decode read.dmp | grep -q "^Read: *$(date -u +%Y-%m-%d)" || fail "read date: $("$BIN" decode read.dmp)"
+[ "$("$BIN" pn532 02)" = "32 01 06 07" ] || fail "pn532"
+
+echo "smoke test passed"
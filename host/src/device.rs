@@ -0,0 +1,129 @@
+//! Serial link to the device
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::termios::{self, SetArg};
+
+use crate::protocol::host::{Command, Frame, FrameError, FrameReader, Input, Status, RESPONSE};
+use crate::Error;
+
+/// Reading a 4K tag takes a few seconds, writing one with verification longer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Device {
+    port: File,
+    reader: FrameReader,
+    frames: VecDeque<Result<Frame, FrameError>>,
+    seq: u8,
+    /// Copy the device's log text to stderr.
+    pub echo_log: bool,
+}
+
+impl Device {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| Error::Io(path.into(), e))?;
+        // USB Serial/JTAG ignores the baud rate; only raw mode matters
+        let mut tio = termios::tcgetattr(&port)?;
+        termios::cfmakeraw(&mut tio);
+        termios::tcsetattr(&port, SetArg::TCSANOW, &tio)?;
+        Ok(Self {
+            port,
+            reader: FrameReader::new(),
+            frames: VecDeque::new(),
+            seq: 0,
+            echo_log: false,
+        })
+    }
+
+    /// Gives the device the time for the read dates of its dumps; it has no
+    /// clock of its own. Firmware without the command is left alone.
+    pub fn set_time(&mut self) -> Result<(), Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32);
+        match self.request(Command::SetTime, &now.to_le_bytes()) {
+            Err(Error::Device(Some(Status::UnknownCommand), _)) => Ok(()),
+            other => other.map(|_| ()),
+        }
+    }
+
+    /// Sends a request and waits for its response. Returns the payload after
+    /// the status byte, or the error status and message.
+    pub fn request(&mut self, command: Command, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        self.seq = self.seq.wrapping_add(1);
+        let req = Frame {
+            code: command as u8,
+            seq: self.seq,
+            payload: payload.to_vec(),
+        };
+        self.port
+            .write_all(&req.encode())
+            .map_err(|e| Error::Io("write".into(), e))?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let frame = self
+                .next_frame(deadline)?
+                .map_err(|e| Error::Protocol(format!("bad frame: {:?}", e)))?;
+            // Late answers to an earlier, timed-out request
+            if frame.seq != req.seq || frame.code != req.code | RESPONSE {
+                continue;
+            }
+            let Some((&status, rest)) = frame.payload.split_first() else {
+                return Err(Error::Protocol("empty response".into()));
+            };
+            return match Status::from_code(status) {
+                Some(Status::Ok) => Ok(rest.to_vec()),
+                status => Err(Error::Device(
+                    status,
+                    String::from_utf8_lossy(rest).into_owned(),
+                )),
+            };
+        }
+    }
+
+    fn next_frame(&mut self, deadline: Instant) -> Result<Result<Frame, FrameError>, Error> {
+        let mut buf = [0u8; 512];
+        loop {
+            if let Some(frame) = self.frames.pop_front() {
+                return Ok(frame);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(Error::Timeout);
+            }
+            let timeout = PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX);
+            let mut fds = [PollFd::new(self.port.as_fd(), PollFlags::POLLIN)];
+            if poll(&mut fds, timeout)? == 0 {
+                continue;
+            }
+            let n = self
+                .port
+                .read(&mut buf)
+                .map_err(|e| Error::Io("read".into(), e))?;
+            if n == 0 {
+                return Err(Error::Protocol("port closed".into()));
+            }
+            for &byte in &buf[..n] {
+                match self.reader.push(byte) {
+                    Input::None => {}
+                    Input::Text(c) => {
+                        if self.echo_log {
+                            let _ = std::io::stderr().write_all(&[c]);
+                        }
+                    }
+                    Input::Frame(frame) => self.frames.push_back(frame),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Device stand-in on a pseudo-terminal
+//!
+//! `emulate` opens a pty, prints the path of its device end and answers host
+//! protocol requests like the firmware does: one tag on the antenna, an editor
+//! slot and a dump store in memory. Log lines are mixed into the output as on
+//! the real link. The other commands run against it without hardware (see
+//! `host/smoke.sh`).
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+use nix::pty::openpty;
+use nix::sys::termios::{self, SetArg};
+use nix::unistd::ttyname;
+
+use crate::drivers::storage::{decode_dump, encode_dump, DumpStore, StorageError};
+use crate::protocol::host::{self, Command, Frame, FrameReader, Info, Input, Status};
+use crate::protocol::st25tb::{otp_transition_ok, BlockKind, ChipData, FLAG_UNREADABLE};
+use crate::Error;
+
+/// PN532 IC, firmware version and revision.
+const PN532_VERSION: [u8; 3] = [0x32, 0x01, 0x06];
+const PN532_GET_FIRMWARE_VERSION: u8 = 0x02;
+
+#[derive(Default)]
+struct MemoryStore {
+    dumps: BTreeMap<String, ChipData>,
+    patches: BTreeMap<String, String>,
+}
+
+impl DumpStore for MemoryStore {
+    fn list_dumps(&mut self) -> Result<Vec<String>, StorageError> {
+        Ok(self.dumps.keys().cloned().collect())
+    }
+
+    fn save_dump(&mut self, name: &str, data: &ChipData) -> Result<(), StorageError> {
+        self.dumps.insert(name.into(), data.clone());
+        Ok(())
+    }
+
+    fn load_dump(&mut self, name: &str) -> Result<ChipData, StorageError> {
+        self.dumps.get(name).cloned().ok_or(StorageError::NotFound)
+    }
+
+    fn delete_dump(&mut self, name: &str) -> Result<(), StorageError> {
+        self.dumps
+            .remove(name)
+            .map(|_| ())
+            .ok_or(StorageError::NotFound)
+    }
+
+    fn list_patches(&mut self) -> Result<Vec<String>, StorageError> {
+        Ok(self.patches.keys().cloned().collect())
+    }
+
+    fn save_patch(&mut self, name: &str, text: &str) -> Result<(), StorageError> {
+        self.patches.insert(name.into(), text.into());
+        Ok(())
+    }
+
+    fn load_patch(&mut self, name: &str) -> Result<String, StorageError> {
+        self.patches
+            .get(name)
+            .cloned()
+            .ok_or(StorageError::NotFound)
+    }
+}
+
+struct Emulator {
+    /// Tag on the antenna; `None` answers like an empty field.
+    tag: Option<ChipData>,
+    editor: Option<ChipData>,
+    store: MemoryStore,
+    /// Log text sent ahead of the next response.
+    log: String,
+    /// Unix time set by the host and when, for read dates.
+    clock: Option<(u32, Instant)>,
+}
+
+impl Emulator {
+    fn info(&mut self, msg: std::fmt::Arguments) {
+        self.log.push_str(&format!("INFO - {}\r\n", msg));
+    }
+
+    fn error_reply(req: &Frame, status: Status, error: impl Debug) -> Frame {
+        req.reply(status, format!("{:?}", error).as_bytes())
+    }
+
+    fn serve(&mut self, req: &Frame) -> Frame {
+        let Some(command) = Command::from_code(req.code) else {
+            return req.reply(Status::UnknownCommand, &[]);
+        };
+        let payload = req.payload.as_slice();
+        match command {
+            Command::GetInfo => {
+                let info = Info {
+                    protocol: host::PROTOCOL_VERSION,
+                    storage: 2,
+                    dump_loaded: self.editor.is_some(),
+                    pn532: PN532_VERSION,
+                    firmware: format!("{} (emulator)", env!("CARGO_PKG_VERSION")),
+                };
+                req.reply(Status::Ok, &info.encode())
+            }
+            Command::ReadChip => match self.tag.clone() {
+                Some(mut data) => {
+                    data.timestamp = self
+                        .clock
+                        .map_or(0, |(time, at)| time + at.elapsed().as_secs() as u32);
+                    self.info(format_args!("Host read, UID: {:02X?}", data.uid));
+                    let dump = encode_dump(&data);
+                    self.editor = Some(data);
+                    req.reply(Status::Ok, &dump)
+                }
+                None => req.reply(Status::Tag, b"Timeout"),
+            },
+            Command::WriteChip => {
+                let data = if payload.is_empty() {
+                    match self.editor.clone() {
+                        Some(data) => data,
+                        None => return req.reply(Status::NoData, &[]),
+                    }
+                } else {
+                    match decode_dump(payload) {
+                        Ok(data) => data,
+                        Err(e) => return Self::error_reply(req, Status::BadRequest, e),
+                    }
+                };
+                match self.write_tag(&data) {
+                    Ok(()) => req.reply(Status::Ok, &[]),
+                    Err(msg) => req.reply(Status::Tag, msg.as_bytes()),
+                }
+            }
+            Command::GetDump if payload.is_empty() => match &self.editor {
+                Some(data) => req.reply(Status::Ok, &encode_dump(data)),
+                None => req.reply(Status::NoData, &[]),
+            },
+            Command::PutDump => {
+                let Some((name, dump)) = host::decode_put(payload) else {
+                    return req.reply(Status::BadRequest, &[]);
+                };
+                let data = match decode_dump(dump) {
+                    Ok(d) => d,
+                    Err(e) => return Self::error_reply(req, Status::BadRequest, e),
+                };
+                if name.is_empty() {
+                    self.editor = Some(data);
+                    return req.reply(Status::Ok, &[]);
+                }
+                match self.store.save_dump(name, &data) {
+                    Ok(_) => req.reply(Status::Ok, &[]),
+                    Err(e) => Self::error_reply(req, Status::Storage, e),
+                }
+            }
+            Command::GetDump | Command::ListDumps | Command::DeleteDump => {
+                let Ok(name) = core::str::from_utf8(payload) else {
+                    return req.reply(Status::BadRequest, &[]);
+                };
+                let result = match command {
+                    Command::GetDump => self.store.load_dump(name).map(|d| encode_dump(&d)),
+                    Command::ListDumps => self.store.list_info().map(|l| host::encode_list(&l)),
+                    _ => self.store.delete_dump(name).map(|_| Vec::new()),
+                };
+                match result {
+                    Ok(out) => req.reply(Status::Ok, &out),
+                    Err(e) => Self::error_reply(req, Status::Storage, e),
+                }
+            }
+            Command::Pn532 => match payload {
+                [PN532_GET_FIRMWARE_VERSION] => {
+                    let mut response = PN532_VERSION.to_vec();
+                    // supported protocols: ISO 14443-A and B, ISO 18092
+                    response.push(0x07);
+                    req.reply(Status::Ok, &response)
+                }
+                _ => req.reply(Status::Pn532, b"InvalidArgument"),
+            },
+            Command::SetTime => match <[u8; 4]>::try_from(payload) {
+                Ok(bytes) => {
+                    self.clock = Some((u32::from_le_bytes(bytes), Instant::now()));
+                    req.reply(Status::Ok, &[])
+                }
+                Err(_) => req.reply(Status::BadRequest, &[]),
+            },
+        }
+    }
+
+    /// Applies the rules `St25tb::write_full_chip` works under: block 0 and
+    /// locked blocks are skipped, OTP bits only clear, counters only count
+    /// down. A block that does not take the new value fails verification.
+    fn write_tag(&mut self, data: &ChipData) -> Result<(), String> {
+        let tag = self.tag.as_mut().ok_or("Timeout")?;
+        let chip = tag.chip_type();
+        let mut written = Vec::new();
+        let mut mismatch = false;
+        for i in 1..data.block_count.min(tag.block_count) {
+            let (old, new) = (tag.blocks[i], data.blocks[i]);
+            if data.flags[i] & FLAG_UNREADABLE != 0 || old == new || tag.is_locked(i) {
+                continue;
+            }
+            match chip.block_kind(i) {
+                BlockKind::ResettableOtp if !otp_transition_ok(&old, &new) => {
+                    mismatch = true;
+                    tag.blocks[i] = [0, 1, 2, 3].map(|b| old[b] & new[b]);
+                }
+                BlockKind::Counter if data.counter(i) > tag.counter(i) => continue,
+                _ => tag.blocks[i] = new,
+            }
+            written.push(i);
+        }
+        for i in written {
+            self.info(format_args!("Writing block {}...", i));
+        }
+        if mismatch {
+            return Err("InvalidResponse".into());
+        }
+        Ok(())
+    }
+}
+
+pub fn run(tag: Option<ChipData>) -> Result<(), Error> {
+    let pty = openpty(None, None)?;
+    let mut tio = termios::tcgetattr(&pty.slave)?;
+    termios::cfmakeraw(&mut tio);
+    termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &tio)?;
+    println!("{}", ttyname(&pty.slave)?.display());
+    std::io::stdout()
+        .flush()
+        .map_err(|e| Error::Io("stdout".into(), e))?;
+
+    // Keeping the device end open lets clients come and go without the
+    // master side seeing a hangup.
+    let _slave = pty.slave;
+    let mut port = File::from(pty.master);
+    let mut emulator = Emulator {
+        tag,
+        editor: None,
+        store: MemoryStore::default(),
+        log: String::new(),
+        clock: None,
+    };
+    emulator.info(format_args!("Emulator ready"));
+
+    let mut reader = FrameReader::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let n = port
+            .read(&mut buf)
+            .map_err(|e| Error::Io("pty".into(), e))?;
+        for &byte in &buf[..n] {
+            let Input::Frame(Ok(req)) = reader.push(byte) else {
+                continue;
+            };
+            let reply = emulator.serve(&req);
+            let mut out = std::mem::take(&mut emulator.log).into_bytes();
+            out.extend_from_slice(&reply.encode());
+            port.write_all(&out)
+                .map_err(|e| Error::Io("pty".into(), e))?;
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Dump files, in the format their extension names
+
+use std::path::Path;
+
+use crate::drivers::storage::{decode_dump, encode_dump};
+use crate::interop::text::{self, TextImport};
+use crate::interop::{self, proxmark, Format};
+use crate::protocol::st25tb::ChipData;
+use crate::Error;
+
+const MAGIC: &[u8] = b"ST25";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileFormat {
+    /// The device's own binary format (`drivers::storage::format`).
+    Native,
+    /// "Dump Serial" text, or anything else `TextImport` reads.
+    Text,
+    Interop(Format),
+}
+
+impl FileFormat {
+    fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        if ext.eq_ignore_ascii_case("dmp") {
+            Some(FileFormat::Native)
+        } else if ext.eq_ignore_ascii_case("txt") || ext.eq_ignore_ascii_case("log") {
+            Some(FileFormat::Text)
+        } else {
+            Format::from_extension(ext).map(FileFormat::Interop)
+        }
+    }
+}
+
+pub fn load(path: &str) -> Result<ChipData, Error> {
+    let bytes = std::fs::read(path).map_err(|e| Error::Io(path.into(), e))?;
+    let format = FileFormat::from_path(path).unwrap_or(if bytes.starts_with(MAGIC) {
+        FileFormat::Native
+    } else {
+        FileFormat::Text
+    });
+    let invalid = |e: &dyn std::fmt::Debug| Error::File(format!("{}: {:?}", path, e));
+    match format {
+        FileFormat::Native => decode_dump(&bytes).map_err(|e| invalid(&e)),
+        FileFormat::Interop(f) => {
+            interop::import(&bytes, Some(f), proxmark::uid_from_filename(path))
+                .map_err(|e| invalid(&e))
+        }
+        FileFormat::Text => {
+            let mut import = TextImport::new();
+            for &b in &bytes {
+                if let Some((line, text::Line::Error(e))) = import.push(b) {
+                    log::warn!("{}:{}: {:?}", path, line, e);
+                }
+            }
+            import.push(b'\n');
+            import.finish().map_err(|e| invalid(&e))
+        }
+    }
+}
+
+pub fn save(path: &str, data: &ChipData) -> Result<(), Error> {
+    let bytes = match FileFormat::from_path(path) {
+        Some(FileFormat::Native) => encode_dump(data),
+        Some(FileFormat::Text) => text::export(data).into_bytes(),
+        Some(FileFormat::Interop(f)) => interop::export(data, f),
+        None => return Err(Error::File(format!("{}: unknown extension", path))),
+    };
+    std::fs::write(path, bytes).map_err(|e| Error::Io(path.into(), e))
+}
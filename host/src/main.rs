@@ -0,0 +1,479 @@
+//! Command-line companion for the RFIDReader firmware
+//!
+//! Talks to the device over the host protocol (`protocol::host`) on its USB
+//! serial port, and converts, compares and decodes dump files offline. The
+//! protocol, dump format, interop and profile code is the firmware's own,
+//! compiled for the host through `#[path]`.
+
+extern crate alloc;
+
+mod device;
+mod emulator;
+mod files;
+mod report;
+
+// Firmware modules. `crate::drivers` and `crate::util` only carry the parts
+// that build without the ESP32 HAL.
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/analysis/mod.rs"]
+mod analysis;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/util/cobs.rs"]
+mod cobs;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/util/crc.rs"]
+mod crc;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/util/hex.rs"]
+mod hex;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/interop/mod.rs"]
+mod interop;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/drivers/pn532.rs"]
+mod pn532;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/profile/mod.rs"]
+mod profile;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/protocol/mod.rs"]
+mod protocol;
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/drivers/storage/mod.rs"]
+mod storage;
+// Editor logic with no display behind it, built for its unit tests only
+#[cfg(test)]
+#[path = "../../src/ui/history.rs"]
+mod history;
+
+mod drivers {
+    pub(crate) use crate::pn532::{self, Pn532};
+    pub(crate) use crate::storage;
+}
+mod util {
+    pub(crate) use crate::{cobs, crc, hex};
+}
+
+use std::fmt;
+use std::process::ExitCode;
+
+use crate::analysis::{checksum, Analysis, Diff};
+use crate::device::Device;
+use crate::drivers::storage::{decode_dump, encode_dump, next_dump_name};
+use crate::interop::patch::{self, Patch};
+use crate::profile::ProfileSet;
+use crate::protocol::host::{self, Command, Info, Status};
+
+const USAGE: &str = "\
+Usage: rfid-host [-p PORT] [-v] COMMAND [ARGS]
+
+Device commands (PORT defaults to $RFID_PORT):
+  info                      firmware, storage and PN532 version
+  dump FILE                 read the tag on the antenna into FILE
+  write FILE                write FILE to the tag on the antenna
+  list                      dumps stored on the device
+  get NAME FILE             copy a stored dump (\"\" for the editor's) to FILE
+  put FILE [NAME]           store FILE on the device (\"\" opens it in the editor)
+  delete NAME               delete a stored dump
+  pn532 HEX                 send a raw PN532 command, print the response
+
+File commands:
+  convert IN OUT            convert between dump formats
+  diff [--json] A B         changed blocks, bytes and bits from A to B
+  analyze [--profile] FILES..
+                            classify every byte over the dumps; --profile
+                            prints a profile seeded from the result
+  checksum FILES..          sums, XORs and CRCs that match a stored field in
+                            every dump
+  patch A B                 the edits that turn A into B, as a patch
+  apply [--force] [--profile FILE].. PATCH IN OUT
+                            apply a patch to IN and save the result as OUT;
+                            stops at conflicts unless --force; --profile adds
+                            the profiles in FILE for the checksum fields
+  decode FILE [PROFILES..]  profile fields; extra .ini files add profiles
+
+  emulate [FILE]            serve the protocol on a pseudo-terminal, with FILE
+                            as the tag on the antenna
+
+Formats by extension: .dmp (device), .nfc (Flipper), .bin/.eml/.json
+(Proxmark3), .txt (Dump Serial text).
+
+Options:
+  -p, --port PORT   serial port of the device
+  -v, --verbose     copy the device's log output to stderr
+";
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Io(String, std::io::Error),
+    Sys(nix::Error),
+    /// Error response from the device, with its message.
+    Device(Option<Status>, String),
+    Protocol(String),
+    Timeout,
+    File(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            Error::Io(path, e) => write!(f, "{}: {}", path, e),
+            Error::Sys(e) => write!(f, "{}", e),
+            Error::Device(status, msg) => {
+                match status {
+                    Some(status) => write!(f, "device: {:?}", status)?,
+                    None => f.write_str("device: unknown status")?,
+                }
+                if !msg.is_empty() {
+                    write!(f, " ({})", msg)?;
+                }
+                Ok(())
+            }
+            Error::Protocol(msg) => write!(f, "protocol: {}", msg),
+            Error::Timeout => write!(f, "no response from the device"),
+            Error::File(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(e: nix::Error) -> Self {
+        Error::Sys(e)
+    }
+}
+
+/// Prints the firmware modules' `log` output (import warnings and the like).
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} - {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+struct Args {
+    port: Option<String>,
+    verbose: bool,
+    command: String,
+    rest: Vec<String>,
+}
+
+impl Args {
+    fn parse(mut argv: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut port = std::env::var("RFID_PORT").ok();
+        let mut verbose = false;
+        loop {
+            let arg = argv
+                .next()
+                .ok_or_else(|| Error::Usage("missing command".into()))?;
+            match arg.as_str() {
+                "-p" | "--port" => {
+                    port = Some(
+                        argv.next()
+                            .ok_or_else(|| Error::Usage("missing PORT".into()))?,
+                    )
+                }
+                "-v" | "--verbose" => verbose = true,
+                _ => {
+                    return Ok(Self {
+                        port,
+                        verbose,
+                        command: arg,
+                        rest: argv.collect(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Positional arguments: exactly `min` of them, up to `max`.
+    fn positional(&self, min: usize, max: usize) -> Result<&[String], Error> {
+        if self.rest.len() < min || self.rest.len() > max {
+            return Err(Error::Usage(format!(
+                "wrong number of arguments for '{}'",
+                self.command
+            )));
+        }
+        Ok(&self.rest)
+    }
+
+    fn device(&self) -> Result<Device, Error> {
+        let port = self
+            .port
+            .as_deref()
+            .ok_or_else(|| Error::Usage("no port given (-p or $RFID_PORT)".into()))?;
+        let mut device = Device::open(port)?;
+        device.echo_log = self.verbose;
+        device.set_time()?;
+        Ok(device)
+    }
+}
+
+fn main() -> ExitCode {
+    let _ = log::set_logger(&LOGGER).map(|_| log::set_max_level(log::LevelFilter::Warn));
+    let result = Args::parse(std::env::args().skip(1)).and_then(|args| run(&args));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), Error> {
+    match args.command.as_str() {
+        "info" => {
+            args.positional(0, 0)?;
+            let payload = args.device()?.request(Command::GetInfo, &[])?;
+            let info = Info::decode(&payload)
+                .ok_or_else(|| Error::Protocol("malformed info response".into()))?;
+            println!("Firmware: {}", info.firmware);
+            println!("Protocol: {}", info.protocol);
+            let storage = ["none", "SD card", "internal flash"];
+            println!(
+                "Storage:  {}",
+                storage.get(info.storage as usize).unwrap_or(&"?")
+            );
+            println!(
+                "Dump loaded: {}",
+                if info.dump_loaded { "yes" } else { "no" }
+            );
+            match info.pn532 {
+                [0, 0, 0] => println!("PN532:    not answering"),
+                [ic, ver, rev] => println!("PN532:    IC {:02X}, firmware {}.{}", ic, ver, rev),
+            }
+        }
+        "dump" => {
+            let [file] = args.positional(1, 1)? else {
+                unreachable!()
+            };
+            let data = decode_dump(&args.device()?.request(Command::ReadChip, &[])?)
+                .map_err(|e| Error::Protocol(format!("bad dump from device: {:?}", e)))?;
+            files::save(file, &data)?;
+            println!(
+                "{}: {} blocks, UID {}",
+                file,
+                data.block_count,
+                report::uid(&data.uid)
+            );
+        }
+        "write" => {
+            let [file] = args.positional(1, 1)? else {
+                unreachable!()
+            };
+            let data = files::load(file)?;
+            args.device()?
+                .request(Command::WriteChip, &encode_dump(&data))?;
+            println!("Wrote {} to the tag", file);
+        }
+        "list" => {
+            args.positional(0, 0)?;
+            let payload = args.device()?.request(Command::ListDumps, &[])?;
+            let entries = host::decode_list(&payload)
+                .ok_or_else(|| Error::Protocol("malformed list response".into()))?;
+            for e in entries {
+                println!(
+                    "{:<28} {} {:<12} {:>3} blocks  {}",
+                    e.name,
+                    report::uid(&e.uid),
+                    e.chip_type.name(),
+                    e.block_count,
+                    report::date(e.timestamp)
+                );
+            }
+        }
+        "get" => {
+            let [name, file] = args.positional(2, 2)? else {
+                unreachable!()
+            };
+            let payload = args.device()?.request(Command::GetDump, name.as_bytes())?;
+            let data = decode_dump(&payload)
+                .map_err(|e| Error::Protocol(format!("bad dump from device: {:?}", e)))?;
+            files::save(file, &data)?;
+        }
+        "put" => {
+            let rest = args.positional(1, 2)?;
+            let data = files::load(&rest[0])?;
+            let mut device = args.device()?;
+            let name = match rest.get(1) {
+                Some(name) => name.clone(),
+                None => {
+                    let payload = device.request(Command::ListDumps, &[])?;
+                    let names: Vec<String> = host::decode_list(&payload)
+                        .ok_or_else(|| Error::Protocol("malformed list response".into()))?
+                        .into_iter()
+                        .map(|e| e.name)
+                        .collect();
+                    next_dump_name(&data.uid, &names)
+                        .map_err(|e| Error::File(format!("no free dump name: {:?}", e)))?
+                        .as_str()
+                        .into()
+                }
+            };
+            device.request(
+                Command::PutDump,
+                &host::encode_put(&name, &encode_dump(&data)),
+            )?;
+            if !name.is_empty() {
+                println!("Stored as {}", name);
+            }
+        }
+        "delete" => {
+            let [name] = args.positional(1, 1)? else {
+                unreachable!()
+            };
+            args.device()?
+                .request(Command::DeleteDump, name.as_bytes())?;
+        }
+        "pn532" => {
+            let [command] = args.positional(1, 1)? else {
+                unreachable!()
+            };
+            let mut bytes = [0u8; 56];
+            let len = hex::parse_bytes(command, &mut bytes)
+                .filter(|&n| n > 0)
+                .ok_or_else(|| Error::Usage(format!("not hex bytes: {}", command)))?;
+            let response = args.device()?.request(Command::Pn532, &bytes[..len])?;
+            println!("{}", report::hex(&response, " "));
+        }
+        "convert" => {
+            let [input, output] = args.positional(2, 2)? else {
+                unreachable!()
+            };
+            files::save(output, &files::load(input)?)?;
+        }
+        "diff" => {
+            let (json, a, b) = match args.positional(2, 3)? {
+                [a, b] => (false, a, b),
+                [flag, a, b] if flag == "--json" => (true, a, b),
+                _ => return Err(Error::Usage("usage: diff [--json] A B".into())),
+            };
+            let diff = Diff::new(&files::load(a)?, &files::load(b)?);
+            print!("{}", if json { diff.json() } else { diff.text() });
+            // Exit status like diff(1)
+            if !diff.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        "analyze" => {
+            let rest = args.positional(1, usize::MAX)?;
+            let (profile, paths) = match rest.split_first() {
+                Some((flag, paths)) if flag == "--profile" && !paths.is_empty() => (true, paths),
+                _ => (false, rest),
+            };
+            let mut dumps = paths
+                .iter()
+                .map(|p| files::load(p))
+                .collect::<Result<Vec<_>, _>>()?;
+            // Each tag's dumps together, in the order given
+            dumps.sort_by_key(|d| d.uid_msb_first());
+            let mut analysis = Analysis::new();
+            for data in &dumps {
+                analysis.add(data);
+            }
+            if profile {
+                print!("{}", analysis.profile("Seed"));
+            } else {
+                print!("{}", analysis.map());
+            }
+        }
+        "checksum" => {
+            let paths = args.positional(2, usize::MAX)?;
+            let dumps = paths
+                .iter()
+                .map(|p| files::load(p))
+                .collect::<Result<Vec<_>, _>>()?;
+            let matches = checksum::search(&dumps, true);
+            print!("{}", checksum::report(dumps.len(), &matches));
+        }
+        "patch" => {
+            let [a, b] = args.positional(2, 2)? else {
+                unreachable!()
+            };
+            let new = files::load(b)?;
+            let profiles = ProfileSet::builtin();
+            print!(
+                "{}",
+                Patch::between(&files::load(a)?, &new, profiles.find_match(&new)).text()
+            );
+        }
+        "apply" => {
+            let usage =
+                || Error::Usage("usage: apply [--force] [--profile FILE].. PATCH IN OUT".into());
+            let (mut force, mut extra, mut paths) = (false, Vec::new(), Vec::new());
+            let mut rest = args.positional(3, usize::MAX)?.iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--force" => force = true,
+                    "--profile" => extra.push(rest.next().ok_or_else(usage)?.clone()),
+                    _ => paths.push(arg),
+                }
+            }
+            let [path, input, output] = paths[..] else {
+                return Err(usage());
+            };
+            let text = std::fs::read_to_string(path).map_err(|e| Error::Io(path.clone(), e))?;
+            let patch = Patch::parse(&text).map_err(|patch::ParseError::Line(line)| {
+                Error::File(format!("{}: error at line {}", path, line))
+            })?;
+            let mut data = files::load(input)?;
+            let conflicts = patch.conflicts(&data);
+            for conflict in &conflicts {
+                eprintln!("conflict {}", conflict.text());
+            }
+            if !force && !conflicts.is_empty() {
+                return Err(Error::File(format!(
+                    "{}: {} conflicts, nothing written",
+                    input,
+                    conflicts.len()
+                )));
+            }
+            let changed = patch.apply(&mut data, true).unwrap_or(0);
+            if let Some(profile) = load_profiles(&extra)?.find_match(&data) {
+                profile.update_checksums(&mut data);
+            }
+            files::save(output, &data)?;
+            println!("{}: {} blocks changed", output, changed);
+        }
+        "decode" => {
+            let rest = args.positional(1, usize::MAX)?;
+            let data = files::load(&rest[0])?;
+            let profiles = load_profiles(&rest[1..])?;
+            print!("{}", report::decode(&data, profiles.find_match(&data)));
+        }
+        "emulate" => {
+            let rest = args.positional(0, 1)?;
+            let tag = rest.first().map(|f| files::load(f)).transpose()?;
+            emulator::run(tag)?;
+        }
+        "help" | "-h" | "--help" => print!("{}", USAGE),
+        other => return Err(Error::Usage(format!("unknown command '{}'", other))),
+    }
+    Ok(())
+}
+
+/// The built-in profiles plus those in the .ini files at `paths`, which
+/// replace built-in ones of the same name.
+fn load_profiles(paths: &[String]) -> Result<ProfileSet, Error> {
+    let mut profiles = ProfileSet::builtin();
+    for path in paths {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::Io(path.clone(), e))?;
+        profiles
+            .load_str(&text)
+            .map_err(|e| Error::File(format!("{}: {:?}", path, e)))?;
+    }
+    Ok(profiles)
+}
@@ -0,0 +1,89 @@
+//! Text output for `decode` and listings
+
+use std::fmt::Write;
+
+use crate::profile::date::civil_from_days;
+use crate::profile::Profile;
+use crate::protocol::st25tb::{ChipData, FLAG_LOCKED};
+
+pub fn hex(bytes: &[u8], sep: &str) -> String {
+    let mut out = String::new();
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push_str(sep);
+        }
+        let _ = write!(out, "{:02X}", b);
+    }
+    out
+}
+
+/// MSB first, as printed on tags and by other tools.
+pub fn uid(uid: &[u8; 8]) -> String {
+    let mut msb = *uid;
+    msb.reverse();
+    hex(&msb, "")
+}
+
+/// `YYYY-MM-DD HH:MM` UTC, `-` when the device had no clock.
+pub fn date(timestamp: u32) -> String {
+    if timestamp == 0 {
+        return "-".into();
+    }
+    let (y, m, d) = civil_from_days((timestamp / 86400) as i32);
+    let secs = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        y,
+        m,
+        d,
+        secs / 3600,
+        secs / 60 % 60
+    )
+}
+
+pub fn decode(data: &ChipData, profile: Option<&Profile>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "UID:     {}", uid(&data.uid));
+    let _ = writeln!(
+        out,
+        "Type:    {}, {} blocks",
+        data.chip_type().name(),
+        data.block_count
+    );
+    let _ = writeln!(out, "Read:    {}", date(data.timestamp));
+    let locked = (0..data.block_count)
+        .filter(|&i| data.flags[i] & FLAG_LOCKED != 0)
+        .count();
+    if locked > 0 {
+        let _ = writeln!(out, "Locked:  {} blocks", locked);
+    }
+    let Some(profile) = profile else {
+        out.push_str("Profile: none matched\n");
+        return out;
+    };
+    let _ = writeln!(out, "Profile: {}", profile.name);
+    let width = profile
+        .fields
+        .iter()
+        .map(|f| f.name.len())
+        .max()
+        .unwrap_or(0);
+    for field in &profile.fields {
+        let (first, last) = field.blocks();
+        if last >= data.block_count {
+            let _ = writeln!(out, "  {:width$}  (past the end of the dump)", field.name);
+            continue;
+        }
+        let mut value = String::new();
+        let stored = field.read(data);
+        let _ = field.format_value(stored, &mut value);
+        let _ = write!(out, "  {:width$}  {:<20} B{:03}", field.name, value, first);
+        match field.checksum(data) {
+            Some(expected) if expected != stored => {
+                let _ = writeln!(out, "  BAD, expected {:0w$X}", expected, w = field.size * 2);
+            }
+            _ => out.push('\n'),
+        }
+    }
+    out
+}
@@ -0,0 +1,721 @@
+//! Search for checksum fields: stored values that a common algorithm
+//! reproduces from another part of the dump, in every dump
+//!
+//! Candidate fields are the bytes that differ between the dumps (a checksum
+//! over data that never changes cannot be told from a constant), 1, 2 or 4
+//! bytes wide, in either byte order. Candidate ranges are up to `MAX_RANGE`
+//! bytes anywhere else in the dump and must include a differing byte. An
+//! 8-bit match is only reported when it held for at least 3 different stored
+//! values, a wider one for 2, which keeps chance hits out. 8-bit hits over
+//! long ranges can still be luck with only a few dumps; more dumps settle it.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::protocol::st25tb::ChipData;
+
+/// Longest range tried, in bytes (16 blocks).
+pub const MAX_RANGE: usize = 64;
+
+/// A CRC in the usual Rocksoft/reveng parameters, with `refin == refout`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crc {
+    /// Catalogue name, `None` for a swept variant.
+    pub name: Option<&'static str>,
+    pub width: u8,
+    pub poly: u32,
+    pub init: u32,
+    pub reflected: bool,
+    pub xorout: u32,
+}
+
+impl Crc {
+    const fn named(
+        name: &'static str,
+        width: u8,
+        poly: u32,
+        init: u32,
+        reflected: bool,
+        xorout: u32,
+    ) -> Self {
+        Self {
+            name: Some(name),
+            width,
+            poly,
+            init,
+            reflected,
+            xorout,
+        }
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX >> (32 - self.width)
+    }
+
+    fn step(&self, crc: u32, byte: u8) -> u32 {
+        let byte = if self.reflected {
+            byte.reverse_bits()
+        } else {
+            byte
+        };
+        let top = 1 << (self.width - 1);
+        let mut crc = crc ^ ((byte as u32) << (self.width - 8));
+        for _ in 0..8 {
+            crc = if crc & top != 0 {
+                (crc << 1) ^ self.poly
+            } else {
+                crc << 1
+            };
+        }
+        crc & self.mask()
+    }
+
+    fn finish(&self, crc: u32) -> u32 {
+        let crc = if self.reflected {
+            crc.reverse_bits() >> (32 - self.width)
+        } else {
+            crc
+        };
+        crc ^ self.xorout
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// Sum of the bytes, low 8 bits.
+    Sum8,
+    /// Two's complement of `Sum8`, so that data plus checksum sums to zero.
+    Sum8Negated,
+    /// Sum of the bytes, low 16 bits.
+    Sum16,
+    Xor8,
+    Crc(Crc),
+}
+
+impl Algorithm {
+    /// Stored size in bytes.
+    pub fn width(&self) -> usize {
+        match self {
+            Algorithm::Sum8 | Algorithm::Sum8Negated | Algorithm::Xor8 => 1,
+            Algorithm::Sum16 => 2,
+            Algorithm::Crc(crc) => crc.width as usize / 8,
+        }
+    }
+
+    fn start(&self) -> u32 {
+        match self {
+            Algorithm::Crc(crc) => crc.init,
+            _ => 0,
+        }
+    }
+
+    fn step(&self, state: u32, byte: u8) -> u32 {
+        match self {
+            Algorithm::Sum8 | Algorithm::Sum8Negated | Algorithm::Sum16 => {
+                state.wrapping_add(byte as u32)
+            }
+            Algorithm::Xor8 => state ^ byte as u32,
+            Algorithm::Crc(crc) => crc.step(state, byte),
+        }
+    }
+
+    fn finish(&self, state: u32) -> u32 {
+        match self {
+            Algorithm::Sum8 | Algorithm::Xor8 => state & 0xFF,
+            Algorithm::Sum8Negated => state.wrapping_neg() & 0xFF,
+            Algorithm::Sum16 => state & 0xFFFF,
+            Algorithm::Crc(crc) => crc.finish(state),
+        }
+    }
+
+    pub fn compute(&self, data: &[u8]) -> u32 {
+        self.finish(data.iter().fold(self.start(), |s, &b| self.step(s, b)))
+    }
+
+    /// Parses a name as `Display` prints it, ignoring case: a catalogue name
+    /// or a CRC given by its parameters, `CRC-8 poly 4D init 00 reflected
+    /// xorout 43`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        for preset in PRESETS {
+            let mut text = String::new();
+            let _ = write!(text, "{}", preset);
+            if text.eq_ignore_ascii_case(name) {
+                return Some(preset);
+            }
+        }
+        let mut words = name.split_whitespace();
+        let first = words.next()?;
+        if !first.get(..4)?.eq_ignore_ascii_case("CRC-") {
+            return None;
+        }
+        let width: u8 = first[4..].parse().ok()?;
+        if !matches!(width, 8 | 16 | 32) {
+            return None;
+        }
+        let mut crc = Crc {
+            name: None,
+            width,
+            poly: 0,
+            init: 0,
+            reflected: false,
+            xorout: 0,
+        };
+        while let Some(key) = words.next() {
+            if key.eq_ignore_ascii_case("reflected") {
+                crc.reflected = true;
+                continue;
+            }
+            let value = u32::from_str_radix(words.next()?, 16)
+                .ok()
+                .filter(|&v| v <= crc.mask())?;
+            if key.eq_ignore_ascii_case("poly") {
+                crc.poly = value;
+            } else if key.eq_ignore_ascii_case("init") {
+                crc.init = value;
+            } else if key.eq_ignore_ascii_case("xorout") {
+                crc.xorout = value;
+            } else {
+                return None;
+            }
+        }
+        if crc.poly & 1 == 0 {
+            return None;
+        }
+        Some(named(Algorithm::Crc(crc), crc.xorout))
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Sum8 => f.write_str("sum8"),
+            Algorithm::Sum8Negated => f.write_str("sum8, negated"),
+            Algorithm::Sum16 => f.write_str("sum16"),
+            Algorithm::Xor8 => f.write_str("xor8"),
+            Algorithm::Crc(Crc {
+                name: Some(name), ..
+            }) => f.write_str(name),
+            Algorithm::Crc(crc) => write!(
+                f,
+                "CRC-{} poly {:02X} init {:02X}{} xorout {:02X}",
+                crc.width,
+                crc.poly,
+                crc.init,
+                if crc.reflected { " reflected" } else { "" },
+                crc.xorout
+            ),
+        }
+    }
+}
+
+/// Common checksums, CRCs named as in the reveng catalogue.
+pub const PRESETS: [Algorithm; 27] = [
+    Algorithm::Sum8,
+    Algorithm::Sum8Negated,
+    Algorithm::Sum16,
+    Algorithm::Xor8,
+    Algorithm::Crc(Crc::named("CRC-8/SMBUS", 8, 0x07, 0x00, false, 0x00)),
+    Algorithm::Crc(Crc::named("CRC-8/I-432-1", 8, 0x07, 0x00, false, 0x55)),
+    Algorithm::Crc(Crc::named("CRC-8/ROHC", 8, 0x07, 0xFF, true, 0x00)),
+    Algorithm::Crc(Crc::named("CRC-8/MAXIM-DOW", 8, 0x31, 0x00, true, 0x00)),
+    Algorithm::Crc(Crc::named("CRC-8/NRSC-5", 8, 0x31, 0xFF, false, 0x00)),
+    Algorithm::Crc(Crc::named("CRC-8/SAE-J1850", 8, 0x1D, 0xFF, false, 0xFF)),
+    Algorithm::Crc(Crc::named("CRC-8/MIFARE-MAD", 8, 0x1D, 0xC7, false, 0x00)),
+    Algorithm::Crc(Crc::named("CRC-8/AUTOSAR", 8, 0x2F, 0xFF, false, 0xFF)),
+    Algorithm::Crc(Crc::named("CRC-8/CDMA2000", 8, 0x9B, 0xFF, false, 0x00)),
+    Algorithm::Crc(Crc::named("CRC-16/ARC", 16, 0x8005, 0x0000, true, 0x0000)),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/MODBUS",
+        16,
+        0x8005,
+        0xFFFF,
+        true,
+        0x0000,
+    )),
+    Algorithm::Crc(Crc::named("CRC-16/USB", 16, 0x8005, 0xFFFF, true, 0xFFFF)),
+    Algorithm::Crc(Crc::named("CRC-16/UMTS", 16, 0x8005, 0x0000, false, 0x0000)),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/IBM-3740",
+        16,
+        0x1021,
+        0xFFFF,
+        false,
+        0x0000,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/XMODEM",
+        16,
+        0x1021,
+        0x0000,
+        false,
+        0x0000,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/GENIBUS",
+        16,
+        0x1021,
+        0xFFFF,
+        false,
+        0xFFFF,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/KERMIT",
+        16,
+        0x1021,
+        0x0000,
+        true,
+        0x0000,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/MCRF4XX",
+        16,
+        0x1021,
+        0xFFFF,
+        true,
+        0x0000,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/IBM-SDLC",
+        16,
+        0x1021,
+        0xFFFF,
+        true,
+        0xFFFF,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-16/ISO-IEC-14443-3-A",
+        16,
+        0x1021,
+        0xC6C6,
+        true,
+        0x0000,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-32/ISO-HDLC",
+        32,
+        0x04C1_1DB7,
+        0xFFFF_FFFF,
+        true,
+        0xFFFF_FFFF,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-32/BZIP2",
+        32,
+        0x04C1_1DB7,
+        0xFFFF_FFFF,
+        false,
+        0xFFFF_FFFF,
+    )),
+    Algorithm::Crc(Crc::named(
+        "CRC-32/ISCSI",
+        32,
+        0x1EDC_6F41,
+        0xFFFF_FFFF,
+        true,
+        0xFFFF_FFFF,
+    )),
+];
+
+/// Every CRC-8: all polynomials, init `00` or `FF`, either reflection. The
+/// final XOR is solved rather than tried, see `search`.
+fn crc8_sweep() -> impl Iterator<Item = Algorithm> {
+    (1..=0xFFu32).step_by(2).flat_map(|poly| {
+        [(0x00, false), (0x00, true), (0xFF, false), (0xFF, true)]
+            .into_iter()
+            .map(move |(init, reflected)| {
+                Algorithm::Crc(Crc {
+                    name: None,
+                    width: 8,
+                    poly,
+                    init,
+                    reflected,
+                    xorout: 0,
+                })
+            })
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub algorithm: Algorithm,
+    /// Covered bytes, as offsets from the start of block 0.
+    pub start: usize,
+    pub len: usize,
+    /// Offset of the stored value.
+    pub field: usize,
+    pub big_endian: bool,
+    /// Different stored values it held for.
+    pub values: usize,
+}
+
+impl Match {
+    fn covers(&self, offset: usize) -> bool {
+        (self.start..self.start + self.len).contains(&offset)
+    }
+
+    fn contains(&self, other: &Match) -> bool {
+        self.start <= other.start && other.start + other.len <= self.start + self.len
+    }
+}
+
+/// Runs the catalogue (and with `sweep`, every CRC-8) over the dumps. Only
+/// blocks all of them have read are used.
+///
+/// Swept CRC-8s take their final XOR from the first dump, which leaves one
+/// dump less to confirm them, so they are only tried on ranges right before or
+/// after the field and need 4 different stored values.
+pub fn search(dumps: &[ChipData], sweep: bool) -> Vec<Match> {
+    let count = dumps.iter().map(|d| d.block_count).min().unwrap_or(0);
+    let total = count * 4;
+    let readable: Vec<bool> = (0..total)
+        .map(|p| dumps.iter().all(|d| d.block(p / 4).is_some()))
+        .collect();
+    let bytes: Vec<Vec<u8>> = dumps
+        .iter()
+        .map(|d| {
+            (0..total)
+                .map(|p| d.block(p / 4).map_or(0, |b| b[p % 4]))
+                .collect()
+        })
+        .collect();
+    let varying: Vec<bool> = (0..total)
+        .map(|p| readable[p] && bytes.iter().any(|b| b[p] != bytes[0][p]))
+        .collect();
+
+    let mut matches: Vec<Match> = Vec::new();
+    let swept = sweep.then(crc8_sweep).into_iter().flatten();
+    for algorithm in PRESETS.iter().copied().chain(swept) {
+        let solve_xorout = matches!(algorithm, Algorithm::Crc(Crc { name: None, .. }));
+        let width = algorithm.width();
+        let needed = match (width, solve_xorout) {
+            (1, true) => 4,
+            (1, false) => 3,
+            _ => 2,
+        };
+        // (offset, big endian, different values) of the fields worth trying
+        let orders: &[bool] = if width > 1 { &[true, false] } else { &[true] };
+        let candidates: Vec<(usize, bool, usize)> = (0..total.saturating_sub(width - 1))
+            .filter(|&p| (p..p + width).all(|i| varying[i]))
+            .flat_map(|p| orders.iter().map(move |&big| (p, big)))
+            .map(|(p, big)| {
+                let mut values: Vec<u32> = bytes.iter().map(|b| stored(b, p, width, big)).collect();
+                values.sort_unstable();
+                values.dedup();
+                (p, big, values.len())
+            })
+            .filter(|&(_, _, distinct)| distinct >= needed)
+            .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+        for start in 0..total {
+            let mut state = alloc::vec![algorithm.start(); dumps.len()];
+            let mut has_varying = false;
+            for end in start..total.min(start + MAX_RANGE) {
+                if !readable[end] {
+                    break;
+                }
+                has_varying |= varying[end];
+                for (s, b) in state.iter_mut().zip(&bytes) {
+                    *s = algorithm.step(*s, b[end]);
+                }
+                if !has_varying {
+                    continue;
+                }
+                for &(p, big, distinct) in &candidates {
+                    if p + width > start && p <= end {
+                        continue;
+                    }
+                    if solve_xorout && end + 1 != p && p + width != start {
+                        continue;
+                    }
+                    let xorout = if solve_xorout {
+                        algorithm.finish(state[0]) ^ stored(&bytes[0], p, width, big)
+                    } else {
+                        0
+                    };
+                    let holds = state
+                        .iter()
+                        .zip(&bytes)
+                        .all(|(&s, b)| algorithm.finish(s) ^ xorout == stored(b, p, width, big));
+                    if !holds {
+                        continue;
+                    }
+                    let found = Match {
+                        algorithm: named(algorithm, xorout),
+                        start,
+                        len: end + 1 - start,
+                        field: p,
+                        big_endian: big,
+                        values: distinct,
+                    };
+                    if !matches.contains(&found) {
+                        matches.push(found);
+                    }
+                }
+            }
+        }
+    }
+
+    // Ranges padded with bytes that do not change the result say nothing new.
+    // For a solved CRC, constant padding and the init value only move the
+    // final XOR, so any init counts as the same algorithm there. With a
+    // negated sum or an XOR the whole range plus the field comes to zero, so
+    // each byte of it "checks" the others; only the first reading is kept.
+    let all = matches.clone();
+    let mut n = 0;
+    matches.retain(|m| {
+        let i = n;
+        n += 1;
+        !all.iter().enumerate().any(|(j, o)| {
+            let padded = o.field == m.field
+                && o.big_endian == m.big_endian
+                && m.contains(o)
+                && (o.len < m.len || j < i);
+            let mirrored = j < i
+                && matches!(o.algorithm, Algorithm::Sum8Negated | Algorithm::Xor8)
+                && o.covers(m.field)
+                && m.covers(o.field);
+            j != i && same_family(&o.algorithm, &m.algorithm) && (padded || mirrored)
+        })
+    });
+    matches
+}
+
+/// The `width`-byte value at `offset`.
+fn stored(bytes: &[u8], offset: usize, width: usize, big_endian: bool) -> u32 {
+    let value = bytes[offset..offset + width].iter().map(|&b| b as u32);
+    if big_endian {
+        value.fold(0, |v, b| v << 8 | b)
+    } else {
+        value.rev().fold(0, |v, b| v << 8 | b)
+    }
+}
+
+fn same_family(a: &Algorithm, b: &Algorithm) -> bool {
+    match (a, b) {
+        (Algorithm::Crc(a), Algorithm::Crc(b)) if a.name.is_none() && b.name.is_none() => {
+            a.width == b.width && a.poly == b.poly && a.reflected == b.reflected
+        }
+        _ => a == b,
+    }
+}
+
+/// A swept CRC with its solved final XOR, under its catalogue name if it has
+/// one.
+fn named(algorithm: Algorithm, xorout: u32) -> Algorithm {
+    let Algorithm::Crc(crc) = algorithm else {
+        return algorithm;
+    };
+    if crc.name.is_some() {
+        return algorithm;
+    }
+    let crc = Crc { xorout, ..crc };
+    PRESETS
+        .iter()
+        .copied()
+        .find(|p| matches!(p, Algorithm::Crc(c) if Crc { name: None, ..*c } == crc))
+        .unwrap_or(Algorithm::Crc(crc))
+}
+
+fn position(out: &mut String, offset: usize) {
+    let _ = write!(out, "B{:03}:{}", offset / 4, offset % 4);
+}
+
+/// One line per match:
+///
+/// ```text
+/// CRC-16/ARC  B016:0-B019:1 (14 bytes) -> B019:2 big endian, 4 values
+/// ```
+pub fn report(dumps: usize, matches: &[Match]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# {} dumps; 8-bit results need 3 different stored values (4 when swept), wider ones 2",
+        dumps
+    );
+    if matches.is_empty() {
+        out.push_str("# no checksum found\n");
+    }
+    for m in matches {
+        let _ = write!(out, "{}  ", m.algorithm);
+        position(&mut out, m.start);
+        out.push('-');
+        position(&mut out, m.start + m.len - 1);
+        let _ = write!(out, " ({} bytes) -> ", m.len);
+        position(&mut out, m.field);
+        if m.algorithm.width() > 1 {
+            out.push_str(if m.big_endian {
+                " big endian"
+            } else {
+                " little endian"
+            });
+        }
+        let _ = writeln!(out, ", {} values", m.values);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    /// Four dumps: block 8 summed into B009:0, block 10 under CRC-16/KERMIT
+    /// in B011:0-1 (little endian) and block 12 under an uncatalogued CRC-8
+    /// in B013:0.
+    fn dumps() -> Vec<ChipData> {
+        let kermit = Algorithm::from_name("CRC-16/KERMIT").unwrap();
+        let swept = Algorithm::Crc(Crc {
+            name: None,
+            width: 8,
+            poly: 0x4D,
+            init: 0x00,
+            reflected: true,
+            xorout: 0x43,
+        });
+        (0..4u8)
+            .map(|i| {
+                let mut data = ChipData {
+                    uid: [0x78, 0x56, 0x34, 0x12, 0x00, 0x30, 0x02, 0xD0],
+                    block_count: 16,
+                    ..ChipData::default()
+                };
+                data.blocks[8] = [i * 37 + 5, i * 11 + 1, 0x40 + i, 0x99 - i * 3];
+                data.blocks[9][0] = Algorithm::Sum8.compute(&data.blocks[8]) as u8;
+                data.blocks[10] = [0x10 * i, 0x20, 0x30 + i, 0x40];
+                let crc = kermit.compute(&data.blocks[10]) as u16;
+                data.blocks[11][..2].copy_from_slice(&crc.to_le_bytes());
+                data.blocks[12] = [0xC0 ^ i, 0x7E, i * i, 0x01];
+                data.blocks[13][0] = swept.compute(&data.blocks[12]) as u8;
+                data
+            })
+            .collect()
+    }
+
+    fn find(matches: &[Match], name: &str, start: usize, field: usize) -> Option<Match> {
+        matches
+            .iter()
+            .find(|m| m.algorithm.to_string() == name && m.start == start && m.field == field)
+            .copied()
+    }
+
+    #[test]
+    fn presets_give_catalogue_check_values() {
+        let checks: [u32; 27] = [
+            0xDD,
+            0x23,
+            0x01DD,
+            0x31,
+            0xF4,
+            0xA1,
+            0xD0,
+            0xA1,
+            0xF7,
+            0x4B,
+            0x99,
+            0xDF,
+            0xDA,
+            0xBB3D,
+            0x4B37,
+            0xB4C8,
+            0xFEE8,
+            0x29B1,
+            0x31C3,
+            0xD64E,
+            0x2189,
+            0x6F91,
+            0x906E,
+            0xBF05,
+            0xCBF4_3926,
+            0xFC89_1918,
+            0xE306_9283,
+        ];
+        for (preset, check) in PRESETS.iter().zip(checks) {
+            assert_eq!(preset.compute(b"123456789"), check, "{}", preset);
+        }
+    }
+
+    #[test]
+    fn finds_sums_and_catalogue_crcs() {
+        let matches = search(&dumps(), false);
+        let sum = find(&matches, "sum8", 32, 36).expect("sum8");
+        assert_eq!((sum.len, sum.values), (4, 4));
+        let crc = find(&matches, "CRC-16/KERMIT", 40, 44).expect("CRC-16/KERMIT");
+        assert_eq!(crc.len, 4);
+        assert!(!crc.big_endian);
+        assert!(find(
+            &matches,
+            "CRC-8 poly 4D init 00 reflected xorout 43",
+            48,
+            52
+        )
+        .is_none());
+
+        // Two dumps are not enough for an 8-bit result
+        let matches = search(&dumps()[..2], false);
+        assert!(find(&matches, "sum8", 32, 36).is_none());
+        assert!(find(&matches, "CRC-16/KERMIT", 40, 44).is_some());
+    }
+
+    #[test]
+    fn sweep_solves_crc8_final_xor() {
+        let matches = search(&dumps(), true);
+        let crc = find(
+            &matches,
+            "CRC-8 poly 4D init 00 reflected xorout 43",
+            48,
+            52,
+        );
+        assert_eq!(crc.map(|m| m.len), Some(4));
+        let report = report(4, &matches);
+        assert!(report.starts_with("# 4 dumps;"));
+        assert!(report.contains("\nsum8  B008:0-B008:3 (4 bytes) -> B009:0, 4 values\n"));
+        assert!(report.contains(
+            "\nCRC-16/KERMIT  B010:0-B010:3 (4 bytes) -> B011:0 little endian, 4 values\n"
+        ));
+    }
+
+    #[test]
+    fn preset_names_round_trip() {
+        for preset in PRESETS {
+            assert_eq!(Algorithm::from_name(&preset.to_string()), Some(preset));
+        }
+        assert_eq!(
+            Algorithm::from_name("SUM8, NEGATED"),
+            Some(Algorithm::Sum8Negated)
+        );
+    }
+
+    #[test]
+    fn swept_crc_names_round_trip() {
+        let crc = Algorithm::Crc(Crc {
+            name: None,
+            width: 8,
+            poly: 0x4D,
+            init: 0x00,
+            reflected: true,
+            xorout: 0x43,
+        });
+        let name = crc.to_string();
+        assert_eq!(name, "CRC-8 poly 4D init 00 reflected xorout 43");
+        assert_eq!(Algorithm::from_name(&name), Some(crc));
+        // Parameters of a catalogue CRC give it its name back
+        let kermit = Algorithm::from_name("CRC-16 poly 1021 init 00 reflected xorout 00");
+        assert_eq!(
+            kermit.map(|a| a.to_string()).as_deref(),
+            Some("CRC-16/KERMIT")
+        );
+        assert_eq!(
+            Algorithm::from_name("CRC-8 poly 4C init 00 xorout 00"),
+            None
+        );
+        assert_eq!(
+            Algorithm::from_name("CRC-12 poly 80F init 0 xorout 0"),
+            None
+        );
+        assert_eq!(Algorithm::from_name("md5"), None);
+    }
+}
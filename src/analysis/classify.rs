@@ -0,0 +1,609 @@
+//! Byte classification over a set of dumps
+//!
+//! Dumps are added one at a time, so a whole library can be analysed without
+//! holding it in memory. Each byte ends up as:
+//! - constant: the same in every dump
+//! - unique: the same across one tag's dumps, different between tags (serials,
+//!   keys, signatures)
+//! - counter: changes within a tag, always in the same direction
+//! - random: changes within a tag in both directions (checksums, MACs, or a
+//!   multi-byte counter whose low byte wraps)
+//!
+//! A block whose bytes move in both directions but whose 32-bit value (either
+//! byte order) is monotonic counts as one counter. Dumps of the same tag must
+//! be added together, oldest first; a tag is recognised by its UID.
+//!
+//! Counters and random bytes are told apart from unique ones only by how they
+//! change on one tag, so they need a tag dumped at least twice; with a single
+//! dump per tag a checksum looks unique. `reread_tags` says whether that is
+//! the case.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::protocol::st25tb::{ChipData, ChipType};
+
+/// Blocks below this are the chip's OTP area and hardware counters, which
+/// every cartridge uses the same way; they make poor profile matches.
+const FIRST_DATA_BLOCK: usize = 7;
+/// Most `match` lines put in a seeded profile.
+const MAX_MATCHES: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteClass {
+    /// Never read.
+    Missing,
+    Constant(u8),
+    Unique,
+    Counter {
+        down: bool,
+    },
+    Random,
+}
+
+impl ByteClass {
+    pub fn symbol(&self) -> char {
+        match self {
+            ByteClass::Missing => '-',
+            ByteClass::Constant(_) => '=',
+            ByteClass::Unique => 'U',
+            ByteClass::Counter { down: true } => 'v',
+            ByteClass::Counter { down: false } => '^',
+            ByteClass::Random => '?',
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ByteClass::Missing => "missing",
+            ByteClass::Constant(_) => "constant",
+            ByteClass::Unique => "unique",
+            ByteClass::Counter { .. } => "counter",
+            ByteClass::Random => "random",
+        }
+    }
+}
+
+/// Which way a value moved within a tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Trend {
+    up: bool,
+    down: bool,
+}
+
+impl Trend {
+    fn add<T: PartialOrd>(&mut self, last: T, value: T) {
+        self.up |= value > last;
+        self.down |= value < last;
+    }
+
+    fn monotonic(&self) -> bool {
+        !(self.up && self.down)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ByteStats {
+    first: Option<u8>,
+    /// Differs between any two dumps.
+    varies: bool,
+    /// Differs between two dumps of the same tag.
+    changes: bool,
+    trend: Trend,
+    /// Value in the current tag's previous dump.
+    last: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlockStats {
+    last: Option<[u8; 4]>,
+    le: Trend,
+    be: Trend,
+    /// Every change within a tag only cleared bits, like an OTP counter.
+    only_cleared: bool,
+}
+
+impl Default for BlockStats {
+    fn default() -> Self {
+        Self {
+            last: None,
+            le: Trend::default(),
+            be: Trend::default(),
+            only_cleared: true,
+        }
+    }
+}
+
+/// Multi-byte counter layout of a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockCounter {
+    /// Bits only ever cleared.
+    Otp,
+    Little,
+    Big,
+}
+
+#[derive(Default)]
+pub struct Analysis {
+    dumps: usize,
+    tags: usize,
+    /// Tags with more than one dump.
+    reread_tags: usize,
+    /// Dumps of the current tag so far.
+    tag_dumps: usize,
+    last_uid: Option<[u8; 8]>,
+    /// Longest MSB-first UID prefix shared by every dump.
+    uid_prefix: Vec<u8>,
+    /// `None` when the dumps are of different chip types.
+    chip_type: Option<ChipType>,
+    bytes: Vec<ByteStats>,
+    blocks: Vec<BlockStats>,
+}
+
+impl Analysis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dumps(&self) -> usize {
+        self.dumps
+    }
+
+    pub fn tags(&self) -> usize {
+        self.tags
+    }
+
+    /// Tags with two or more dumps; without any, no byte can be a counter or
+    /// random.
+    pub fn reread_tags(&self) -> usize {
+        self.reread_tags
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn add(&mut self, data: &ChipData) {
+        let uid = data.uid_msb_first();
+        if self.dumps == 0 {
+            self.uid_prefix = uid.to_vec();
+            self.chip_type = Some(data.chip_type());
+        } else {
+            let common = self.uid_prefix.iter().zip(&uid).take_while(|(a, b)| a == b);
+            let common = common.count();
+            self.uid_prefix.truncate(common);
+            if self.chip_type != Some(data.chip_type()) {
+                self.chip_type = None;
+            }
+        }
+        let new_tag = self.last_uid != Some(data.uid);
+        if new_tag {
+            self.tags += 1;
+            self.tag_dumps = 0;
+            self.last_uid = Some(data.uid);
+        }
+        self.tag_dumps += 1;
+        if self.tag_dumps == 2 {
+            self.reread_tags += 1;
+        }
+        self.dumps += 1;
+
+        if self.blocks.len() < data.block_count {
+            self.blocks.resize(data.block_count, BlockStats::default());
+            self.bytes
+                .resize(data.block_count * 4, ByteStats::default());
+        }
+        for (i, stats) in self.blocks.iter_mut().enumerate() {
+            let block = data.block(i);
+            if new_tag {
+                stats.last = None;
+            }
+            if let (Some(last), Some(value)) = (stats.last, block) {
+                stats
+                    .le
+                    .add(u32::from_le_bytes(last), u32::from_le_bytes(value));
+                stats
+                    .be
+                    .add(u32::from_be_bytes(last), u32::from_be_bytes(value));
+                stats.only_cleared &= (0..4).all(|b| value[b] & !last[b] == 0);
+            }
+            if block.is_some() {
+                stats.last = block;
+            }
+            for b in 0..4 {
+                let byte = &mut self.bytes[i * 4 + b];
+                if new_tag {
+                    byte.last = None;
+                }
+                let Some(value) = block.map(|v| v[b]) else {
+                    continue;
+                };
+                match byte.first {
+                    None => byte.first = Some(value),
+                    Some(first) => byte.varies |= first != value,
+                }
+                if let Some(last) = byte.last {
+                    byte.changes |= last != value;
+                    byte.trend.add(last, value);
+                }
+                byte.last = Some(value);
+            }
+        }
+    }
+
+    /// Bytes whose class satisfies `pred`.
+    pub fn count(&self, pred: impl Fn(&ByteClass) -> bool) -> usize {
+        (0..self.blocks.len())
+            .flat_map(|block| self.classes(block))
+            .filter(|c| pred(c))
+            .count()
+    }
+
+    /// How a block behaves as a whole, when some byte of it changes within a
+    /// tag and the 32-bit value moves one way.
+    pub fn block_counter(&self, block: usize) -> Option<BlockCounter> {
+        let stats = &self.blocks[block];
+        let bytes = &self.bytes[block * 4..block * 4 + 4];
+        if !bytes.iter().any(|b| b.changes) {
+            return None;
+        }
+        if stats.only_cleared {
+            Some(BlockCounter::Otp)
+        } else if stats.le.monotonic() {
+            Some(BlockCounter::Little)
+        } else if stats.be.monotonic() {
+            Some(BlockCounter::Big)
+        } else {
+            None
+        }
+    }
+
+    pub fn class(&self, block: usize, byte: usize) -> ByteClass {
+        let stats = &self.bytes[block * 4 + byte];
+        let Some(first) = stats.first else {
+            return ByteClass::Missing;
+        };
+        if !stats.varies {
+            return ByteClass::Constant(first);
+        }
+        if !stats.changes {
+            return ByteClass::Unique;
+        }
+        if stats.trend.monotonic() {
+            return ByteClass::Counter {
+                down: stats.trend.down,
+            };
+        }
+        let block_stats = &self.blocks[block];
+        match self.block_counter(block) {
+            Some(BlockCounter::Otp) => ByteClass::Counter { down: true },
+            Some(BlockCounter::Little) => ByteClass::Counter {
+                down: block_stats.le.down,
+            },
+            Some(BlockCounter::Big) => ByteClass::Counter {
+                down: block_stats.be.down,
+            },
+            None => ByteClass::Random,
+        }
+    }
+
+    fn classes(&self, block: usize) -> [ByteClass; 4] {
+        core::array::from_fn(|b| self.class(block, b))
+    }
+
+    /// One line per block, runs of identical constant (or unread) blocks
+    /// merged:
+    ///
+    /// ```text
+    /// B016       .. .. .. ..  U U U U
+    /// B020       27 29 FF ..  = = = v  counter
+    /// B023-B127  FF FF FF FF  = = = =
+    /// ```
+    ///
+    /// Constant bytes show their value, the others `..`.
+    pub fn map(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# {} dumps of {} tags", self.dumps, self.tags);
+        out.push_str("# = constant  U unique per tag  v/^ counter down/up  ? random  - unread\n");
+        if self.reread_tags == 0 {
+            out.push_str("# no tag dumped twice: counters and random bytes show as U\n");
+        }
+        let mut i = 0;
+        while i < self.blocks.len() {
+            let classes = self.classes(i);
+            let fixed = classes
+                .iter()
+                .all(|c| matches!(c, ByteClass::Constant(_) | ByteClass::Missing));
+            let mut end = i;
+            while fixed && end + 1 < self.blocks.len() && self.classes(end + 1) == classes {
+                end += 1;
+            }
+            let mut range = String::new();
+            let _ = write!(range, "B{:03}", i);
+            if end > i {
+                let _ = write!(range, "-B{:03}", end);
+            }
+            let _ = write!(out, "{:<10} ", range);
+            for (b, class) in classes.iter().enumerate() {
+                let sep = if b > 0 { " " } else { "" };
+                match class {
+                    ByteClass::Constant(v) => {
+                        let _ = write!(out, "{}{:02X}", sep, v);
+                    }
+                    _ => {
+                        let _ = write!(out, "{}..", sep);
+                    }
+                }
+            }
+            out.push(' ');
+            for class in &classes {
+                out.push(' ');
+                out.push(class.symbol());
+            }
+            match self.block_counter(i) {
+                Some(BlockCounter::Otp) => out.push_str("  counter, bits only cleared"),
+                Some(BlockCounter::Little) => out.push_str("  counter, u32 little endian"),
+                Some(BlockCounter::Big) => out.push_str("  counter, u32 big endian"),
+                None => {}
+            }
+            out.push('\n');
+            i = end + 1;
+        }
+        out
+    }
+
+    /// A profile in the `profile::parser` format with a field for every run
+    /// of non-constant bytes, as a starting point to rename and retype.
+    pub fn profile(&self, name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# Seeded from {} dumps of {} tags; rename the fields and check their types",
+            self.dumps, self.tags
+        );
+        out.push_str("[profile]\n");
+        let _ = writeln!(out, "name = {}", name);
+        if let Some(chip) = self.chip_type.filter(|&c| c != ChipType::Unknown) {
+            let _ = writeln!(out, "chip = {}", chip.name());
+        }
+        if !self.uid_prefix.is_empty() {
+            out.push_str("uid_prefix =");
+            for b in self.uid_prefix.iter().take(2) {
+                let _ = write!(out, " {:02X}", b);
+            }
+            out.push('\n');
+        }
+        for block in self.match_blocks() {
+            let _ = write!(out, "match = {}:", block);
+            for class in self.classes(block) {
+                match class {
+                    ByteClass::Constant(v) => {
+                        let _ = write!(out, " {:02X}", v);
+                    }
+                    _ => out.push_str(" ??"),
+                }
+            }
+            out.push('\n');
+        }
+
+        for block in 0..self.blocks.len() {
+            // A counter spread over the block is one field
+            if let Some(counter) = self.block_counter(block) {
+                let kind = if counter == BlockCounter::Otp {
+                    "otp"
+                } else {
+                    "dec"
+                };
+                push_field(&mut out, "counter", block, 0, 4, kind);
+                if counter == BlockCounter::Big {
+                    out.push_str("endian = big\n");
+                }
+                continue;
+            }
+            let classes = self.classes(block);
+            let mut b = 0;
+            while b < 4 {
+                let class = classes[b];
+                if matches!(class, ByteClass::Constant(_) | ByteClass::Missing) {
+                    b += 1;
+                    continue;
+                }
+                let same =
+                    |c: &ByteClass| core::mem::discriminant(c) == core::mem::discriminant(&class);
+                let len = classes[b..].iter().take_while(|c| same(c)).count();
+                let kind = match class {
+                    ByteClass::Counter { .. } => "dec",
+                    _ => "hex",
+                };
+                push_field(&mut out, class.name(), block, b, len, kind);
+                if len > 1 {
+                    out.push_str("endian = big\n");
+                }
+                b += len;
+            }
+        }
+        out
+    }
+
+    /// Data blocks with the most constant bytes that are neither `00` nor
+    /// `FF`, i.e. probably written by the cartridge maker; blocks without any
+    /// varying byte first.
+    fn match_blocks(&self) -> Vec<usize> {
+        let score = |block: usize| {
+            self.classes(block)
+                .iter()
+                .filter(|c| matches!(c, ByteClass::Constant(v) if *v != 0x00 && *v != 0xFF))
+                .count()
+        };
+        let fixed = |block: usize| {
+            self.classes(block)
+                .iter()
+                .all(|c| matches!(c, ByteClass::Constant(_)))
+        };
+        let mut blocks: Vec<usize> = (FIRST_DATA_BLOCK..self.blocks.len())
+            .filter(|&b| score(b) >= 2)
+            .collect();
+        blocks.sort_by_key(|&b| core::cmp::Reverse((fixed(b), score(b))));
+        blocks.truncate(MAX_MATCHES);
+        blocks.sort();
+        blocks
+    }
+}
+
+fn push_field(out: &mut String, class: &str, block: usize, offset: usize, size: usize, kind: &str) {
+    let _ = write!(out, "\n[field {}_{}", class, block);
+    if offset > 0 {
+        let _ = write!(out, "_{}", offset);
+    }
+    let _ = writeln!(out, "]\nblock = {}", block);
+    if offset > 0 {
+        let _ = writeln!(out, "offset = {}", offset);
+    }
+    let _ = writeln!(out, "size = {}\ntype = {}", size, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{parser, FieldKind, ProfileSet};
+
+    /// Three dumps of one tag, oldest first: block 8 fixed, 9 a serial, 10 a
+    /// little-endian counter, 11 byte 0 random and 12 OTP-style.
+    fn dumps(tag: u8) -> [ChipData; 3] {
+        let counter = 0x100 * tag as u32;
+        let random = if tag == 1 {
+            [0x10, 0x20, 0x15]
+        } else {
+            [0x10; 3]
+        };
+        let otp = [0xFF, 0xFE, 0xFC];
+        core::array::from_fn(|i| {
+            let mut data = ChipData {
+                uid: [tag, 0x56, 0x34, 0x12, 0x00, 0x30, 0x02, 0xD0],
+                block_count: 16,
+                blocks: [[0xFF; 4]; 256],
+                ..ChipData::default()
+            };
+            data.blocks[8] = [0x11, 0x22, 0x33, 0x44];
+            data.blocks[9] = [0xA0 + tag; 4];
+            data.blocks[10] = (counter - i as u32).to_le_bytes();
+            data.blocks[11] = [random[i], 0, 0, 0];
+            data.blocks[12] = [otp[i], 0xFF, 0xFF, 0xFF];
+            data
+        })
+    }
+
+    fn analysis() -> Analysis {
+        let mut analysis = Analysis::new();
+        for data in dumps(1).iter().chain(&dumps(2)) {
+            analysis.add(data);
+        }
+        analysis
+    }
+
+    #[test]
+    fn classifies_bytes() {
+        let a = analysis();
+        assert_eq!((a.dumps(), a.tags(), a.block_count()), (6, 2, 16));
+        assert_eq!(a.class(8, 1), ByteClass::Constant(0x22));
+        assert_eq!(a.class(9, 3), ByteClass::Unique);
+        // Byte 0 of the counter wraps 00 -> FF, the block value still only falls
+        assert_eq!(a.block_counter(10), Some(BlockCounter::Little));
+        assert_eq!(a.class(10, 0), ByteClass::Counter { down: true });
+        assert_eq!(a.class(10, 1), ByteClass::Counter { down: true });
+        assert_eq!(a.class(10, 2), ByteClass::Constant(0));
+        assert_eq!(a.block_counter(11), None);
+        assert_eq!(a.class(11, 0), ByteClass::Random);
+        assert_eq!(a.block_counter(12), Some(BlockCounter::Otp));
+        assert_eq!(a.block_counter(9), None);
+        assert_eq!(a.count(|c| *c == ByteClass::Unique), 4);
+
+        assert_eq!(a.reread_tags(), 2);
+
+        let mut short = Analysis::new();
+        short.add(&dumps(1)[0]);
+        let mut longer = dumps(1)[1].clone();
+        longer.block_count = 17;
+        short.add(&longer);
+        assert_eq!(short.class(16, 0), ByteClass::Constant(0xFF));
+        short.add(&dumps(1)[2]);
+        assert_eq!(short.block_count(), 17);
+    }
+
+    #[test]
+    fn map_merges_fixed_blocks() {
+        let map = analysis().map();
+        let lines: Vec<&str> = map.lines().collect();
+        assert_eq!(lines[0], "# 6 dumps of 2 tags");
+        assert_eq!(lines[2], "B000-B007  FF FF FF FF  = = = =");
+        assert_eq!(lines[3], "B008       11 22 33 44  = = = =");
+        assert_eq!(lines[4], "B009       .. .. .. ..  U U U U");
+        assert_eq!(
+            lines[5],
+            "B010       .. .. 00 00  v v = =  counter, u32 little endian"
+        );
+        assert_eq!(lines[6], "B011       .. 00 00 00  ? = = =");
+        assert_eq!(
+            lines[7],
+            "B012       .. FF FF FF  v = = =  counter, bits only cleared"
+        );
+        assert_eq!(lines[8], "B013-B015  FF FF FF FF  = = = =");
+        assert_eq!(lines.len(), 9);
+    }
+
+    #[test]
+    fn one_dump_per_tag_finds_no_changes() {
+        let mut a = Analysis::new();
+        a.add(&dumps(1)[1]);
+        a.add(&dumps(2)[1]);
+        assert_eq!((a.dumps(), a.tags(), a.reread_tags()), (2, 2, 0));
+        // Counter and random bytes only differ between tags
+        assert_eq!(a.class(10, 1), ByteClass::Unique);
+        assert_eq!(a.class(11, 0), ByteClass::Unique);
+        assert_eq!(a.block_counter(10), None);
+        let map = a.map();
+        assert_eq!(
+            map.lines().nth(2),
+            Some("# no tag dumped twice: counters and random bytes show as U")
+        );
+
+        a.add(&dumps(2)[2]);
+        assert_eq!(a.reread_tags(), 1);
+        assert_eq!(a.class(11, 0), ByteClass::Unique);
+        assert_eq!(a.class(10, 0), ByteClass::Counter { down: true });
+        assert!(!a.map().contains("no tag dumped twice"));
+    }
+
+    #[test]
+    fn seeded_profile_parses_and_matches() {
+        let text = analysis().profile("Seed");
+        let profiles = parser::parse(&text).unwrap();
+        let seed = &profiles[0];
+        assert_eq!(seed.name, "Seed");
+        assert_eq!(seed.chip_types, [ChipType::St25tb512At]);
+        assert_eq!(seed.uid_prefix, [0xD0, 0x02]);
+        assert_eq!(seed.block_matches.len(), 1);
+        assert_eq!(seed.block_matches[0].block, 8);
+        let fields: Vec<(&str, usize, usize, &FieldKind)> = seed
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.block, f.size, &f.kind))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("unique_9", 9, 4, &FieldKind::Hex),
+                ("counter_10", 10, 4, &FieldKind::Decimal),
+                ("random_11", 11, 1, &FieldKind::Hex),
+                ("counter_12", 12, 4, &FieldKind::Otp),
+            ]
+        );
+
+        let mut set = ProfileSet::new();
+        set.load_str(&text).unwrap();
+        for data in dumps(1).iter().chain(&dumps(2)) {
+            assert_eq!(set.find_match(data).map(|p| p.name.as_str()), Some("Seed"));
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Block, byte and bit differences between two dumps
+//!
+//! `old` and `new` are only labels: typically a fresh cartridge and the same
+//! one after use. Blocks missing on one side (past the end of the shorter
+//! dump, or unreadable) count as changed in every byte, with no bit flips.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::protocol::st25tb::{ChipData, SYSTEM_BLOCK};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockDiff {
+    pub index: usize,
+    pub old: Option<[u8; 4]>,
+    pub new: Option<[u8; 4]>,
+}
+
+impl BlockDiff {
+    /// Bit `i` set when byte `i` differs.
+    pub fn byte_mask(&self) -> u8 {
+        match (self.old, self.new) {
+            (Some(a), Some(b)) => (0..4).filter(|&i| a[i] != b[i]).fold(0, |m, i| m | 1 << i),
+            _ => 0x0F,
+        }
+    }
+
+    pub fn bytes(&self) -> u32 {
+        self.byte_mask().count_ones()
+    }
+
+    /// `old ^ new`, all zero when a side is missing.
+    pub fn flips(&self) -> [u8; 4] {
+        match (self.old, self.new) {
+            (Some(a), Some(b)) => core::array::from_fn(|i| a[i] ^ b[i]),
+            _ => [0; 4],
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.flips().iter().map(|b| b.count_ones()).sum()
+    }
+
+    /// Bits that went 0 -> 1.
+    pub fn bits_set(&self) -> u32 {
+        let new = self.new.unwrap_or([0; 4]);
+        let flips = self.flips();
+        (0..4).map(|i| (flips[i] & new[i]).count_ones()).sum()
+    }
+
+    /// Bits that went 1 -> 0, the only direction OTP and counter blocks allow.
+    pub fn bits_cleared(&self) -> u32 {
+        self.bits() - self.bits_set()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff {
+    pub old_uid: [u8; 8],
+    pub new_uid: [u8; 8],
+    pub old_count: usize,
+    pub new_count: usize,
+    /// Changed blocks in block order, the system block last.
+    pub blocks: Vec<BlockDiff>,
+}
+
+impl Diff {
+    pub fn new(old: &ChipData, new: &ChipData) -> Self {
+        let count = old.block_count.max(new.block_count);
+        let blocks = (0..count)
+            .chain([SYSTEM_BLOCK])
+            .map(|index| BlockDiff {
+                index,
+                old: old.block(index),
+                new: new.block(index),
+            })
+            .filter(|d| d.old != d.new)
+            .collect();
+        Self {
+            old_uid: old.uid_msb_first(),
+            new_uid: new.uid_msb_first(),
+            old_count: old.block_count,
+            new_count: new.block_count,
+            blocks,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.old_uid == self.new_uid && self.old_count == self.new_count && self.blocks.is_empty()
+    }
+
+    pub fn bytes(&self) -> u32 {
+        self.blocks.iter().map(BlockDiff::bytes).sum()
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.blocks.iter().map(BlockDiff::bits).sum()
+    }
+
+    /// One line per difference and a summary, nothing when the dumps match:
+    ///
+    /// ```text
+    /// B005: FF FF FF FE -> FF FF FF F0  ^ 00 00 00 0E  +0 -3
+    /// 1 blocks, 1 bytes, 3 bits differ
+    /// ```
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        if self.is_empty() {
+            return out;
+        }
+        if self.old_uid != self.new_uid {
+            out.push_str("UID:  ");
+            push_hex(&mut out, &self.old_uid, "");
+            out.push_str(" -> ");
+            push_hex(&mut out, &self.new_uid, "");
+            out.push('\n');
+        }
+        if self.old_count != self.new_count {
+            let _ = writeln!(out, "Blocks: {} -> {}", self.old_count, self.new_count);
+        }
+        for d in &self.blocks {
+            let _ = write!(out, "B{:03}: ", d.index);
+            push_block(&mut out, d.old);
+            out.push_str(" -> ");
+            push_block(&mut out, d.new);
+            if d.old.is_some() && d.new.is_some() {
+                out.push_str("  ^ ");
+                push_hex(&mut out, &d.flips(), " ");
+                let _ = write!(out, "  +{} -{}", d.bits_set(), d.bits_cleared());
+            }
+            out.push('\n');
+        }
+        let _ = writeln!(
+            out,
+            "{} blocks, {} bytes, {} bits differ",
+            self.blocks.len(),
+            self.bytes(),
+            self.bits()
+        );
+        out
+    }
+
+    /// A single-line JSON object; missing blocks are `null`, `bytes` lists
+    /// the changed byte offsets.
+    pub fn json(&self) -> String {
+        let mut out = String::from("{\"uid\":{\"old\":\"");
+        push_hex(&mut out, &self.old_uid, "");
+        out.push_str("\",\"new\":\"");
+        push_hex(&mut out, &self.new_uid, "");
+        let _ = write!(
+            out,
+            "\"}},\"block_count\":{{\"old\":{},\"new\":{}}},\"blocks\":[",
+            self.old_count, self.new_count
+        );
+        for (n, d) in self.blocks.iter().enumerate() {
+            let _ = write!(
+                out,
+                "{}{{\"block\":{},\"old\":",
+                if n > 0 { "," } else { "" },
+                d.index
+            );
+            push_json_block(&mut out, d.old);
+            out.push_str(",\"new\":");
+            push_json_block(&mut out, d.new);
+            out.push_str(",\"bytes\":[");
+            let mask = d.byte_mask();
+            let offsets = (0..4).filter(|i| mask & 1 << i != 0);
+            for (k, i) in offsets.enumerate() {
+                let _ = write!(out, "{}{}", if k > 0 { "," } else { "" }, i);
+            }
+            out.push_str("],\"flips\":\"");
+            push_hex(&mut out, &d.flips(), "");
+            let _ = write!(
+                out,
+                "\",\"set\":{},\"cleared\":{}}}",
+                d.bits_set(),
+                d.bits_cleared()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "],\"summary\":{{\"blocks\":{},\"bytes\":{},\"bits\":{}}}}}",
+            self.blocks.len(),
+            self.bytes(),
+            self.bits()
+        );
+        out
+    }
+}
+
+fn push_hex(out: &mut String, bytes: &[u8], sep: &str) {
+    for (i, b) in bytes.iter().enumerate() {
+        let _ = write!(out, "{}{:02X}", if i > 0 { sep } else { "" }, b);
+    }
+}
+
+fn push_block(out: &mut String, block: Option<[u8; 4]>) {
+    match block {
+        Some(b) => push_hex(out, &b, " "),
+        None => out.push_str("-- -- -- --"),
+    }
+}
+
+fn push_json_block(out: &mut String, block: Option<[u8; 4]>) {
+    match block {
+        Some(b) => {
+            out.push('"');
+            push_hex(out, &b, "");
+            out.push('"');
+        }
+        None => out.push_str("null"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::st25tb::FLAG_UNREADABLE;
+
+    fn dump(block_count: usize) -> ChipData {
+        let mut data = ChipData {
+            uid: [0x78, 0x56, 0x34, 0x12, 0x00, 0x0C, 0x02, 0xD0],
+            block_count,
+            system: Some([0xFF; 4]),
+            ..ChipData::default()
+        };
+        for block in &mut data.blocks[..block_count] {
+            *block = [0xFF; 4];
+        }
+        data
+    }
+
+    #[test]
+    fn identical_dumps() {
+        let diff = Diff::new(&dump(16), &dump(16));
+        assert!(diff.is_empty());
+        assert_eq!(diff.text(), "");
+        assert!(diff
+            .json()
+            .ends_with("\"blocks\":[],\"summary\":{\"blocks\":0,\"bytes\":0,\"bits\":0}}\n"));
+    }
+
+    #[test]
+    fn counts_bytes_and_bit_directions() {
+        let old = dump(16);
+        let mut new = dump(16);
+        new.blocks[5] = [0xF0, 0xFF, 0xFF, 0xFF];
+        new.blocks[9] = [0x0F, 0xFF, 0x00, 0xFF];
+        let diff = Diff::new(&old, &new);
+        assert_eq!(diff.blocks.len(), 2);
+        let b9 = diff.blocks[1];
+        assert_eq!((b9.index, b9.byte_mask(), b9.bytes()), (9, 0b0101, 2));
+        assert_eq!((b9.bits(), b9.bits_set(), b9.bits_cleared()), (12, 0, 12));
+        // The other way round the same bits are set
+        let back = Diff::new(&new, &old);
+        assert_eq!(back.blocks[1].bits_set(), 12);
+        assert_eq!(
+            diff.text(),
+            "B005: FF FF FF FF -> F0 FF FF FF  ^ 0F 00 00 00  +0 -4\n\
+             B009: FF FF FF FF -> 0F FF 00 FF  ^ F0 00 FF 00  +0 -12\n\
+             2 blocks, 3 bytes, 16 bits differ\n"
+        );
+        assert_eq!(
+            diff.json(),
+            "{\"uid\":{\"old\":\"D0020C0012345678\",\"new\":\"D0020C0012345678\"},\
+             \"block_count\":{\"old\":16,\"new\":16},\"blocks\":[\
+             {\"block\":5,\"old\":\"FFFFFFFF\",\"new\":\"F0FFFFFF\",\"bytes\":[0],\
+             \"flips\":\"0F000000\",\"set\":0,\"cleared\":4},\
+             {\"block\":9,\"old\":\"FFFFFFFF\",\"new\":\"0FFF00FF\",\"bytes\":[0,2],\
+             \"flips\":\"F000FF00\",\"set\":0,\"cleared\":12}],\
+             \"summary\":{\"blocks\":2,\"bytes\":3,\"bits\":16}}\n"
+        );
+    }
+
+    #[test]
+    fn missing_blocks_change_every_byte() {
+        let old = dump(16);
+        let mut new = dump(17);
+        new.flags[3] = FLAG_UNREADABLE;
+        new.uid[0] = 0x79;
+        new.system = None;
+        let diff = Diff::new(&old, &new);
+        let indexes: Vec<usize> = diff.blocks.iter().map(|d| d.index).collect();
+        assert_eq!(indexes, [3, 16, SYSTEM_BLOCK]);
+        assert!(diff.blocks.iter().all(|d| d.bytes() == 4 && d.bits() == 0));
+        assert_eq!(
+            diff.text(),
+            "UID:  D0020C0012345678 -> D0020C0012345679\n\
+             Blocks: 16 -> 17\n\
+             B003: FF FF FF FF -> -- -- -- --\n\
+             B016: -- -- -- -- -> FF FF FF FF\n\
+             B255: FF FF FF FF -> -- -- -- --\n\
+             3 blocks, 12 bytes, 0 bits differ\n"
+        );
+        assert!(diff
+            .json()
+            .contains("{\"block\":16,\"old\":null,\"new\":\"FFFFFFFF\",\"bytes\":[0,1,2,3]"));
+    }
+}
@@ -0,0 +1,8 @@
+//! Comparing dumps to work out what a cartridge stores where
+
+pub mod checksum;
+pub mod classify;
+pub mod diff;
+
+pub use classify::{Analysis, ByteClass};
+pub use diff::{BlockDiff, Diff};
@@ -20,6 +20,11 @@ pub mod pins {
     pub const DISPLAY_DC: u8 = 16;
     pub const DISPLAY_RST: u8 = 40;
 
+    // microSD (shares the display SPI bus)
+    pub const SD_CS: u8 = 13;
+    pub const SD_MISO: u8 = 10;
+    pub const CC1101_CS: u8 = 12;
+
     // Rotary Encoder
     pub const ENCODER_A: u8 = 4;
     pub const ENCODER_B: u8 = 5;
@@ -29,3 +34,10 @@ pub mod pins {
     pub const PWR_EN: u8 = 15;
     pub const USER_KEY: u8 = 6;
 }
+
+/// Flash layout, must match `partitions.csv`
+pub mod flash {
+    /// Data partition `dumps` holding the internal dump store
+    pub const DUMP_PARTITION_OFFSET: u32 = 0x310000;
+    pub const DUMP_PARTITION_SIZE: u32 = 0x80000;
+}
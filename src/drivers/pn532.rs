@@ -43,6 +43,7 @@ pub enum Pn532Error {
     InvalidResponse,
     ChecksumError,
     NackReceived,
+    InvalidArgument,
 }
 
 impl<I2C, IRQ, RST> Pn532<I2C, IRQ, RST>
@@ -124,7 +125,11 @@ where
     }
 
     fn delay_ms(&self, ms: u32) {
+        #[cfg(target_os = "none")]
         esp_hal::rom::ets_delay_us(ms * 1000);
+        // The host tool builds this module too
+        #[cfg(not(target_os = "none"))]
+        std::thread::sleep(core::time::Duration::from_millis(ms as u64));
     }
 
     pub fn get_firmware_version(&mut self) -> Result<(u8, u8, u8), Pn532Error> {
@@ -231,6 +236,16 @@ where
         Ok(&response[1..])
     }
 
+    /// Sends a raw command (code and parameters) and returns the response data
+    /// that follows the response code.
+    pub fn exchange(&mut self, cmd: &[u8]) -> Result<&[u8], Pn532Error> {
+        if cmd.is_empty() || cmd.len() > 56 {
+            return Err(Pn532Error::InvalidArgument);
+        }
+        self.send_command(cmd)?;
+        self.read_response()
+    }
+
     fn send_command(&mut self, cmd: &[u8]) -> Result<(), Pn532Error> {
         let len = cmd.len() + 1;
         let mut frame = [0u8; 64];
@@ -327,9 +342,7 @@ where
             return Err(Pn532Error::ChecksumError);
         }
 
-        for i in 0..len {
-            self.buffer[i] = frame[data_start + i];
-        }
+        self.buffer[..len].copy_from_slice(&frame[data_start..data_start + len]);
         Ok(&self.buffer[2..len])
     }
 
@@ -0,0 +1,879 @@
+//! Wear-levelled key/value store on a raw flash partition
+//!
+//! The partition is used as a log of erase sectors. Records are appended to the
+//! newest sector and only count once their CRC checks out, so a write cut short
+//! by power loss is ignored on the next mount. When the log runs out of room the
+//! oldest sector is compacted: its live records are copied verbatim into a fresh
+//! sector, the old one is marked retired and then erased. Sectors are recycled
+//! oldest first, which spreads erases evenly, and one sector is always kept free
+//! so compaction can proceed.
+//!
+//! Generic over `NorFlash`, so the tests below run it on the host against a
+//! simulated NOR flash that loses power during every write and erase in turn.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_storage::nor_flash::NorFlash;
+
+use super::{decode_dump, encode_dump, parse_patch_name, patch_text, DumpStore, StorageError};
+use crate::protocol::st25tb::ChipData;
+use crate::util::crc::Crc32;
+
+/// Sector header: magic, generation, retired marker, reserved.
+const SECTOR_MAGIC: u32 = 0x564B_4652; // "RFKV"
+const SECTOR_HEADER: u32 = 16;
+const RETIRED_OFFSET: u32 = 8;
+
+/// Record header: magic, key length, flags, value length, reserved, sequence, CRC
+/// over header and payload. Key and value follow, padded to `ALIGN`.
+const RECORD_MAGIC: u16 = 0x4B52;
+const RECORD_HEADER: u32 = 16;
+const FLAG_TOMBSTONE: u8 = 0x01;
+
+const ALIGN: u32 = 4;
+const ERASED: u32 = 0xFFFF_FFFF;
+
+pub const MAX_KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sector {
+    Free,
+    Active { gen: u32 },
+}
+
+/// Newest record stored under a key.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    /// Store-wide write counter; higher is newer.
+    pub seq: u32,
+    pub len: usize,
+    sector: usize,
+    offset: u32,
+    tombstone: bool,
+}
+
+impl Entry {
+    fn size(&self) -> u32 {
+        record_size(self.key.len(), self.len)
+    }
+}
+
+struct RecordHeader {
+    key_len: usize,
+    flags: u8,
+    value_len: usize,
+    seq: u32,
+    crc: u32,
+}
+
+impl RecordHeader {
+    fn parse(b: &[u8; RECORD_HEADER as usize]) -> Option<Self> {
+        if u16::from_le_bytes([b[0], b[1]]) != RECORD_MAGIC {
+            return None;
+        }
+        let key_len = b[2] as usize;
+        if key_len == 0 || key_len > MAX_KEY_LEN {
+            return None;
+        }
+        Some(Self {
+            key_len,
+            flags: b[3],
+            value_len: u16::from_le_bytes([b[4], b[5]]) as usize,
+            seq: u32::from_le_bytes([b[8], b[9], b[10], b[11]]),
+            crc: u32::from_le_bytes([b[12], b[13], b[14], b[15]]),
+        })
+    }
+}
+
+fn record_size(key_len: usize, value_len: usize) -> u32 {
+    let len = RECORD_HEADER + (key_len + value_len) as u32;
+    len.div_ceil(ALIGN) * ALIGN
+}
+
+fn encode_record(key: &str, flags: u8, seq: u32, value: &[u8]) -> Vec<u8> {
+    let size = record_size(key.len(), value.len()) as usize;
+    let mut buf = Vec::with_capacity(size);
+    buf.extend_from_slice(&RECORD_MAGIC.to_le_bytes());
+    buf.push(key.len() as u8);
+    buf.push(flags);
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&[0xFF, 0xFF]);
+    buf.extend_from_slice(&seq.to_le_bytes());
+
+    let mut crc = Crc32::new();
+    crc.update(&buf);
+    crc.update(key.as_bytes());
+    crc.update(value);
+    buf.extend_from_slice(&crc.finish().to_le_bytes());
+
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(value);
+    buf.resize(size, 0xFF);
+    buf
+}
+
+pub struct FlashStore<F: NorFlash> {
+    flash: F,
+    base: u32,
+    sector_size: u32,
+    sectors: Vec<Sector>,
+    head: usize,
+    /// Write offset in the head sector; `sector_size` once it must not be appended to.
+    head_used: u32,
+    gen: u32,
+    seq: u32,
+    index: Vec<Entry>,
+}
+
+impl<F: NorFlash> FlashStore<F> {
+    /// Opens the store in `size` bytes at `offset`, repairing an interrupted commit
+    /// or compaction. A blank partition is formatted on first use.
+    pub fn mount(flash: F, offset: u32, size: u32) -> Result<Self, StorageError> {
+        let sector_size = F::ERASE_SIZE as u32;
+        let count = (size / sector_size) as usize;
+        if !offset.is_multiple_of(sector_size)
+            || !size.is_multiple_of(sector_size)
+            || count < 3
+            || !ALIGN.is_multiple_of(F::READ_SIZE as u32)
+            || !ALIGN.is_multiple_of(F::WRITE_SIZE as u32)
+        {
+            return Err(StorageError::Device);
+        }
+
+        let mut store = Self {
+            flash,
+            base: offset,
+            sector_size,
+            sectors: vec![Sector::Free; count],
+            head: 0,
+            head_used: 0,
+            gen: 0,
+            seq: 0,
+            index: Vec::new(),
+        };
+        store.recover()?;
+        Ok(store)
+    }
+
+    /// Gives the flash back, e.g. to remount it.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Erases every record.
+    pub fn format(&mut self) -> Result<(), StorageError> {
+        for sector in 0..self.sectors.len() {
+            self.erase(sector)?;
+            self.sectors[sector] = Sector::Free;
+        }
+        self.index.clear();
+        self.gen = 0;
+        self.seq = 0;
+        self.open_sector(0)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.index
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.iter().any(|e| e.key == key)
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let (sector, offset) = self
+            .index
+            .iter()
+            .find(|e| e.key == key)
+            .map(|e| (e.sector, e.offset))
+            .ok_or(StorageError::NotFound)?;
+        let (header, payload) = self
+            .read_record(sector, offset)?
+            .ok_or(StorageError::Corrupt)?;
+        Ok(payload[header.key_len..header.key_len + header.value_len].to_vec())
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), StorageError> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(StorageError::InvalidName);
+        }
+        let size = record_size(key.len(), value.len());
+        let live: u32 = self
+            .index
+            .iter()
+            .filter(|e| e.key != key)
+            .map(Entry::size)
+            .sum();
+        if size > self.max_record() || live + size > self.capacity() {
+            return Err(StorageError::Full);
+        }
+        self.commit(key, 0, value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), StorageError> {
+        if !self.contains(key) {
+            return Err(StorageError::NotFound);
+        }
+        self.commit(key, FLAG_TOMBSTONE, &[])
+    }
+
+    /// Bytes of live records the store accepts. One sector stays free for
+    /// compaction and one more is spare; with records capped at a third of a
+    /// sector, at most a quarter of each sector is lost to padding at its end.
+    pub fn capacity(&self) -> u32 {
+        (self.sectors.len() as u32 - 2) * (self.sector_size - SECTOR_HEADER) / 4 * 3
+    }
+
+    /// Largest record (header, key and value) a single `set` may write.
+    pub fn max_record(&self) -> u32 {
+        (self.sector_size - SECTOR_HEADER) / 3
+    }
+
+    pub fn used(&self) -> u32 {
+        self.index.iter().map(Entry::size).sum()
+    }
+
+    fn commit(&mut self, key: &str, flags: u8, value: &[u8]) -> Result<(), StorageError> {
+        let record = encode_record(key, flags, self.seq + 1, value);
+        self.make_room(record.len() as u32)?;
+        self.seq += 1;
+        let offset = self.append(&record)?;
+
+        self.index.retain(|e| e.key != key);
+        if flags & FLAG_TOMBSTONE == 0 {
+            self.index.push(Entry {
+                key: String::from(key),
+                seq: self.seq,
+                len: value.len(),
+                sector: self.head,
+                offset,
+                tombstone: false,
+            });
+        }
+        Ok(())
+    }
+
+    fn make_room(&mut self, size: u32) -> Result<(), StorageError> {
+        for _ in 0..self.sectors.len() {
+            if self.head_used + size <= self.sector_size {
+                return Ok(());
+            }
+            let free = self.sectors.iter().filter(|s| **s == Sector::Free).count();
+            let next = self.next_free().ok_or(StorageError::Corrupt)?;
+            if free > 1 {
+                self.open_sector(next)?;
+            } else {
+                self.compact(next)?;
+            }
+        }
+        Err(StorageError::Full)
+    }
+
+    /// Moves the live records of the oldest sector into `target` and erases it.
+    /// Tombstones are dropped: anything they shadow is at least as old, so it
+    /// lives in the same sector and goes away with it.
+    fn compact(&mut self, target: usize) -> Result<(), StorageError> {
+        let victim = self.oldest().ok_or(StorageError::Corrupt)?;
+        self.open_sector(target)?;
+
+        for i in 0..self.index.len() {
+            if self.index[i].sector != victim {
+                continue;
+            }
+            let mut record = vec![0u8; self.index[i].size() as usize];
+            self.read(victim, self.index[i].offset, &mut record)?;
+            let offset = self.append(&record)?;
+            self.index[i].sector = target;
+            self.index[i].offset = offset;
+        }
+
+        // Retire first: once marked, a remount knows the copies are complete.
+        self.write(victim, RETIRED_OFFSET, &0u32.to_le_bytes())?;
+        self.erase(victim)?;
+        self.sectors[victim] = Sector::Free;
+        Ok(())
+    }
+
+    fn recover(&mut self) -> Result<(), StorageError> {
+        let mut header = [0u8; SECTOR_HEADER as usize];
+        let mut stale = Vec::new();
+        let mut foreign = false;
+        for sector in 0..self.sectors.len() {
+            self.read(sector, 0, &mut header)?;
+            let word = |i: usize| {
+                u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]])
+            };
+            if word(0) == SECTOR_MAGIC && word(8) == ERASED {
+                self.sectors[sector] = Sector::Active { gen: word(4) };
+            } else if header.iter().any(|&b| b != 0xFF) {
+                // Retired, half-erased or not ours
+                foreign |= word(0) != SECTOR_MAGIC;
+                stale.push(sector);
+            }
+        }
+        // Never wipe a partition that holds no store at all, e.g. when running
+        // under a bootloader with a different partition layout.
+        if foreign && self.newest().is_none() {
+            return Err(StorageError::Corrupt);
+        }
+        for sector in stale {
+            self.erase(sector)?;
+        }
+
+        // Without a free sector, power failed during compaction: the newest sector
+        // holds partial copies and the originals are still intact.
+        if !self.sectors.contains(&Sector::Free) {
+            if let Some(newest) = self.newest() {
+                self.erase(newest)?;
+                self.sectors[newest] = Sector::Free;
+            }
+        }
+
+        let Some(head) = self.newest() else {
+            return self.open_sector(0);
+        };
+        self.head = head;
+        if let Sector::Active { gen } = self.sectors[head] {
+            self.gen = gen;
+        }
+
+        let mut order: Vec<(u32, usize)> = self
+            .sectors
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| match s {
+                Sector::Active { gen } => Some((*gen, i)),
+                Sector::Free => None,
+            })
+            .collect();
+        order.sort_unstable();
+
+        let mut latest: Vec<Entry> = Vec::new();
+        for (_, sector) in order {
+            let used = self.scan(sector, &mut latest)?;
+            if sector == head {
+                self.head_used = used;
+            }
+        }
+        // A torn write may have left junk past the last valid record
+        if self.head_used < self.sector_size && !self.is_blank(head, self.head_used)? {
+            self.head_used = self.sector_size;
+        }
+        latest.retain(|e| !e.tombstone);
+        self.index = latest;
+        Ok(())
+    }
+
+    /// Adds the records of `sector` to `latest`, returning the end of the valid log.
+    fn scan(&mut self, sector: usize, latest: &mut Vec<Entry>) -> Result<u32, StorageError> {
+        let mut offset = SECTOR_HEADER;
+        while offset + RECORD_HEADER <= self.sector_size {
+            let mut raw = [0u8; RECORD_HEADER as usize];
+            self.read(sector, offset, &mut raw)?;
+            if raw.iter().all(|&b| b == 0xFF) {
+                return Ok(offset);
+            }
+            let Some((header, payload)) = self.read_record(sector, offset)? else {
+                break;
+            };
+            let Ok(key) = core::str::from_utf8(&payload[..header.key_len]) else {
+                break;
+            };
+
+            self.seq = self.seq.max(header.seq);
+            let newer = latest
+                .iter()
+                .position(|e| e.key == key)
+                .map(|i| (i, latest[i].seq < header.seq));
+            let entry = Entry {
+                key: String::from(key),
+                seq: header.seq,
+                len: header.value_len,
+                sector,
+                offset,
+                tombstone: header.flags & FLAG_TOMBSTONE != 0,
+            };
+            match newer {
+                Some((i, true)) => latest[i] = entry,
+                Some((_, false)) => {}
+                None => latest.push(entry),
+            }
+            offset += record_size(header.key_len, header.value_len);
+        }
+        Ok(self.sector_size)
+    }
+
+    /// Reads and verifies one record; `None` when it is torn or not a record.
+    fn read_record(
+        &mut self,
+        sector: usize,
+        offset: u32,
+    ) -> Result<Option<(RecordHeader, Vec<u8>)>, StorageError> {
+        let mut raw = [0u8; RECORD_HEADER as usize];
+        self.read(sector, offset, &mut raw)?;
+        let Some(header) = RecordHeader::parse(&raw) else {
+            return Ok(None);
+        };
+        let size = record_size(header.key_len, header.value_len);
+        if offset + size > self.sector_size {
+            return Ok(None);
+        }
+
+        let mut payload = vec![0u8; (size - RECORD_HEADER) as usize];
+        self.read(sector, offset + RECORD_HEADER, &mut payload)?;
+        let mut crc = Crc32::new();
+        crc.update(&raw[..12]);
+        crc.update(&payload[..header.key_len + header.value_len]);
+        if crc.finish() != header.crc {
+            return Ok(None);
+        }
+        Ok(Some((header, payload)))
+    }
+
+    fn open_sector(&mut self, sector: usize) -> Result<(), StorageError> {
+        if !self.is_blank(sector, 0)? {
+            self.erase(sector)?;
+        }
+        self.gen += 1;
+        let mut header = [0xFFu8; SECTOR_HEADER as usize];
+        header[0..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&self.gen.to_le_bytes());
+        self.write(sector, 0, &header)?;
+        self.sectors[sector] = Sector::Active { gen: self.gen };
+        self.head = sector;
+        self.head_used = SECTOR_HEADER;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<u32, StorageError> {
+        let offset = self.head_used;
+        // Reserve the space before writing: a failed write leaves junk behind
+        self.head_used += record.len() as u32;
+        if let Err(e) = self.write(self.head, offset, record) {
+            self.head_used = self.sector_size;
+            return Err(e);
+        }
+        Ok(offset)
+    }
+
+    fn next_free(&self) -> Option<usize> {
+        let n = self.sectors.len();
+        (1..=n)
+            .map(|i| (self.head + i) % n)
+            .find(|&s| self.sectors[s] == Sector::Free)
+    }
+
+    fn newest(&self) -> Option<usize> {
+        self.active().max_by_key(|&(gen, _)| gen).map(|(_, s)| s)
+    }
+
+    fn oldest(&self) -> Option<usize> {
+        self.active()
+            .filter(|&(_, s)| s != self.head)
+            .min_by_key(|&(gen, _)| gen)
+            .map(|(_, s)| s)
+    }
+
+    fn active(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        self.sectors
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| match s {
+                Sector::Active { gen } => Some((*gen, i)),
+                Sector::Free => None,
+            })
+    }
+
+    fn is_blank(&mut self, sector: usize, from: u32) -> Result<bool, StorageError> {
+        let mut buf = [0u8; 256];
+        let mut offset = from;
+        while offset < self.sector_size {
+            let len = (self.sector_size - offset).min(buf.len() as u32) as usize;
+            self.read(sector, offset, &mut buf[..len])?;
+            if buf[..len].iter().any(|&b| b != 0xFF) {
+                return Ok(false);
+            }
+            offset += len as u32;
+        }
+        Ok(true)
+    }
+
+    fn addr(&self, sector: usize, offset: u32) -> u32 {
+        self.base + sector as u32 * self.sector_size + offset
+    }
+
+    fn read(&mut self, sector: usize, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        let addr = self.addr(sector, offset);
+        self.flash.read(addr, buf).map_err(|_| StorageError::Device)
+    }
+
+    fn write(&mut self, sector: usize, offset: u32, buf: &[u8]) -> Result<(), StorageError> {
+        let addr = self.addr(sector, offset);
+        self.flash
+            .write(addr, buf)
+            .map_err(|_| StorageError::Device)
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), StorageError> {
+        let from = self.addr(sector, 0);
+        self.flash
+            .erase(from, from + self.sector_size)
+            .map_err(|_| StorageError::Device)
+    }
+}
+
+impl<F: NorFlash> FlashStore<F> {
+    /// Keys in write order, patches or dumps.
+    fn keys(&self, patches: bool) -> Vec<String> {
+        let mut entries: Vec<&Entry> = self
+            .index
+            .iter()
+            .filter(|e| parse_patch_name(&e.key).is_some() == patches)
+            .collect();
+        entries.sort_unstable_by_key(|e| e.seq);
+        entries.into_iter().map(|e| e.key.clone()).collect()
+    }
+}
+
+/// Dumps and patches share the key space; patch names are kept for patches.
+impl<F: NorFlash> DumpStore for FlashStore<F> {
+    fn list_dumps(&mut self) -> Result<Vec<String>, StorageError> {
+        Ok(self.keys(false))
+    }
+
+    fn save_dump(&mut self, name: &str, data: &ChipData) -> Result<(), StorageError> {
+        if parse_patch_name(name).is_some() {
+            return Err(StorageError::InvalidName);
+        }
+        self.set(name, &encode_dump(data))
+    }
+
+    fn load_dump(&mut self, name: &str) -> Result<ChipData, StorageError> {
+        Ok(decode_dump(&self.get(name)?)?)
+    }
+
+    fn delete_dump(&mut self, name: &str) -> Result<(), StorageError> {
+        self.remove(name)
+    }
+
+    fn list_patches(&mut self) -> Result<Vec<String>, StorageError> {
+        Ok(self.keys(true))
+    }
+
+    fn save_patch(&mut self, name: &str, text: &str) -> Result<(), StorageError> {
+        if parse_patch_name(name).is_none() {
+            return Err(StorageError::InvalidName);
+        }
+        self.set(name, text.as_bytes())
+    }
+
+    fn load_patch(&mut self, name: &str) -> Result<String, StorageError> {
+        patch_text(self.get(name)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR: u32 = 4096;
+    const SECTORS: u32 = 4;
+
+    #[derive(Debug)]
+    struct PowerCut;
+
+    impl NorFlashError for PowerCut {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    /// NOR flash in RAM: erase sets bytes to FF, programming only clears bits.
+    /// With `budget` set, the write or erase that exhausts it is torn halfway
+    /// and fails, as if power went away during it.
+    #[derive(Clone)]
+    struct SimFlash {
+        mem: Vec<u8>,
+        budget: Option<usize>,
+        ops: usize,
+        erases: Vec<u32>,
+    }
+
+    impl SimFlash {
+        fn new() -> Self {
+            Self {
+                mem: vec![0xFF; (SECTOR * SECTORS) as usize],
+                budget: None,
+                ops: 0,
+                erases: vec![0; SECTORS as usize],
+            }
+        }
+
+        /// Counts an operation; false when power is cut during it.
+        fn spend(&mut self) -> bool {
+            self.ops += 1;
+            match self.budget.as_mut() {
+                Some(0) => false,
+                Some(b) => {
+                    *b -= 1;
+                    true
+                }
+                None => true,
+            }
+        }
+    }
+
+    impl ErrorType for SimFlash {
+        type Error = PowerCut;
+    }
+
+    impl ReadNorFlash for SimFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerCut> {
+            let at = offset as usize;
+            bytes.copy_from_slice(&self.mem[at..at + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.mem.len()
+        }
+    }
+
+    impl NorFlash for SimFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR as usize;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerCut> {
+            assert!(from.is_multiple_of(SECTOR) && to.is_multiple_of(SECTOR));
+            let whole = self.spend();
+            let end = if whole { to } else { from + (to - from) / 2 };
+            self.mem[from as usize..end as usize].fill(0xFF);
+            if !whole {
+                return Err(PowerCut);
+            }
+            for sector in from / SECTOR..to / SECTOR {
+                self.erases[sector as usize] += 1;
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerCut> {
+            assert!(offset.is_multiple_of(4) && bytes.len().is_multiple_of(4));
+            let whole = self.spend();
+            let len = if whole { bytes.len() } else { bytes.len() / 2 };
+            let at = offset as usize;
+            for (cell, b) in self.mem[at..at + len].iter_mut().zip(bytes) {
+                *cell &= b;
+            }
+            if whole {
+                Ok(())
+            } else {
+                Err(PowerCut)
+            }
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    enum Op {
+        Set(&'static str, u8, usize),
+        Remove(&'static str),
+    }
+
+    /// Enough rewrites of a few keys to go round the log and compact twice.
+    fn workload() -> Vec<Op> {
+        let keys = ["A", "BB", "CCC", "DDDD"];
+        let mut ops = Vec::new();
+        for round in 0..12u8 {
+            for (i, key) in keys.iter().enumerate() {
+                ops.push(Op::Set(key, round, 200 + 97 * i + round as usize));
+            }
+            if round % 4 == 3 {
+                ops.push(Op::Remove(keys[round as usize % keys.len()]));
+            }
+        }
+        ops
+    }
+
+    fn value(fill: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| fill ^ i as u8).collect()
+    }
+
+    type Model = BTreeMap<&'static str, Vec<u8>>;
+
+    fn apply(model: &mut Model, op: Op) {
+        match op {
+            Op::Set(key, fill, len) => {
+                model.insert(key, value(fill, len));
+            }
+            Op::Remove(key) => {
+                model.remove(key);
+            }
+        }
+    }
+
+    fn run(store: &mut FlashStore<SimFlash>, op: Op) -> Result<(), StorageError> {
+        match op {
+            Op::Set(key, fill, len) => store.set(key, &value(fill, len)),
+            Op::Remove(key) => store.remove(key),
+        }
+    }
+
+    fn contents(store: &mut FlashStore<SimFlash>) -> Model {
+        let keys: Vec<String> = store.entries().iter().map(|e| e.key.clone()).collect();
+        let mut model = Model::new();
+        for key in ["A", "BB", "CCC", "DDDD"] {
+            if keys.iter().any(|k| k == key) {
+                model.insert(key, store.get(key).unwrap());
+            }
+        }
+        assert_eq!(model.len(), keys.len(), "unexpected keys {:?}", keys);
+        model
+    }
+
+    fn mount(flash: SimFlash) -> FlashStore<SimFlash> {
+        FlashStore::mount(flash, 0, SECTOR * SECTORS).unwrap()
+    }
+
+    #[test]
+    fn set_get_remove_remount() {
+        let mut store = mount(SimFlash::new());
+        store.set("a", b"hello").unwrap();
+        store.set("b", &[7; 1000]).unwrap();
+        store.set("a", b"world").unwrap();
+        assert_eq!(store.get("a").unwrap(), b"world");
+        store.remove("b").unwrap();
+        assert_eq!(store.get("b"), Err(StorageError::NotFound));
+        assert_eq!(store.remove("b"), Err(StorageError::NotFound));
+
+        let mut store = mount(store.release());
+        assert_eq!(store.get("a").unwrap(), b"world");
+        assert_eq!(store.get("b"), Err(StorageError::NotFound));
+        assert_eq!(store.entries().len(), 1);
+    }
+
+    #[test]
+    fn patches_beside_dumps() {
+        let mut store = mount(SimFlash::new());
+        let data = ChipData {
+            block_count: 16,
+            ..ChipData::default()
+        };
+        store.save_dump("TAG.DMP", &data).unwrap();
+        assert_eq!(
+            store.save_new_patch("B001: 00 -> 01\n").unwrap(),
+            "PATCH001.PAT"
+        );
+        store.save_dump("OTHER.DMP", &data).unwrap();
+        assert_eq!(
+            store.save_new_patch("B002: 00 -> 02\n").unwrap(),
+            "PATCH002.PAT"
+        );
+
+        assert_eq!(store.list_dumps().unwrap(), ["TAG.DMP", "OTHER.DMP"]);
+        assert_eq!(
+            store.list_patches().unwrap(),
+            ["PATCH001.PAT", "PATCH002.PAT"]
+        );
+        assert_eq!(
+            store.load_patch("PATCH001.PAT").unwrap(),
+            "B001: 00 -> 01\n"
+        );
+        assert_eq!(
+            store.save_dump("PATCH003.PAT", &data),
+            Err(StorageError::InvalidName)
+        );
+        assert_eq!(
+            store.rename_dump("TAG.DMP", "PATCH003.PAT"),
+            Err(StorageError::InvalidName)
+        );
+        assert_eq!(
+            store.save_patch("TAG.DMP", ""),
+            Err(StorageError::InvalidName)
+        );
+    }
+
+    #[test]
+    fn rejects_oversized() {
+        let mut store = mount(SimFlash::new());
+        let too_big = vec![0; store.max_record() as usize];
+        assert_eq!(store.set("a", &too_big), Err(StorageError::Full));
+        assert_eq!(store.set("", b"x"), Err(StorageError::InvalidName));
+    }
+
+    #[test]
+    fn compaction_spreads_erases() {
+        let mut store = mount(SimFlash::new());
+        let mut model = Model::new();
+        for _ in 0..10 {
+            for op in workload() {
+                run(&mut store, op).unwrap();
+                apply(&mut model, op);
+            }
+        }
+        assert_eq!(contents(&mut store), model);
+        let mut store = mount(store.release());
+        assert_eq!(contents(&mut store), model);
+        let erases = &store.release().erases;
+        let (min, max) = (erases.iter().min().unwrap(), erases.iter().max().unwrap());
+        assert!(*min > 0 && max - min <= 1, "uneven wear {:?}", erases);
+    }
+
+    /// Cuts power at every single write and erase of the workload in turn.
+    /// After remounting, each key holds its value from before or after the
+    /// interrupted operation, everything else is intact, and the store keeps
+    /// working.
+    #[test]
+    fn survives_power_loss_anywhere() {
+        let blank = mount(SimFlash::new()).release();
+        let mut clean = mount(blank.clone());
+        for op in workload() {
+            run(&mut clean, op).unwrap();
+        }
+        let clean = clean.release();
+        let compactions = clean.erases.iter().sum::<u32>() - blank.erases.iter().sum::<u32>();
+        assert!(compactions >= 2, "workload compacted {} times", compactions);
+        let total = clean.ops - blank.ops;
+
+        for cut in 0..total {
+            let mut flash = blank.clone();
+            flash.budget = Some(cut);
+            let mut store = mount(flash);
+            let mut model = Model::new();
+            let mut interrupted = None;
+            for op in workload() {
+                if run(&mut store, op).is_err() {
+                    interrupted = Some(op);
+                    break;
+                }
+                apply(&mut model, op);
+            }
+            let op = interrupted.expect("power cut not reached");
+
+            let mut flash = store.release();
+            flash.budget = None;
+            let mut store = mount(flash);
+            let found = contents(&mut store);
+            let mut after = model.clone();
+            apply(&mut after, op);
+            assert!(
+                found == model || found == after,
+                "cut {} of {}: lost data",
+                cut,
+                total
+            );
+
+            store.set("A", b"after the cut").unwrap();
+            let mut store = mount(store.release());
+            assert_eq!(store.get("A").unwrap(), b"after the cut");
+            for (key, value) in found.iter().filter(|(k, _)| **k != "A") {
+                assert_eq!(&store.get(key).unwrap(), value, "cut {}", cut);
+            }
+        }
+    }
+}
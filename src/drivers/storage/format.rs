@@ -0,0 +1,240 @@
+//! Binary dump file format
+//!
+//! ```text
+//! 0   4  magic "ST25"
+//! 4   1  version       format version that wrote the file
+//! 5   1  compat        oldest reader version that can parse it
+//! 6   2  header_len    bytes before the block data (LE)
+//! 8   1  chip type     ChipType::code
+//! 9   1  chip_id
+//! 10  2  block_count   (LE)
+//! 12  8  UID           LSB first, as sent by the tag
+//! 20  4  timestamp     Unix time of the read, 0 if unknown (LE)
+//! 24  1  has_system    1 if the system block below is valid
+//! 25  3  reserved
+//! 28  4  system block 255
+//! --     block data    block_count * 4
+//! --     block flags   block_count bytes (FLAG_*)
+//! --     CRC-32        over everything before it (LE)
+//! ```
+//!
+//! Newer versions may append header fields (growing `header_len`) and sections
+//! between the flags and the CRC; they only raise `compat` when older readers
+//! would misread the file.
+//!
+//! The board has no battery-backed clock: `timestamp` stays 0 until a host
+//! sends the time (`rfid-host` does when it connects), and such dumps are
+//! listed without a read date.
+
+use alloc::vec::Vec;
+
+use crate::protocol::st25tb::{BlockKind, ChipData, ChipType, FLAG_OTP};
+use crate::util::crc::crc32;
+
+pub const MAGIC: [u8; 4] = *b"ST25";
+pub const VERSION: u8 = 1;
+const HEADER_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatError {
+    Truncated,
+    BadMagic,
+    /// Needs a reader of at least this version.
+    Unsupported(u8),
+    BadCrc,
+    BadBlockCount,
+}
+
+pub fn encode_dump(data: &ChipData) -> Vec<u8> {
+    let count = data.block_count;
+    let chip = data.chip_type();
+    let mut out = Vec::with_capacity(HEADER_LEN + count * 5 + 4);
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    out.push(VERSION);
+    out.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+    out.push(chip.code());
+    out.push(data.chip_id);
+    out.extend_from_slice(&(count as u16).to_le_bytes());
+    out.extend_from_slice(&data.uid);
+    out.extend_from_slice(&data.timestamp.to_le_bytes());
+    out.push(data.system.is_some() as u8);
+    out.extend_from_slice(&[0; 3]);
+    out.extend_from_slice(&data.system.unwrap_or([0xFF; 4]));
+
+    for block in &data.blocks[..count] {
+        out.extend_from_slice(block);
+    }
+    for (i, &flags) in data.flags[..count].iter().enumerate() {
+        let otp = matches!(
+            chip.block_kind(i),
+            BlockKind::ResettableOtp | BlockKind::Counter
+        );
+        out.push(if otp { flags | FLAG_OTP } else { flags });
+    }
+
+    let crc = crc32(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+pub fn decode_dump(bytes: &[u8]) -> Result<ChipData, FormatError> {
+    if !bytes.starts_with(&MAGIC) {
+        return Err(FormatError::BadMagic);
+    }
+    if bytes.len() < HEADER_LEN + 4 {
+        return Err(FormatError::Truncated);
+    }
+    let compat = bytes[5];
+    if compat > VERSION {
+        return Err(FormatError::Unsupported(compat));
+    }
+
+    let (body, crc) = bytes.split_at(bytes.len() - 4);
+    if crc32(body) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+        return Err(FormatError::BadCrc);
+    }
+
+    let header_len = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+    let count = u16::from_le_bytes([bytes[10], bytes[11]]) as usize;
+    if header_len < HEADER_LEN {
+        return Err(FormatError::Truncated);
+    }
+    if count > 256 {
+        return Err(FormatError::BadBlockCount);
+    }
+    if body.len() < header_len + count * 5 {
+        return Err(FormatError::Truncated);
+    }
+
+    let mut data = ChipData {
+        chip_id: bytes[9],
+        block_count: count,
+        timestamp: u32::from_le_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]),
+        ..Default::default()
+    };
+    data.uid.copy_from_slice(&bytes[12..20]);
+    if bytes[24] != 0 {
+        data.system = Some([bytes[28], bytes[29], bytes[30], bytes[31]]);
+    }
+    if ChipType::from_code(bytes[8]) != data.chip_type() {
+        log::warn!("Dump chip type {} does not match its UID", bytes[8]);
+    }
+
+    let blocks = &body[header_len..header_len + count * 4];
+    for (i, chunk) in blocks.chunks_exact(4).enumerate() {
+        data.blocks[i].copy_from_slice(chunk);
+    }
+    let flags = &body[header_len + count * 4..header_len + count * 5];
+    data.flags[..count].copy_from_slice(flags);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::st25tb::FLAG_UNREADABLE;
+
+    fn dump() -> ChipData {
+        let mut data = ChipData {
+            chip_id: 0x42,
+            uid: [0x78, 0x56, 0x34, 0x12, 0x00, 0x1C, 0x02, 0xD0],
+            block_count: 128,
+            system: Some([0xFF, 0xFF, 0xFF, 0x7F]),
+            timestamp: 1_760_000_000,
+            ..ChipData::default()
+        };
+        for (i, block) in data.blocks[..128].iter_mut().enumerate() {
+            *block = [i as u8, 0xA5, 0x5A, !(i as u8)];
+        }
+        data.flags[40] = FLAG_UNREADABLE;
+        data
+    }
+
+    /// Fixes up the CRC after a test edits the bytes before it.
+    fn reseal(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - 4);
+        let crc = crc32(bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = dump();
+        let bytes = encode_dump(&data);
+        assert_eq!(bytes.len(), HEADER_LEN + 128 * 5 + 4);
+        let back = decode_dump(&bytes).unwrap();
+        assert_eq!(back.chip_id, data.chip_id);
+        assert_eq!(back.uid, data.uid);
+        assert_eq!(back.block_count, 128);
+        assert_eq!(back.blocks, data.blocks);
+        assert_eq!(back.system, data.system);
+        assert_eq!(back.timestamp, data.timestamp);
+        // OTP and counter blocks are marked for other tools
+        assert!((0..7).all(|i| back.flags[i] == FLAG_OTP));
+        assert_eq!(back.flags[7], 0);
+        assert_eq!(back.flags[40], FLAG_UNREADABLE);
+
+        let no_system = ChipData {
+            system: None,
+            ..data
+        };
+        assert_eq!(decode_dump(&encode_dump(&no_system)).unwrap().system, None);
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = encode_dump(&dump());
+        assert_eq!(
+            decode_dump(b"not a dump").err(),
+            Some(FormatError::BadMagic)
+        );
+        assert_eq!(
+            decode_dump(&bytes[..20]).err(),
+            Some(FormatError::Truncated)
+        );
+        assert_eq!(
+            decode_dump(&bytes[..bytes.len() - 1]).err(),
+            Some(FormatError::BadCrc)
+        );
+        let mut flipped = bytes.clone();
+        flipped[HEADER_LEN + 17] ^= 0x10;
+        assert_eq!(decode_dump(&flipped).err(), Some(FormatError::BadCrc));
+
+        let mut too_many = bytes.clone();
+        too_many[10..12].copy_from_slice(&257u16.to_le_bytes());
+        reseal(&mut too_many);
+        assert_eq!(
+            decode_dump(&too_many).err(),
+            Some(FormatError::BadBlockCount)
+        );
+        let mut short = bytes.clone();
+        short[10..12].copy_from_slice(&200u16.to_le_bytes());
+        reseal(&mut short);
+        assert_eq!(decode_dump(&short).err(), Some(FormatError::Truncated));
+    }
+
+    #[test]
+    fn reads_newer_compatible_versions() {
+        let data = dump();
+        let mut bytes = encode_dump(&data);
+        // A later version with 8 more header bytes and an extra section
+        bytes.truncate(bytes.len() - 4);
+        bytes[4] = VERSION + 1;
+        bytes[6..8].copy_from_slice(&(HEADER_LEN as u16 + 8).to_le_bytes());
+        bytes.splice(HEADER_LEN..HEADER_LEN, [0xEE; 8]);
+        bytes.extend_from_slice(b"extra section");
+        bytes.extend_from_slice(&[0; 4]);
+        reseal(&mut bytes);
+        let back = decode_dump(&bytes).unwrap();
+        assert_eq!(back.blocks, data.blocks);
+        assert_eq!(back.flags[40], FLAG_UNREADABLE);
+
+        bytes[5] = VERSION + 1;
+        reseal(&mut bytes);
+        assert_eq!(
+            decode_dump(&bytes).err(),
+            Some(FormatError::Unsupported(VERSION + 1))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+pub mod flash;
+pub mod format;
+pub mod sd;
+
+pub use flash::FlashStore;
+pub use format::{decode_dump, encode_dump, FormatError};
+pub use sd::{FixedTime, SdStorage};
+
+use crate::protocol::st25tb::{ChipData, ChipType};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use heapless::String;
+
+pub const DUMP_DIR: &str = "DUMPS";
+pub const PROFILE_DIR: &str = "PROFILES";
+pub const PATCH_DIR: &str = "PATCHES";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageError {
+    Device,
+    NotFound,
+    Full,
+    Corrupt,
+    Filesystem,
+    InvalidName,
+    Exists,
+    Format(FormatError),
+}
+
+impl From<FormatError> for StorageError {
+    fn from(e: FormatError) -> Self {
+        StorageError::Format(e)
+    }
+}
+
+/// Summary of a stored dump for listings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpInfo {
+    pub name: alloc::string::String,
+    pub uid: [u8; 8],
+    pub chip_type: ChipType,
+    pub block_count: usize,
+    /// Unix time of the read, 0 if unknown.
+    pub timestamp: u32,
+}
+
+/// Somewhere dumps are kept by name: the SD card or the internal flash store.
+/// Patches recorded by the editor are kept beside them as text, under names
+/// from [`patch_name`].
+pub trait DumpStore {
+    fn list_dumps(&mut self) -> Result<Vec<alloc::string::String>, StorageError>;
+    fn save_dump(&mut self, name: &str, data: &ChipData) -> Result<(), StorageError>;
+    fn load_dump(&mut self, name: &str) -> Result<ChipData, StorageError>;
+    fn delete_dump(&mut self, name: &str) -> Result<(), StorageError>;
+
+    fn list_patches(&mut self) -> Result<Vec<alloc::string::String>, StorageError>;
+    fn save_patch(&mut self, name: &str, text: &str) -> Result<(), StorageError>;
+    fn load_patch(&mut self, name: &str) -> Result<alloc::string::String, StorageError>;
+
+    /// Saves `text` under the next free patch name and returns that name.
+    fn save_new_patch(&mut self, text: &str) -> Result<String<PATCH_NAME_LEN>, StorageError> {
+        let name = next_patch_name(&self.list_patches()?)?;
+        self.save_patch(&name, text)?;
+        Ok(name)
+    }
+
+    /// Saves `data` as a new revision and returns its name; older ones are kept.
+    fn save_revision(&mut self, data: &ChipData) -> Result<String<DUMP_NAME_LEN>, StorageError> {
+        let name = next_dump_name(&data.uid, &self.list_dumps()?)?;
+        self.save_dump(&name, data)?;
+        Ok(name)
+    }
+
+    fn dump_info(&mut self, name: &str) -> Result<DumpInfo, StorageError> {
+        let data = self.load_dump(name)?;
+        Ok(DumpInfo {
+            name: name.into(),
+            uid: data.uid,
+            chip_type: data.chip_type(),
+            block_count: data.block_count,
+            timestamp: data.timestamp,
+        })
+    }
+
+    /// Listing with summaries; dumps that fail to load are skipped.
+    fn list_info(&mut self) -> Result<Vec<DumpInfo>, StorageError> {
+        let mut infos = Vec::new();
+        for name in self.list_dumps()? {
+            match self.dump_info(&name) {
+                Ok(info) => infos.push(info),
+                Err(e) => log::warn!("Skipping dump {}: {:?}", name, e),
+            }
+        }
+        Ok(infos)
+    }
+
+    /// Copies the dump to `to` and then removes `from`; neither backend can
+    /// rename in place.
+    fn rename_dump(&mut self, from: &str, to: &str) -> Result<(), StorageError> {
+        if self.list_dumps()?.iter().any(|n| n == to) {
+            return Err(StorageError::Exists);
+        }
+        let data = self.load_dump(from)?;
+        self.save_dump(to, &data)?;
+        self.delete_dump(from)
+    }
+}
+
+/// Longest generated name, e.g. `D00212345678ABCD-001.DMP`.
+pub const DUMP_NAME_LEN: usize = 24;
+pub const MAX_REVISION: u16 = 999;
+
+/// `<UID MSB-first>-<revision>.DMP`; every save of a tag gets the next revision.
+pub fn dump_name(uid: &[u8; 8], revision: u16) -> String<DUMP_NAME_LEN> {
+    let mut name = String::new();
+    for b in uid.iter().rev() {
+        let _ = write!(name, "{:02X}", b);
+    }
+    let _ = write!(name, "-{:03}.DMP", revision);
+    name
+}
+
+/// Inverse of [`dump_name`]; `None` for user-chosen names.
+pub fn parse_dump_name(name: &str) -> Option<([u8; 8], u16)> {
+    let (stem, ext) = name.split_once('.')?;
+    let (uid_hex, rev) = stem.split_once('-')?;
+    if !ext.eq_ignore_ascii_case("DMP") || uid_hex.len() != 16 || rev.len() != 3 {
+        return None;
+    }
+    let mut msb = [0u8; 8];
+    if crate::util::hex::parse_bytes(uid_hex, &mut msb)? != 8 {
+        return None;
+    }
+    let mut uid = msb;
+    uid.reverse();
+    Some((uid, rev.parse().ok()?))
+}
+
+/// Name for the next revision of `uid`, after the highest one in `existing`.
+pub fn next_dump_name(
+    uid: &[u8; 8],
+    existing: &[alloc::string::String],
+) -> Result<String<DUMP_NAME_LEN>, StorageError> {
+    let last = existing
+        .iter()
+        .filter_map(|n| parse_dump_name(n))
+        .filter(|(u, _)| u == uid)
+        .map(|(_, rev)| rev)
+        .max()
+        .unwrap_or(0);
+    if last >= MAX_REVISION {
+        return Err(StorageError::Full);
+    }
+    Ok(dump_name(uid, last + 1))
+}
+
+/// `PATCH001.PAT`, a valid 8.3 name as is.
+pub const PATCH_NAME_LEN: usize = 12;
+
+pub fn patch_name(number: u16) -> String<PATCH_NAME_LEN> {
+    let mut name = String::new();
+    let _ = write!(name, "PATCH{:03}.PAT", number);
+    name
+}
+
+/// Inverse of [`patch_name`].
+pub fn parse_patch_name(name: &str) -> Option<u16> {
+    let (stem, ext) = name.split_once('.')?;
+    let number = stem.strip_prefix("PATCH")?;
+    if !ext.eq_ignore_ascii_case("PAT") || number.len() != 3 {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// Name after the highest numbered patch in `existing`.
+pub fn next_patch_name(
+    existing: &[alloc::string::String],
+) -> Result<String<PATCH_NAME_LEN>, StorageError> {
+    let last = existing
+        .iter()
+        .filter_map(|n| parse_patch_name(n))
+        .max()
+        .unwrap_or(0);
+    if last >= MAX_REVISION {
+        return Err(StorageError::Full);
+    }
+    Ok(patch_name(last + 1))
+}
+
+/// Stored patch text, which must be UTF-8.
+fn patch_text(bytes: Vec<u8>) -> Result<alloc::string::String, StorageError> {
+    alloc::string::String::from_utf8(bytes).map_err(|_| StorageError::Corrupt)
+}
@@ -0,0 +1,391 @@
+//! microSD storage (FAT16/32) on the SPI bus shared with the display
+//!
+//! Generic over `BlockDevice`, so the same code runs against a disk image on the host.
+//!
+//! The FAT driver only writes 8.3 names, so generated dump names
+//! (`D00212345678ABCD-001.DMP`) are split into a directory per UID half:
+//! `DUMPS/D0021234/5678ABCD.001`. Names chosen by the user stay flat in `DUMPS`.
+//! Patches are text files in `PATCHES`.
+
+use super::{
+    decode_dump, encode_dump, parse_dump_name, parse_patch_name, patch_text, DumpStore,
+    StorageError, DUMP_DIR, PATCH_DIR,
+};
+use crate::protocol::st25tb::ChipData;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use embedded_sdmmc::{
+    BlockDevice, Directory, Error, Mode, TimeSource, Timestamp, VolumeIdx, VolumeManager,
+};
+
+impl<E: core::fmt::Debug> From<Error<E>> for StorageError {
+    fn from(e: Error<E>) -> Self {
+        match e {
+            Error::DeviceError(_) => StorageError::Device,
+            Error::NotFound => StorageError::NotFound,
+            Error::DiskFull | Error::NotEnoughSpace => StorageError::Full,
+            Error::FilenameError(_) => StorageError::InvalidName,
+            _ => StorageError::Filesystem,
+        }
+    }
+}
+
+/// The board has no battery-backed clock; files get a fixed timestamp.
+pub struct FixedTime;
+
+impl TimeSource for FixedTime {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 56,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+pub struct SdStorage<D: BlockDevice, T: TimeSource> {
+    volume_mgr: VolumeManager<D, T>,
+}
+
+impl<D: BlockDevice, T: TimeSource> SdStorage<D, T> {
+    pub fn new(device: D, time: T) -> Self {
+        Self {
+            volume_mgr: VolumeManager::new(device, time),
+        }
+    }
+
+    /// Opens the `/`-separated `path` below the root directory, creating missing
+    /// directories when `create` is set.
+    fn with_dir<R>(
+        &self,
+        path: &str,
+        create: bool,
+        f: impl FnOnce(&Directory<'_, D, T, 4, 4, 1>) -> Result<R, StorageError>,
+    ) -> Result<R, StorageError> {
+        let volume = self.volume_mgr.open_volume(VolumeIdx(0))?;
+        let root = volume.open_root_dir()?;
+        walk(&root, path, create, f)
+    }
+
+    /// Names of the files (or subdirectories, with `dirs` set) in `path`.
+    fn entries(&self, path: &str, dirs: bool) -> Result<Vec<String>, StorageError> {
+        let result = self.with_dir(path, false, |d| {
+            let mut names = Vec::new();
+            d.iterate_dir(|entry| {
+                if entry.attributes.is_directory() == dirs && !entry.attributes.is_volume() {
+                    let mut name = String::new();
+                    let _ = write!(name, "{}", entry.name);
+                    if name != "." && name != ".." {
+                        names.push(name);
+                    }
+                }
+            })?;
+            Ok(names)
+        });
+        match result {
+            Err(StorageError::NotFound) => Ok(Vec::new()),
+            other => other,
+        }
+    }
+
+    pub fn list(&self, path: &str) -> Result<Vec<String>, StorageError> {
+        self.entries(path, false)
+    }
+
+    pub fn read_file(&self, dir: &str, name: &str) -> Result<Vec<u8>, StorageError> {
+        self.with_dir(dir, false, |d| {
+            let file = d.open_file_in_dir(name, Mode::ReadOnly)?;
+            let mut out = Vec::with_capacity(file.length() as usize);
+            let mut buf = [0u8; 128];
+            while !file.is_eof() {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                out.extend_from_slice(&buf[..n]);
+            }
+            Ok(out)
+        })
+    }
+
+    pub fn write_file(&self, dir: &str, name: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        self.with_dir(dir, true, |d| {
+            let file = d.open_file_in_dir(name, Mode::ReadWriteCreateOrTruncate)?;
+            file.write(bytes)?;
+            file.close()?;
+            Ok(())
+        })
+    }
+
+    pub fn delete(&self, dir: &str, name: &str) -> Result<(), StorageError> {
+        self.with_dir(dir, false, |d| Ok(d.delete_file_in_dir(name)?))
+    }
+}
+
+/// Opens the first component of `path` in `dir` and recurses into it; each
+/// directory handle borrows its parent, so every level gets its own frame.
+fn walk<D: BlockDevice, T: TimeSource, R>(
+    dir: &Directory<'_, D, T, 4, 4, 1>,
+    path: &str,
+    create: bool,
+    f: impl FnOnce(&Directory<'_, D, T, 4, 4, 1>) -> Result<R, StorageError>,
+) -> Result<R, StorageError> {
+    if path.is_empty() {
+        return f(dir);
+    }
+    let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+    let sub = match dir.open_dir(name) {
+        Ok(d) => d,
+        Err(Error::NotFound) if create => {
+            dir.make_dir_in_dir(name)?;
+            dir.open_dir(name)?
+        }
+        Err(e) => return Err(e.into()),
+    };
+    walk(&sub, rest, create, f)
+}
+
+/// Directory and 8.3 file name holding the dump called `name`.
+fn dump_path(name: &str) -> (String, String) {
+    let mut dir = String::from(DUMP_DIR);
+    if parse_dump_name(name).is_none() {
+        return (dir, String::from(name));
+    }
+    // D00212345678ABCD-001.DMP -> D0021234 / 5678ABCD.001
+    let _ = write!(dir, "/{}", &name[..8]);
+    let mut file = String::from(&name[8..16]);
+    let _ = write!(file, ".{}", &name[17..20]);
+    (dir, file)
+}
+
+impl<D: BlockDevice, T: TimeSource> DumpStore for SdStorage<D, T> {
+    fn list_dumps(&mut self) -> Result<Vec<String>, StorageError> {
+        let mut names = self.list(DUMP_DIR)?;
+        for dir in self.entries(DUMP_DIR, true)? {
+            let mut path = String::from(DUMP_DIR);
+            let _ = write!(path, "/{}", dir);
+            for file in self.list(&path)? {
+                let Some((stem, rev)) = file.split_once('.') else {
+                    continue;
+                };
+                let mut name = String::new();
+                let _ = write!(name, "{}{}-{}.DMP", dir, stem, rev);
+                if parse_dump_name(&name).is_some() {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn save_dump(&mut self, name: &str, data: &ChipData) -> Result<(), StorageError> {
+        let (dir, file) = dump_path(name);
+        self.write_file(&dir, &file, &encode_dump(data))
+    }
+
+    fn load_dump(&mut self, name: &str) -> Result<ChipData, StorageError> {
+        let (dir, file) = dump_path(name);
+        Ok(decode_dump(&self.read_file(&dir, &file)?)?)
+    }
+
+    fn delete_dump(&mut self, name: &str) -> Result<(), StorageError> {
+        let (dir, file) = dump_path(name);
+        self.delete(&dir, &file)
+    }
+
+    fn list_patches(&mut self) -> Result<Vec<String>, StorageError> {
+        let mut names = self.list(PATCH_DIR)?;
+        names.retain(|n| parse_patch_name(n).is_some());
+        Ok(names)
+    }
+
+    fn save_patch(&mut self, name: &str, text: &str) -> Result<(), StorageError> {
+        self.write_file(PATCH_DIR, name, text.as_bytes())
+    }
+
+    fn load_patch(&mut self, name: &str) -> Result<String, StorageError> {
+        patch_text(self.read_file(PATCH_DIR, name)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use embedded_sdmmc::{Block, BlockCount, BlockIdx};
+
+    /// Disk image in RAM: an MBR with one FAT16 partition, freshly formatted.
+    struct RamDisk(RefCell<Vec<u8>>);
+
+    const PART_START: usize = 1;
+    const PART_BLOCKS: usize = 8192;
+    const FAT_BLOCKS: usize = 32;
+    const ROOT_ENTRIES: usize = 512;
+
+    impl RamDisk {
+        fn formatted() -> Self {
+            let mut img = alloc::vec![0u8; (PART_START + PART_BLOCKS) * 512];
+            // MBR partition entry 0: FAT16, LBA start and size
+            let entry = &mut img[446..462];
+            entry[4] = 0x06;
+            entry[8..12].copy_from_slice(&(PART_START as u32).to_le_bytes());
+            entry[12..16].copy_from_slice(&(PART_BLOCKS as u32).to_le_bytes());
+            img[510] = 0x55;
+            img[511] = 0xAA;
+
+            let bpb = &mut img[PART_START * 512..(PART_START + 1) * 512];
+            bpb[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+            bpb[3..11].copy_from_slice(b"RFIDTEST");
+            bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+            bpb[13] = 1; // blocks per cluster
+            bpb[14..16].copy_from_slice(&1u16.to_le_bytes()); // reserved
+            bpb[16] = 2; // FATs
+            bpb[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+            bpb[19..21].copy_from_slice(&(PART_BLOCKS as u16).to_le_bytes());
+            bpb[21] = 0xF8;
+            bpb[22..24].copy_from_slice(&(FAT_BLOCKS as u16).to_le_bytes());
+            bpb[38] = 0x29;
+            bpb[43..54].copy_from_slice(b"NO NAME    ");
+            bpb[54..62].copy_from_slice(b"FAT16   ");
+            bpb[510] = 0x55;
+            bpb[511] = 0xAA;
+
+            for fat in 0..2 {
+                let at = (PART_START + 1 + fat * FAT_BLOCKS) * 512;
+                img[at..at + 4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]);
+            }
+            RamDisk(RefCell::new(img))
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        type Error = ();
+
+        fn read(&self, blocks: &mut [Block], start: BlockIdx) -> Result<(), ()> {
+            let img = self.0.borrow();
+            for (i, block) in blocks.iter_mut().enumerate() {
+                let at = (start.0 as usize + i) * 512;
+                block
+                    .contents
+                    .copy_from_slice(img.get(at..at + 512).ok_or(())?);
+            }
+            Ok(())
+        }
+
+        fn write(&self, blocks: &[Block], start: BlockIdx) -> Result<(), ()> {
+            let mut img = self.0.borrow_mut();
+            for (i, block) in blocks.iter().enumerate() {
+                let at = (start.0 as usize + i) * 512;
+                img.get_mut(at..at + 512)
+                    .ok_or(())?
+                    .copy_from_slice(&block.contents);
+            }
+            Ok(())
+        }
+
+        fn num_blocks(&self) -> Result<BlockCount, ()> {
+            Ok(BlockCount((self.0.borrow().len() / 512) as u32))
+        }
+    }
+
+    fn storage() -> SdStorage<RamDisk, FixedTime> {
+        SdStorage::new(RamDisk::formatted(), FixedTime)
+    }
+
+    fn chip(fill: u8) -> ChipData {
+        let mut data = ChipData {
+            uid: [0x0D, 0xCB, 0xA9, 0x78, 0x56, 0x34, 0x12, 0xD0],
+            block_count: 16,
+            ..Default::default()
+        };
+        for (i, block) in data.blocks[..16].iter_mut().enumerate() {
+            *block = [fill, i as u8, 0, 0];
+        }
+        data
+    }
+
+    #[test]
+    fn dumps_round_trip() {
+        let mut sd = storage();
+        assert_eq!(sd.list_dumps(), Ok(Vec::new()));
+        let first = sd.save_revision(&chip(1)).unwrap();
+        let second = sd.save_revision(&chip(2)).unwrap();
+        assert_ne!(first, second);
+        sd.save_dump("ORIGINAL.DMP", &chip(3)).unwrap();
+
+        let mut names = sd.list_dumps().unwrap();
+        names.sort();
+        assert_eq!(names, [first.as_str(), second.as_str(), "ORIGINAL.DMP"]);
+        assert_eq!(
+            sd.load_dump(&first).unwrap().blocks[..16],
+            chip(1).blocks[..16]
+        );
+        assert_eq!(
+            sd.load_dump(&second).unwrap().blocks[..16],
+            chip(2).blocks[..16]
+        );
+        assert_eq!(sd.load_dump("ORIGINAL.DMP").unwrap().uid, chip(3).uid);
+
+        sd.delete_dump(&first).unwrap();
+        assert_eq!(sd.load_dump(&first).err(), Some(StorageError::NotFound));
+        assert_eq!(sd.list_dumps().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn patches_beside_dumps() {
+        let mut sd = storage();
+        assert_eq!(sd.list_patches(), Ok(Vec::new()));
+        sd.save_revision(&chip(1)).unwrap();
+        assert_eq!(
+            sd.save_new_patch("B001: 00 -> 01\n").unwrap(),
+            "PATCH001.PAT"
+        );
+        assert_eq!(
+            sd.save_new_patch("B002: 00 -> 02\n").unwrap(),
+            "PATCH002.PAT"
+        );
+        let mut names = sd.list_patches().unwrap();
+        names.sort();
+        assert_eq!(names, ["PATCH001.PAT", "PATCH002.PAT"]);
+        assert_eq!(sd.load_patch("PATCH002.PAT").unwrap(), "B002: 00 -> 02\n");
+        assert_eq!(sd.list_dumps().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn files_overwrite() {
+        let sd = storage();
+        sd.write_file("PROFILES", "A.INI", b"first version")
+            .unwrap();
+        sd.write_file("PROFILES", "A.INI", b"second").unwrap();
+        assert_eq!(sd.read_file("PROFILES", "A.INI").unwrap(), b"second");
+        let long: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        sd.write_file("PROFILES", "B.INI", &long).unwrap();
+        assert_eq!(sd.read_file("PROFILES", "B.INI").unwrap(), long);
+        assert_eq!(sd.list("PROFILES").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn nested_directories() {
+        let sd = storage();
+        assert_eq!(sd.read_file("A/B/C", "X.TXT"), Err(StorageError::NotFound));
+        sd.write_file("A/B/C", "X.TXT", b"deep").unwrap();
+        sd.write_file("A/B", "Y.TXT", b"middle").unwrap();
+        sd.write_file("A/D", "Z.TXT", b"side").unwrap();
+        assert_eq!(sd.read_file("A/B/C", "X.TXT").unwrap(), b"deep");
+        assert_eq!(sd.read_file("A/B", "Y.TXT").unwrap(), b"middle");
+        assert_eq!(sd.read_file("A/D", "Z.TXT").unwrap(), b"side");
+
+        let mut dirs = sd.entries("A", true).unwrap();
+        dirs.sort();
+        assert_eq!(dirs, ["B", "D"]);
+        assert_eq!(sd.entries("A/B", true).unwrap(), ["C"]);
+        assert_eq!(sd.list("A/B").unwrap(), ["Y.TXT"]);
+        assert_eq!(sd.list("A/B/C").unwrap(), ["X.TXT"]);
+        assert_eq!(sd.list("A/E"), Ok(Vec::new()));
+        assert_eq!(sd.read_file("A/E", "X.TXT"), Err(StorageError::NotFound));
+    }
+}
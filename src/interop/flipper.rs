@@ -0,0 +1,202 @@
+//! Flipper Zero NFC device files with `Device type: ST25TB`
+//!
+//! The UID line is MSB first. Flipper always lists every block of the chip
+//! type, so short dumps are padded with `FF`; a dump with more blocks than its
+//! chip type keeps all of them.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::{parse_hex, push_hex, uid_from_msb_first, ImportError};
+use crate::protocol::st25tb::{ChipData, ChipType};
+
+pub const FILETYPE: &str = "Filetype: Flipper NFC device";
+const VERSION: u32 = 4;
+const DEVICE_TYPE: &str = "ST25TB";
+
+/// Flipper names the SRI parts after their ST25TBX equivalents.
+fn type_name(chip: ChipType, block_count: usize) -> &'static str {
+    match chip {
+        ChipType::Sri512 => "ST25TBX512",
+        ChipType::Srix4k => "ST25TBX4K",
+        ChipType::Unknown => match block_count {
+            0..=16 => ChipType::St25tb512At.name(),
+            17..=64 => ChipType::St25tb02k.name(),
+            _ => ChipType::St25tb04k.name(),
+        },
+        other => other.name(),
+    }
+}
+
+fn type_from_name(name: &str) -> Option<ChipType> {
+    match name {
+        "ST25TBX512" => Some(ChipType::Sri512),
+        "ST25TBX4K" => Some(ChipType::Srix4k),
+        _ => ChipType::from_name(name),
+    }
+}
+
+pub fn export(data: &ChipData) -> String {
+    let name = type_name(data.chip_type(), data.block_count);
+    let size = type_from_name(name).map_or(data.block_count, |t| t.block_count());
+    if data.block_count > size {
+        log::warn!("{} blocks, more than a {} has", data.block_count, name);
+    }
+    let count = size.max(data.block_count);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", FILETYPE);
+    let _ = writeln!(out, "Version: {}", VERSION);
+    let _ = writeln!(out, "Device type: {}", DEVICE_TYPE);
+    out.push_str("# UID is common for all formats\nUID: ");
+    push_hex(&mut out, &data.uid_msb_first(), " ");
+    out.push_str("\n# ST25TB specific data\n");
+    let _ = writeln!(out, "ST25TB Type: {}", name);
+    for i in 0..count {
+        let block = if i < data.block_count {
+            data.blocks[i]
+        } else {
+            [0xFF; 4]
+        };
+        let _ = write!(out, "Block {}: ", i);
+        push_hex(&mut out, &block, " ");
+        out.push('\n');
+    }
+    out.push_str("System OTP Block: ");
+    push_hex(&mut out, &data.system.unwrap_or([0xFF; 4]), " ");
+    out.push('\n');
+    out
+}
+
+pub fn import(text: &str) -> Result<ChipData, ImportError> {
+    let mut data = ChipData::default();
+    let mut chip = None;
+    for (n, line) in text.lines().enumerate() {
+        let line_no = n + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(ImportError::Syntax(line_no));
+        };
+        match key.trim() {
+            "Device type" => {
+                if value.trim() != DEVICE_TYPE {
+                    return Err(ImportError::NotSt25tb);
+                }
+            }
+            "UID" => data.uid = uid_from_msb_first(&parse_hex(value, line_no)?),
+            "ST25TB Type" => {
+                chip = Some(type_from_name(value.trim()).ok_or(ImportError::Syntax(line_no))?);
+            }
+            "System OTP Block" => data.system = Some(parse_hex(value, line_no)?),
+            key => {
+                // Filetype, Version and anything newer firmware adds
+                let Some(index) = key.strip_prefix("Block ") else {
+                    continue;
+                };
+                let i: usize = index.parse().map_err(|_| ImportError::Syntax(line_no))?;
+                if i >= data.blocks.len() {
+                    return Err(ImportError::BadBlockCount(i + 1));
+                }
+                data.blocks[i] = parse_hex(value, line_no)?;
+                data.block_count = data.block_count.max(i + 1);
+            }
+        }
+    }
+
+    if data.block_count == 0 {
+        return Err(ImportError::BadBlockCount(0));
+    }
+    if let Some(chip) = chip {
+        if chip != data.chip_type() {
+            log::warn!("Flipper type {} does not match the UID", chip.name());
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(uid5: u8, block_count: usize) -> ChipData {
+        let mut data = ChipData {
+            uid: [0x78, 0x56, 0x34, 0x12, 0x00, uid5, 0x02, 0xD0],
+            block_count,
+            system: Some([0xFF, 0xFF, 0xFF, 0x7F]),
+            ..ChipData::default()
+        };
+        for (i, block) in data.blocks[..block_count].iter_mut().enumerate() {
+            *block = [i as u8, 0xA5, 0x5A, !(i as u8)];
+        }
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = dump(0x1C, 128);
+        let text = export(&data);
+        assert!(text.starts_with(
+            "Filetype: Flipper NFC device\nVersion: 4\nDevice type: ST25TB\n\
+             # UID is common for all formats\nUID: D0 02 1C 00 12 34 56 78\n\
+             # ST25TB specific data\nST25TB Type: ST25TB04K\nBlock 0: 00 A5 5A FF\n"
+        ));
+        assert!(text.ends_with("Block 127: 7F A5 5A 80\nSystem OTP Block: FF FF FF 7F\n"));
+        let back = import(&text).unwrap();
+        assert_eq!(back.uid, data.uid);
+        assert_eq!(back.block_count, 128);
+        assert_eq!(back.blocks, data.blocks);
+        assert_eq!(back.system, data.system);
+
+        // SRI parts go by their ST25TBX names
+        let sri = dump(0x0C, 128);
+        let text = export(&sri);
+        assert!(text.contains("ST25TB Type: ST25TBX4K\n"));
+        assert_eq!(import(&text).unwrap().uid, sri.uid);
+    }
+
+    #[test]
+    fn lists_every_block_of_the_chip() {
+        // Short dumps are padded to the chip's size
+        let short = dump(0x1C, 100);
+        let back = import(&export(&short)).unwrap();
+        assert_eq!(back.block_count, 128);
+        assert_eq!(back.blocks[..100], short.blocks[..100]);
+        assert!(back.blocks[100..128].iter().all(|b| *b == [0xFF; 4]));
+
+        // A 128-block dump whose UID reads as a 512-bit part loses nothing
+        let long = dump(0x30, 128);
+        let text = export(&long);
+        assert!(text.contains("ST25TB Type: ST25TB512-AT\n"));
+        let back = import(&text).unwrap();
+        assert_eq!(back.block_count, 128);
+        assert_eq!(back.blocks, long.blocks);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let text = export(&dump(0x1C, 16));
+        assert_eq!(
+            import(&text.replace("Device type: ST25TB", "Device type: NTAG216")).err(),
+            Some(ImportError::NotSt25tb)
+        );
+        assert_eq!(
+            import(&text.replace("Block 3: ", "Block 3 ")).err(),
+            Some(ImportError::Syntax(11))
+        );
+        assert_eq!(
+            import(&text.replace("Block 3: 03 A5 5A FC", "Block 3: 03 A5 5A")).err(),
+            Some(ImportError::Syntax(11))
+        );
+        assert_eq!(
+            import(&text.replace("Block 3:", "Block 300:")).err(),
+            Some(ImportError::BadBlockCount(301))
+        );
+        assert_eq!(
+            import("Filetype: Flipper NFC device\nDevice type: ST25TB\n").err(),
+            Some(ImportError::BadBlockCount(0))
+        );
+    }
+}
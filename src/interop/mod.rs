@@ -0,0 +1,243 @@
+//! Dump interchange with Flipper Zero (`.nfc`) and Proxmark3 (`hf 14b dump`)
+//!
+//! Both tools print the UID MSB first, the reverse of `ChipData::uid`. Block
+//! data keeps the byte order the tag sends in every format.
+
+pub mod flipper;
+pub mod patch;
+pub mod proxmark;
+pub mod text;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::protocol::st25tb::ChipData;
+use crate::util::hex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Flipper,
+    ProxmarkBin,
+    ProxmarkEml,
+    ProxmarkJson,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [
+        Format::Flipper,
+        Format::ProxmarkBin,
+        Format::ProxmarkEml,
+        Format::ProxmarkJson,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Format::Flipper => "Flipper .nfc",
+            Format::ProxmarkBin => "Proxmark .bin",
+            Format::ProxmarkEml => "Proxmark .eml",
+            Format::ProxmarkJson => "Proxmark .json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Flipper => "nfc",
+            Format::ProxmarkBin => "bin",
+            Format::ProxmarkEml => "eml",
+            Format::ProxmarkJson => "json",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|f| f.extension().eq_ignore_ascii_case(ext))
+    }
+
+    /// Guesses the format from the contents. Anything that is not text is taken
+    /// as a Proxmark binary dump.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        let Ok(text) = core::str::from_utf8(bytes) else {
+            return Some(Format::ProxmarkBin);
+        };
+        let text = text.trim_start();
+        if text.starts_with(flipper::FILETYPE) {
+            Some(Format::Flipper)
+        } else if text.starts_with('{') {
+            Some(Format::ProxmarkJson)
+        } else if proxmark::is_eml(text) {
+            Some(Format::ProxmarkEml)
+        } else if bytes.len().is_multiple_of(4) && text.contains('\0') {
+            Some(Format::ProxmarkBin)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportError {
+    UnknownFormat,
+    /// Malformed input at this line (1-based; 0 for binary files).
+    Syntax(usize),
+    /// Flipper file for another card type.
+    NotSt25tb,
+    BadBlockCount(usize),
+}
+
+pub fn export(data: &ChipData, format: Format) -> Vec<u8> {
+    match format {
+        Format::Flipper => flipper::export(data).into_bytes(),
+        Format::ProxmarkBin => proxmark::export_bin(data),
+        Format::ProxmarkEml => proxmark::export_eml(data).into_bytes(),
+        Format::ProxmarkJson => proxmark::export_json(data).into_bytes(),
+    }
+}
+
+/// Converts `bytes` in `format`, or the detected format if `None`. Proxmark
+/// `.bin` and `.eml` files carry no UID; `uid` (LSB first, see
+/// `proxmark::uid_from_filename`) fills it in, otherwise it stays zero.
+pub fn import(
+    bytes: &[u8],
+    format: Option<Format>,
+    uid: Option<[u8; 8]>,
+) -> Result<ChipData, ImportError> {
+    let format = format
+        .or_else(|| Format::detect(bytes))
+        .ok_or(ImportError::UnknownFormat)?;
+    let text = || core::str::from_utf8(bytes).map_err(|_| ImportError::Syntax(0));
+    let mut data = match format {
+        Format::Flipper => flipper::import(text()?)?,
+        Format::ProxmarkBin => proxmark::import_bin(bytes)?,
+        Format::ProxmarkEml => proxmark::import_eml(text()?)?,
+        Format::ProxmarkJson => proxmark::import_json(text()?)?,
+    };
+    if data.uid == [0; 8] {
+        if let Some(uid) = uid {
+            data.uid = uid;
+        }
+    }
+    data.apply_lock_register();
+    Ok(data)
+}
+
+/// Reverses a UID written MSB first into the order `ChipData` keeps it.
+pub fn uid_from_msb_first(msb: &[u8; 8]) -> [u8; 8] {
+    let mut uid = *msb;
+    uid.reverse();
+    uid
+}
+
+/// Fills `data` from a flat block list. A block past the end of a known chip
+/// size is the system block, as both tools append it there.
+fn set_blocks(data: &mut ChipData, blocks: &[[u8; 4]]) -> Result<(), ImportError> {
+    let mut count = blocks.len();
+    if matches!(count, 17 | 65 | 129) {
+        count -= 1;
+        data.system = Some(blocks[count]);
+    }
+    if count == 0 || count > data.blocks.len() {
+        return Err(ImportError::BadBlockCount(count));
+    }
+    data.blocks[..count].copy_from_slice(&blocks[..count]);
+    data.block_count = count;
+    Ok(())
+}
+
+/// Appends `bytes` as upper-case hex, `sep` between bytes.
+fn push_hex(out: &mut String, bytes: &[u8], sep: &str) {
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push_str(sep);
+        }
+        let _ = write!(out, "{:02X}", b);
+    }
+}
+
+/// Exactly `N` hex bytes, spaced or packed.
+fn parse_hex<const N: usize>(value: &str, line: usize) -> Result<[u8; N], ImportError> {
+    let mut out = [0u8; N];
+    match hex::parse_bytes(value.trim(), &mut out) {
+        Some(n) if n == N => Ok(out),
+        _ => Err(ImportError::Syntax(line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_blocks_finds_the_system_block() {
+        let blocks: Vec<[u8; 4]> = (0..129).map(|i| [i as u8; 4]).collect();
+        for (len, count, system) in [
+            (17, 16, Some([16; 4])),
+            (16, 16, None),
+            (65, 64, Some([64; 4])),
+            (129, 128, Some([128; 4])),
+            (100, 100, None),
+        ] {
+            let mut data = ChipData::default();
+            set_blocks(&mut data, &blocks[..len]).unwrap();
+            assert_eq!(data.block_count, count);
+            assert_eq!(data.system, system);
+            assert_eq!(data.blocks[..count], blocks[..count]);
+        }
+
+        let mut data = ChipData::default();
+        assert_eq!(
+            set_blocks(&mut data, &[]),
+            Err(ImportError::BadBlockCount(0))
+        );
+        let too_many = alloc::vec![[0; 4]; 257];
+        assert_eq!(
+            set_blocks(&mut data, &too_many),
+            Err(ImportError::BadBlockCount(257))
+        );
+    }
+
+    #[test]
+    fn detects_and_imports_each_format() {
+        let mut data = ChipData {
+            uid: [0x78, 0x56, 0x34, 0x12, 0x00, 0x1C, 0x02, 0xD0],
+            block_count: 128,
+            system: Some([0xFF, 0xFF, 0xFF, 0x7F]),
+            ..ChipData::default()
+        };
+        data.blocks[3] = [0x12, 0x00, 0x34, 0x00];
+        for format in Format::ALL {
+            let bytes = export(&data, format);
+            assert_eq!(Format::detect(&bytes), Some(format));
+            let back = import(&bytes, None, Some(data.uid)).unwrap();
+            assert_eq!(back.uid, data.uid);
+            assert_eq!(back.block_count, 128);
+            assert_eq!(back.blocks, data.blocks);
+            assert_eq!(back.system, data.system);
+        }
+        assert_eq!(Format::detect(b"hello"), None);
+        assert_eq!(
+            import(b"hello", None, None).err(),
+            Some(ImportError::UnknownFormat)
+        );
+        assert_eq!(
+            import(&[0xFF, 0xFE, 0x00], Some(Format::ProxmarkEml), None).err(),
+            Some(ImportError::Syntax(0))
+        );
+    }
+
+    #[test]
+    fn parses_spaced_and_packed_hex() {
+        assert_eq!(
+            parse_hex::<4>(" 12 AB cd 00 ", 3),
+            Ok([0x12, 0xAB, 0xCD, 0x00])
+        );
+        assert_eq!(parse_hex::<4>("12ABCD00", 3), Ok([0x12, 0xAB, 0xCD, 0x00]));
+        assert_eq!(parse_hex::<4>("12ABCD", 3), Err(ImportError::Syntax(3)));
+        assert_eq!(parse_hex::<4>("12ABCD0011", 3), Err(ImportError::Syntax(3)));
+        let mut out = String::new();
+        push_hex(&mut out, &[0x0A, 0xB0], " ");
+        assert_eq!(out, "0A B0");
+    }
+}
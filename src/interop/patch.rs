@@ -0,0 +1,425 @@
+//! Edit patches: block changes recorded on one dump and replayed on others
+//!
+//! Only the bytes that were edited are part of a change, so a patch applies to
+//! cartridges whose other bytes in the same block differ (serial numbers,
+//! checksums). As text, one change per line in the style of `diff`, with `..`
+//! for bytes the change leaves alone and the profile field it touched, if any:
+//!
+//! ```text
+//! # 2 changes
+//! B021: 05 36 .. .. -> 40 42 .. ..  level
+//! B022: .. .. 00 00 -> .. .. 00 01
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::profile::{FieldKind, Profile};
+use crate::protocol::st25tb::{ChipData, FLAG_UNREADABLE};
+use crate::util::hex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub block: usize,
+    /// Bit `i` set when byte `i` is part of the change.
+    pub mask: u8,
+    pub old: [u8; 4],
+    pub new: [u8; 4],
+    /// Profile field the change was made in, for the reader.
+    pub field: Option<String>,
+}
+
+impl Change {
+    fn matches(&self, block: &[u8; 4], value: &[u8; 4]) -> bool {
+        (0..4).all(|i| self.mask & 1 << i == 0 || block[i] == value[i])
+    }
+}
+
+/// A change the target dump cannot take: its bytes hold neither the old nor
+/// the new value, or the block is missing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub change: Change,
+    pub found: Option<[u8; 4]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    /// 1-based line that is not a change.
+    Line(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    /// In the order the blocks were first edited.
+    pub changes: Vec<Change>,
+}
+
+impl Patch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Notes that `byte` of `block` went from `old` to `new`. Editing a byte
+    /// back to where the patch started drops it again.
+    pub fn record(&mut self, block: usize, byte: usize, old: u8, new: u8, field: Option<&str>) {
+        let index = match self.changes.iter().position(|c| c.block == block) {
+            Some(index) => index,
+            None => {
+                self.changes.push(Change {
+                    block,
+                    mask: 0,
+                    old: [0; 4],
+                    new: [0; 4],
+                    field: None,
+                });
+                self.changes.len() - 1
+            }
+        };
+        let change = &mut self.changes[index];
+        if change.mask & 1 << byte == 0 {
+            change.mask |= 1 << byte;
+            change.old[byte] = old;
+        }
+        change.new[byte] = new;
+        if change.new[byte] == change.old[byte] {
+            change.mask &= !(1 << byte);
+        }
+        if change.field.is_none() {
+            change.field = field.map(String::from);
+        }
+        if change.mask == 0 {
+            self.changes.remove(index);
+        }
+    }
+
+    /// The changes that turn `old` into `new`.
+    pub fn between(old: &ChipData, new: &ChipData, profile: Option<&Profile>) -> Self {
+        let count = old.block_count.min(new.block_count);
+        let mut patch = Self::new();
+        patch.record_blocks(&old.blocks[..count], &new.blocks[..count], profile);
+        patch
+    }
+
+    /// Records every byte that differs between `old` and `new`, leaving out
+    /// checksum fields of `profile` since those are recomputed wherever the
+    /// patch is applied.
+    pub fn record_blocks(&mut self, old: &[[u8; 4]], new: &[[u8; 4]], profile: Option<&Profile>) {
+        for (block, (a, b)) in old.iter().zip(new).enumerate() {
+            for byte in (0..4).filter(|&i| a[i] != b[i]) {
+                let field = profile.and_then(|p| p.field_at(block, byte));
+                if field.is_some_and(|f| matches!(f.kind, FieldKind::Checksum { .. })) {
+                    continue;
+                }
+                self.record(
+                    block,
+                    byte,
+                    a[byte],
+                    b[byte],
+                    field.map(|f| f.name.as_str()),
+                );
+            }
+        }
+    }
+
+    pub fn conflicts(&self, data: &ChipData) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for change in &self.changes {
+            let found = (change.block < data.block_count
+                && data.flags[change.block] & FLAG_UNREADABLE == 0)
+                .then(|| data.blocks[change.block]);
+            let fits = found.is_some_and(|b| {
+                change.matches(&b, &change.old) || change.matches(&b, &change.new)
+            });
+            if !fits {
+                conflicts.push(Conflict {
+                    change: change.clone(),
+                    found,
+                });
+            }
+        }
+        conflicts
+    }
+
+    /// Writes the new bytes when no change conflicts, or with `force` into
+    /// every block that exists. Returns the number of blocks that changed;
+    /// ones already patched count as done.
+    pub fn apply(&self, data: &mut ChipData, force: bool) -> Result<usize, Vec<Conflict>> {
+        let conflicts = self.conflicts(data);
+        if !force && !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+        let mut changed = 0;
+        for change in self.changes.iter().filter(|c| c.block < data.block_count) {
+            let block = &mut data.blocks[change.block];
+            if change.matches(block, &change.new) {
+                continue;
+            }
+            for i in (0..4).filter(|i| change.mask & 1 << i != 0) {
+                block[i] = change.new[i];
+            }
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# {} changes", self.changes.len());
+        for change in &self.changes {
+            let _ = write!(out, "B{:03}: ", change.block);
+            push_masked(&mut out, &change.old, change.mask);
+            out.push_str(" -> ");
+            push_masked(&mut out, &change.new, change.mask);
+            if let Some(field) = &change.field {
+                let _ = write!(out, "  {}", field);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut patch = Self::new();
+        for (idx, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let change = parse_change(line).ok_or(ParseError::Line(idx + 1))?;
+            if patch.changes.iter().any(|c| c.block == change.block) {
+                return Err(ParseError::Line(idx + 1));
+            }
+            patch.changes.push(change);
+        }
+        Ok(patch)
+    }
+}
+
+impl Conflict {
+    /// `B021: want 05 36 .. .. found 07 36 00 00  level`
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(out, "B{:03}: want ", self.change.block);
+        push_masked(&mut out, &self.change.old, self.change.mask);
+        out.push_str(" found ");
+        match self.found {
+            Some(found) => push_masked(&mut out, &found, 0x0F),
+            None => out.push_str("-- -- -- --"),
+        }
+        if let Some(field) = &self.change.field {
+            let _ = write!(out, "  {}", field);
+        }
+        out
+    }
+}
+
+fn push_masked(out: &mut String, bytes: &[u8; 4], mask: u8) {
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        if mask & 1 << i != 0 {
+            let _ = write!(out, "{:02X}", b);
+        } else {
+            out.push_str("..");
+        }
+    }
+}
+
+fn parse_change(line: &str) -> Option<Change> {
+    let (block, rest) = line.split_once(':')?;
+    let block: usize = block.trim().strip_prefix(['B', 'b'])?.parse().ok()?;
+    if block > 255 {
+        return None;
+    }
+    let mut tokens = rest.split_whitespace();
+    let (old_mask, old) = parse_masked(&mut tokens)?;
+    if tokens.next()? != "->" {
+        return None;
+    }
+    let (mask, new) = parse_masked(&mut tokens)?;
+    if mask == 0 || mask != old_mask {
+        return None;
+    }
+    let rest: Vec<&str> = tokens.collect();
+    let field = (!rest.is_empty()).then(|| rest.join(" "));
+    Some(Change {
+        block,
+        mask,
+        old,
+        new,
+        field,
+    })
+}
+
+fn parse_masked<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<(u8, [u8; 4])> {
+    let mut mask = 0;
+    let mut bytes = [0u8; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let token = tokens.next()?.as_bytes();
+        if token == b".." {
+            continue;
+        }
+        if token.len() != 2 {
+            return None;
+        }
+        *byte = hex::byte(token[0], token[1])?;
+        mask |= 1 << i;
+    }
+    Some((mask, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::parser;
+
+    fn dump(serial: u8) -> ChipData {
+        let mut data = ChipData {
+            block_count: 32,
+            ..ChipData::default()
+        };
+        data.blocks[16] = [serial, serial, serial, serial];
+        data.blocks[21] = [0x05, 0x36, serial, 0x00];
+        data.blocks[22] = [0x2E, 0x4A, 0x00, 0x00];
+        data
+    }
+
+    fn edited() -> Patch {
+        let mut patch = Patch::new();
+        patch.record(21, 0, 0x05, 0x06, Some("level"));
+        patch.record(21, 1, 0x36, 0x42, None);
+        patch.record(21, 0, 0x06, 0x40, None);
+        patch.record(22, 3, 0x00, 0x01, None);
+        patch
+    }
+
+    #[test]
+    fn records_only_edited_bytes() {
+        let patch = edited();
+        assert_eq!(
+            patch.changes[0],
+            Change {
+                block: 21,
+                mask: 0b0011,
+                old: [0x05, 0x36, 0, 0],
+                new: [0x40, 0x42, 0, 0],
+                field: Some("level".into()),
+            }
+        );
+        assert_eq!(
+            patch.text(),
+            "# 2 changes\n\
+             B021: 05 36 .. .. -> 40 42 .. ..  level\n\
+             B022: .. .. .. 00 -> .. .. .. 01\n"
+        );
+        // Editing a byte back to where it started drops it
+        let mut patch = patch;
+        patch.record(22, 3, 0x01, 0x00, None);
+        patch.record(21, 1, 0x42, 0x36, None);
+        assert_eq!(patch.len(), 1);
+        assert_eq!(patch.changes[0].mask, 0b0001);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let patch = edited();
+        assert_eq!(Patch::parse(&patch.text()), Ok(patch));
+        let text = "b7: ff .. .. .. -> 00 .. .. ..  two words\n";
+        let parsed = Patch::parse(text).unwrap();
+        assert_eq!(parsed.changes[0].field.as_deref(), Some("two words"));
+        assert_eq!(Patch::parse(&parsed.text()), Ok(parsed));
+        assert_eq!(Patch::parse(""), Ok(Patch::new()));
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let line = |text| Patch::parse(text).err();
+        assert_eq!(
+            line("# x\nB021: 05 .. .. .. 40 .. .. ..\n"),
+            Some(ParseError::Line(2))
+        );
+        assert_eq!(
+            line("B021: 05 .. .. .. -> .. 40 .. ..\n"),
+            Some(ParseError::Line(1))
+        );
+        assert_eq!(
+            line("B021: .. .. .. .. -> .. .. .. ..\n"),
+            Some(ParseError::Line(1))
+        );
+        assert_eq!(
+            line("B256: 05 .. .. .. -> 40 .. .. ..\n"),
+            Some(ParseError::Line(1))
+        );
+        assert_eq!(
+            line("B021: 05 .. .. .. -> 40 .. .. ..\n\nB021: .. 36 .. .. -> .. 42 .. ..\n"),
+            Some(ParseError::Line(3))
+        );
+    }
+
+    #[test]
+    fn applies_to_other_dumps() {
+        let patch = edited();
+        let mut other = dump(0x77);
+        assert_eq!(patch.apply(&mut other, false), Ok(2));
+        assert_eq!(other.blocks[21], [0x40, 0x42, 0x77, 0x00]);
+        assert_eq!(other.blocks[22], [0x2E, 0x4A, 0x00, 0x01]);
+        assert_eq!(other.blocks[16], [0x77; 4]);
+        // Already patched blocks are left alone
+        assert_eq!(patch.apply(&mut other, false), Ok(0));
+    }
+
+    #[test]
+    fn conflicts_stop_apply_unless_forced() {
+        let patch = edited();
+        let mut other = dump(0x77);
+        other.blocks[21][1] = 0x99;
+        other.block_count = 22;
+        let conflicts = patch.apply(&mut other.clone(), false).unwrap_err();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(
+            conflicts[0].text(),
+            "B021: want 05 36 .. .. found 05 99 77 00  level"
+        );
+        assert_eq!(conflicts[1].found, None);
+        assert_eq!(
+            conflicts[1].text(),
+            "B022: want .. .. .. 00 found -- -- -- --"
+        );
+
+        assert_eq!(patch.apply(&mut other, true), Ok(1));
+        assert_eq!(other.blocks[21], [0x40, 0x42, 0x77, 0x00]);
+    }
+
+    #[test]
+    fn between_leaves_out_checksums() {
+        let profile = &parser::parse(
+            "[profile]\nname = T\n\
+             [field level]\nblock = 21\nsize = 2\ntype = dec\n\
+             [field sum]\nblock = 23\ntype = checksum\nalgorithm = sum8\ncovers = 21\n",
+        )
+        .unwrap()[0];
+        let old = dump(0x10);
+        let mut new = dump(0x10);
+        new.blocks[21][0] = 0x04;
+        profile.update_checksums(&mut new);
+        assert_ne!(new.blocks[23], old.blocks[23]);
+
+        let patch = Patch::between(&old, &new, Some(profile));
+        assert_eq!(
+            patch.text(),
+            "# 1 changes\nB021: 05 .. .. .. -> 04 .. .. ..  level\n"
+        );
+        assert_eq!(Patch::between(&old, &new, None).len(), 2);
+    }
+}
@@ -0,0 +1,317 @@
+//! Proxmark3 `hf 14b dump` files
+//!
+//! `.bin` is the raw blocks, `.eml` one block per line as packed hex, and the
+//! JSON file (`"FileType": "14b v2"`) a `blocks` object keyed by block number;
+//! older JSON dumps hold a single `raw` hex string instead. All three append
+//! the system block after the last block. Only the JSON file carries the UID;
+//! the Proxmark client also puts it in the file name (`hf-14b-<UID>-dump`).
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::{parse_hex, push_hex, set_blocks, uid_from_msb_first, ImportError};
+use crate::protocol::st25tb::ChipData;
+use crate::util::hex;
+
+const FILE_TYPE: &str = "14b v2";
+const FILENAME_PREFIX: &str = "hf-14b-";
+
+fn blocks_with_system(data: &ChipData) -> impl Iterator<Item = &[u8; 4]> {
+    data.blocks[..data.block_count]
+        .iter()
+        .chain(data.system.as_ref())
+}
+
+/// File name the Proxmark client would use, without extension.
+pub fn filename(data: &ChipData) -> String {
+    let mut name = String::from(FILENAME_PREFIX);
+    push_hex(&mut name, &data.uid_msb_first(), "");
+    name.push_str("-dump");
+    name
+}
+
+/// UID (LSB first) from a Proxmark dump file name.
+pub fn uid_from_filename(name: &str) -> Option<[u8; 8]> {
+    let start = name.find(FILENAME_PREFIX)? + FILENAME_PREFIX.len();
+    let digits = name.get(start..start + 16)?;
+    let mut uid = [0u8; 8];
+    match hex::parse_bytes(digits, &mut uid) {
+        Some(8) => Some(uid_from_msb_first(&uid)),
+        _ => None,
+    }
+}
+
+pub fn export_bin(data: &ChipData) -> Vec<u8> {
+    blocks_with_system(data).flatten().copied().collect()
+}
+
+pub fn export_eml(data: &ChipData) -> String {
+    let mut out = String::new();
+    for block in blocks_with_system(data) {
+        push_hex(&mut out, block, "");
+        out.push('\n');
+    }
+    out
+}
+
+pub fn export_json(data: &ChipData) -> String {
+    let mut out = String::new();
+    out.push_str("{\n  \"Created\": \"rfid-reader\",\n");
+    let _ = writeln!(out, "  \"FileType\": \"{}\",", FILE_TYPE);
+    out.push_str("  \"Card\": {\n    \"UID\": \"");
+    push_hex(&mut out, &data.uid_msb_first(), "");
+    out.push_str("\"\n  },\n  \"blocks\": {");
+    for (i, block) in blocks_with_system(data).enumerate() {
+        let _ = write!(out, "{}\n    \"{}\": \"", if i > 0 { "," } else { "" }, i);
+        push_hex(&mut out, block, "");
+        out.push('"');
+    }
+    out.push_str("\n  }\n}\n");
+    out
+}
+
+/// Every non-empty line is a packed 4-byte block.
+pub fn is_eml(text: &str) -> bool {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    lines.clone().next().is_some()
+        && lines.all(|l| l.len() == 8 && l.bytes().all(|c| hex::nibble(c).is_some()))
+}
+
+pub fn import_bin(bytes: &[u8]) -> Result<ChipData, ImportError> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(ImportError::Syntax(0));
+    }
+    let blocks: Vec<[u8; 4]> = bytes
+        .chunks_exact(4)
+        .map(|c| [c[0], c[1], c[2], c[3]])
+        .collect();
+    let mut data = ChipData::default();
+    set_blocks(&mut data, &blocks)?;
+    Ok(data)
+}
+
+pub fn import_eml(text: &str) -> Result<ChipData, ImportError> {
+    let mut blocks = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if !line.trim().is_empty() {
+            blocks.push(parse_hex(line, n + 1)?);
+        }
+    }
+    let mut data = ChipData::default();
+    set_blocks(&mut data, &blocks)?;
+    Ok(data)
+}
+
+pub fn import_json(text: &str) -> Result<ChipData, ImportError> {
+    let mut data = ChipData::default();
+    let mut blocks: Vec<Option<[u8; 4]>> = Vec::new();
+    let mut raw = None;
+    for (path, value, line) in json_strings(text)? {
+        if path == "Card.UID" {
+            data.uid = uid_from_msb_first(&parse_hex(&value, line)?);
+        } else if path == "raw" {
+            raw = Some((value, line));
+        } else if let Some(index) = path.strip_prefix("blocks.") {
+            let i: usize = index.parse().map_err(|_| ImportError::Syntax(line))?;
+            if i >= data.blocks.len() {
+                return Err(ImportError::BadBlockCount(i + 1));
+            }
+            if blocks.len() <= i {
+                blocks.resize(i + 1, None);
+            }
+            blocks[i] = Some(parse_hex(&value, line)?);
+        }
+    }
+
+    let blocks: Vec<[u8; 4]> = match raw {
+        Some((hex_str, line)) if blocks.is_empty() => {
+            let mut bytes = alloc::vec![0u8; hex_str.len() / 2];
+            match hex::parse_bytes(&hex_str, &mut bytes) {
+                Some(n) if n == bytes.len() && n % 4 == 0 => {}
+                _ => return Err(ImportError::Syntax(line)),
+            }
+            bytes
+                .chunks_exact(4)
+                .map(|c| [c[0], c[1], c[2], c[3]])
+                .collect()
+        }
+        _ => blocks
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b.ok_or(ImportError::BadBlockCount(i)))
+            .collect::<Result<_, _>>()?,
+    };
+    set_blocks(&mut data, &blocks)?;
+    Ok(data)
+}
+
+/// `(path, value, line)` for every string value in a JSON document, `path`
+/// joining the object keys with `.`. Just enough JSON for Proxmark dumps:
+/// escapes are kept verbatim and array elements are skipped.
+fn json_strings(text: &str) -> Result<Vec<(String, String, usize)>, ImportError> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut key: Option<String> = None;
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => line += 1,
+            b'{' | b'[' => path.push(key.take().unwrap_or_default()),
+            b'}' | b']' => {
+                path.pop().ok_or(ImportError::Syntax(line))?;
+            }
+            b'"' => {
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    if bytes.get(i) == Some(&b'\n') {
+                        return Err(ImportError::Syntax(line));
+                    }
+                    i += 1;
+                }
+                if i >= bytes.len() {
+                    return Err(ImportError::Syntax(line));
+                }
+                let s = String::from(&text[start..i]);
+                let is_key = bytes[i + 1..]
+                    .iter()
+                    .find(|c| !c.is_ascii_whitespace())
+                    .is_some_and(|&c| c == b':');
+                if is_key {
+                    key = Some(s);
+                } else if let Some(k) = key.take() {
+                    let mut full = String::new();
+                    for p in path.iter().filter(|p| !p.is_empty()) {
+                        full.push_str(p);
+                        full.push('.');
+                    }
+                    full.push_str(&k);
+                    out.push((full, s, line));
+                }
+            }
+            b':' | b',' | b' ' | b'\t' | b'\r' => {}
+            // numbers, true/false/null
+            _ => key = None,
+        }
+        i += 1;
+    }
+    if !path.is_empty() {
+        return Err(ImportError::Syntax(line));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(block_count: usize, system: bool) -> ChipData {
+        let mut data = ChipData {
+            uid: [0x78, 0x56, 0x34, 0x12, 0x00, 0x1C, 0x02, 0xD0],
+            block_count,
+            system: system.then_some([0xFF, 0xFF, 0xFF, 0x7F]),
+            ..ChipData::default()
+        };
+        for (i, block) in data.blocks[..block_count].iter_mut().enumerate() {
+            *block = [i as u8, 0xA5, 0x5A, !(i as u8)];
+        }
+        data
+    }
+
+    fn assert_same(back: &ChipData, data: &ChipData) {
+        assert_eq!(back.block_count, data.block_count);
+        assert_eq!(back.blocks, data.blocks);
+        assert_eq!(back.system, data.system);
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        // The system block is recognised after 16, 64 and 128 blocks only
+        for data in [
+            dump(128, true),
+            dump(16, true),
+            dump(128, false),
+            dump(100, false),
+        ] {
+            let bin = export_bin(&data);
+            let extra = data.system.is_some() as usize;
+            assert_eq!(bin.len(), (data.block_count + extra) * 4);
+            assert_same(&import_bin(&bin).unwrap(), &data);
+
+            let eml = export_eml(&data);
+            assert!(is_eml(&eml));
+            assert_same(&import_eml(&eml).unwrap(), &data);
+
+            let json = export_json(&data);
+            let back = import_json(&json).unwrap();
+            assert_same(&back, &data);
+            assert_eq!(back.uid, data.uid);
+        }
+        let eml = export_eml(&dump(16, true));
+        assert!(eml.starts_with("00A55AFF\n01A55AFE\n"));
+        assert!(eml.ends_with("0FA55AF0\nFFFFFF7F\n"));
+    }
+
+    #[test]
+    fn reads_older_json_and_file_names() {
+        let json = "{\"Card\": {\"UID\": \"D0021C0012345678\"},\n\"raw\": \"00A55AFF01A55AFE\"}";
+        let data = import_json(json).unwrap();
+        assert_eq!(data.uid, [0x78, 0x56, 0x34, 0x12, 0x00, 0x1C, 0x02, 0xD0]);
+        assert_eq!(data.block_count, 2);
+        assert_eq!(data.blocks[1], [0x01, 0xA5, 0x5A, 0xFE]);
+
+        let name = filename(&data);
+        assert_eq!(name, "hf-14b-D0021C0012345678-dump");
+        assert_eq!(
+            uid_from_filename(&alloc::format!("/sd/{}.bin", name)),
+            Some(data.uid)
+        );
+        assert_eq!(uid_from_filename("hf-14b-D0021C00-dump.bin"), None);
+        assert_eq!(uid_from_filename("dump.bin"), None);
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        assert_eq!(import_bin(&[0; 6]).err(), Some(ImportError::Syntax(0)));
+        assert_eq!(import_bin(&[]).err(), Some(ImportError::BadBlockCount(0)));
+        assert_eq!(
+            import_bin(&[0; 258 * 4]).err(),
+            Some(ImportError::BadBlockCount(258))
+        );
+        assert_eq!(
+            import_eml("00A55AFF\n01A55A\n").err(),
+            Some(ImportError::Syntax(2))
+        );
+        assert!(!is_eml("00A55AFF\nB001: 01 A5 5A FE\n"));
+        assert!(!is_eml("\n"));
+
+        let json = export_json(&dump(16, false));
+        // A missing block, one past the largest dump and broken JSON
+        assert_eq!(
+            import_json(&json.replace("\"3\":", "\"20\":")).err(),
+            Some(ImportError::BadBlockCount(3))
+        );
+        assert_eq!(
+            import_json(&json.replace("\"3\":", "\"256\":")).err(),
+            Some(ImportError::BadBlockCount(257))
+        );
+        assert_eq!(
+            import_json(&json.replace("\"3\":", "\"3x\":")).err(),
+            Some(ImportError::Syntax(11))
+        );
+        assert_eq!(
+            import_json(json.trim_end().trim_end_matches('}')).err(),
+            Some(ImportError::Syntax(25))
+        );
+        assert_eq!(
+            import_json("{\"raw\": \"00A55A\"}").err(),
+            Some(ImportError::Syntax(1))
+        );
+    }
+}
@@ -0,0 +1,509 @@
+//! Line-by-line import of dumps pasted over serial, and the text "Dump Serial"
+//! sends
+//!
+//! Accepts, mixed freely:
+//! - this device's "Dump Serial" text, with or without a log prefix
+//!   (`INFO - B000: 0F FF FF FF`, `UID: [78, 56, ..]`, `Blocks: 128`)
+//! - indexed rows: `B12:`, `Block 12:`, `12:`, `0x0C:`, Proxmark's
+//!   `12/0x0C | 0F FF FF FF | ....` table; index 255 is the system block
+//! - plain rows of four hex bytes (`0F FF FF FF` or `0FFFFFFF`), which fill the
+//!   block after the previous row
+//! - a `UID:` line, LSB first in brackets as this device prints it, otherwise
+//!   MSB first as other tools print it
+//!
+//! Text starting with `Filetype:` (Flipper) or `{` (Proxmark JSON) is collected
+//! and converted as a whole by `interop::import` when the paste ends.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::{parse_hex, set_blocks, uid_from_msb_first, ImportError};
+use crate::protocol::st25tb::{ChipData, FLAG_UNREADABLE};
+use crate::util::hex;
+
+/// Longest line accepted; longer ones are reported, not truncated.
+pub const LINE_MAX: usize = 256;
+/// Largest Flipper or JSON file kept for conversion.
+const FILE_MAX: usize = 12 * 1024;
+const SYSTEM_BLOCK: usize = 255;
+
+const LOG_PREFIXES: [&str; 9] = [
+    "INFO - ", "WARN - ", "ERROR - ", "DEBUG - ", "TRACE - ", "[=]", "[+]", "[#]", "[!]",
+];
+
+/// What a line contributed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Line {
+    Block(usize),
+    System,
+    Uid,
+    BlockCount(usize),
+    /// Blank, a marker or other informational text.
+    Skipped,
+    /// Collected for whole-file conversion.
+    File,
+    End,
+    Error(LineError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineError {
+    TooLong,
+    NotText,
+    /// Block index is not a number below 256.
+    BadIndex,
+    /// Block data is not exactly four hex bytes.
+    BadData,
+    BadUid,
+    Unrecognized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Rows,
+    /// Flipper or JSON text, converted on `finish`.
+    File,
+}
+
+pub struct TextImport {
+    data: ChipData,
+    mode: Option<Mode>,
+    line: Vec<u8>,
+    overflow: bool,
+    after_cr: bool,
+    file: String,
+    /// 1-based number of the last completed line.
+    line_no: usize,
+    /// Block that a plain row fills.
+    next_block: usize,
+    loaded: [bool; 256],
+    indexed: bool,
+    declared_count: Option<usize>,
+    pub blocks: usize,
+    pub errors: usize,
+}
+
+impl Default for TextImport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextImport {
+    pub fn new() -> Self {
+        Self {
+            data: ChipData::default(),
+            mode: None,
+            line: Vec::new(),
+            overflow: false,
+            after_cr: false,
+            file: String::new(),
+            line_no: 0,
+            next_block: 0,
+            loaded: [false; 256],
+            indexed: false,
+            declared_count: None,
+            blocks: 0,
+            errors: 0,
+        }
+    }
+
+    /// Feeds one received byte; returns the line number and outcome at each
+    /// line end.
+    pub fn push(&mut self, byte: u8) -> Option<(usize, Line)> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let result = if core::mem::take(&mut self.overflow) {
+                    Line::Error(LineError::TooLong)
+                } else {
+                    match core::str::from_utf8(&line) {
+                        Ok(text) => self.feed_line(text),
+                        Err(_) => Line::Error(LineError::NotText),
+                    }
+                };
+                self.line_no += 1;
+                if let Line::Error(_) = result {
+                    self.errors += 1;
+                }
+                Some((self.line_no, result))
+            }
+            _ if self.line.len() >= LINE_MAX => {
+                self.overflow = true;
+                None
+            }
+            _ => {
+                self.line.push(byte);
+                None
+            }
+        }
+    }
+
+    /// Parses one complete line (without line ending).
+    pub fn feed_line(&mut self, raw: &str) -> Line {
+        let text = strip_prefix(raw.trim());
+        if text.eq_ignore_ascii_case("END") {
+            return Line::End;
+        }
+
+        if self.mode.is_none() && !text.is_empty() {
+            if text.starts_with("Filetype:") || text.starts_with('{') {
+                self.mode = Some(Mode::File);
+            } else if !is_marker(text) {
+                self.mode = Some(Mode::Rows);
+            }
+        }
+        if self.mode == Some(Mode::File) {
+            if self.file.len() + raw.len() >= FILE_MAX {
+                return Line::Error(LineError::TooLong);
+            }
+            self.file.push_str(raw);
+            self.file.push('\n');
+            return Line::File;
+        }
+
+        if text.is_empty() || is_marker(text) {
+            return Line::Skipped;
+        }
+        match text.find([':', '|']) {
+            Some(sep) => self.keyed_line(text[..sep].trim(), text[sep + 1..].trim()),
+            None => match parse_hex::<4>(text, 0) {
+                Ok(block) => {
+                    let index = self.next_block;
+                    self.set_block(index, block)
+                }
+                Err(_) => Line::Error(LineError::Unrecognized),
+            },
+        }
+    }
+
+    fn keyed_line(&mut self, key: &str, value: &str) -> Line {
+        if key.eq_ignore_ascii_case("UID") {
+            return match parse_uid(value) {
+                Some(uid) => {
+                    self.data.uid = uid;
+                    Line::Uid
+                }
+                None => Line::Error(LineError::BadUid),
+            };
+        }
+        if key.eq_ignore_ascii_case("Blocks") {
+            return match value.parse::<usize>() {
+                Ok(count) if count <= SYSTEM_BLOCK => {
+                    self.declared_count = Some(count);
+                    Line::BlockCount(count)
+                }
+                _ => Line::Error(LineError::BadIndex),
+            };
+        }
+
+        let Some(index) = block_index(key) else {
+            // "Chip type: ...", table headers and the like
+            return if key.bytes().any(|c| c.is_ascii_alphabetic()) {
+                Line::Skipped
+            } else {
+                Line::Error(LineError::BadIndex)
+            };
+        };
+        let Some(index) = index else {
+            return Line::Error(LineError::BadIndex);
+        };
+        // Proxmark tables follow the data with `| lock | ascii`
+        let value = value.split('|').next().unwrap_or("");
+        match parse_hex::<4>(value, 0) {
+            Ok(block) => {
+                self.indexed = true;
+                self.set_block(index, block)
+            }
+            Err(_) => Line::Error(LineError::BadData),
+        }
+    }
+
+    fn set_block(&mut self, index: usize, block: [u8; 4]) -> Line {
+        if index == SYSTEM_BLOCK {
+            self.data.system = Some(block);
+            return Line::System;
+        }
+        if index > SYSTEM_BLOCK {
+            return Line::Error(LineError::BadIndex);
+        }
+        self.data.blocks[index] = block;
+        if !self.loaded[index] {
+            self.loaded[index] = true;
+            self.blocks += 1;
+        }
+        self.next_block = index + 1;
+        Line::Block(index)
+    }
+
+    /// A block received so far; index 255 is the system block.
+    pub fn block(&self, index: usize) -> Option<[u8; 4]> {
+        if index == SYSTEM_BLOCK {
+            return self.data.system;
+        }
+        (*self.loaded.get(index)?).then(|| self.data.blocks[index])
+    }
+
+    /// Builds the dump. Blocks missing below the highest one received (or the
+    /// `Blocks:` count) are marked unreadable.
+    pub fn finish(mut self) -> Result<ChipData, ImportError> {
+        if self.mode == Some(Mode::File) {
+            return super::import(self.file.as_bytes(), None, None);
+        }
+
+        let highest = self.loaded.iter().rposition(|&l| l).map_or(0, |i| i + 1);
+        if highest == 0 {
+            return Err(ImportError::BadBlockCount(0));
+        }
+        // A .eml paste: plain rows only, system block last
+        if !self.indexed && self.data.system.is_none() && self.blocks == highest {
+            let blocks: Vec<[u8; 4]> = self.data.blocks[..highest].to_vec();
+            set_blocks(&mut self.data, &blocks)?;
+        } else {
+            self.data.block_count = highest.max(self.declared_count.unwrap_or(0));
+        }
+        for i in 0..self.data.block_count {
+            if !self.loaded[i] {
+                self.data.blocks[i] = [0xFF; 4];
+                self.data.flags[i] |= FLAG_UNREADABLE;
+            }
+        }
+        self.data.apply_lock_register();
+        Ok(self.data)
+    }
+}
+
+/// "Dump Serial" text, between its start and end markers.
+pub fn export(data: &ChipData) -> String {
+    let mut out = String::from("=== RFID DUMP START ===\n");
+    let _ = writeln!(out, "UID: {:02X?}", data.uid);
+    let _ = writeln!(out, "Blocks: {}", data.block_count);
+    out.push_str("--- HEX DATA ---\n");
+    let blocks = data.blocks[..data.block_count].iter().enumerate();
+    for (i, b) in blocks.chain(data.system.iter().map(|s| (SYSTEM_BLOCK, s))) {
+        let _ = writeln!(
+            out,
+            "B{:03}: {:02X} {:02X} {:02X} {:02X}",
+            i, b[0], b[1], b[2], b[3]
+        );
+    }
+    out.push_str("=== RFID DUMP END ===\n");
+    out
+}
+
+fn strip_prefix(line: &str) -> &str {
+    for prefix in LOG_PREFIXES {
+        if let Some(rest) = line.strip_prefix(prefix) {
+            return rest.trim();
+        }
+    }
+    line
+}
+
+/// `=== RFID DUMP START ===`, `--- HEX DATA ---`, table rules.
+fn is_marker(text: &str) -> bool {
+    text.starts_with("===") || text.starts_with("---")
+}
+
+/// `None` if `key` is not shaped like a block index, `Some(None)` if it is but
+/// does not parse. Accepts `B12`, `Block 12`, `blk 12`, `12`, `0x0C`, `12/0x0C`.
+fn block_index(key: &str) -> Option<Option<usize>> {
+    let lower = key.to_ascii_lowercase();
+    let number = ["block", "blk", "b"]
+        .iter()
+        .find_map(|p| lower.strip_prefix(p))
+        .unwrap_or(&lower)
+        .trim();
+    let number = number.split('/').next().unwrap_or("").trim();
+    if !number.bytes().next()?.is_ascii_digit() {
+        return None;
+    }
+    let parsed = match number.strip_prefix("0x") {
+        Some(h) => usize::from_str_radix(h, 16).ok(),
+        None => number.parse().ok(),
+    };
+    Some(parsed.filter(|&i| i <= SYSTEM_BLOCK))
+}
+
+/// `[78, 56, 34, 12, 33, 1C, 02, D0]` (LSB first, Debug output) or
+/// `D0 02 1C 33 12 34 56 78` / `D0021C3312345678` (MSB first).
+fn parse_uid(value: &str) -> Option<[u8; 8]> {
+    let mut uid = [0u8; 8];
+    if let Some(list) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        let spaced: String = list
+            .chars()
+            .map(|c| if c == ',' { ' ' } else { c })
+            .collect();
+        (hex::parse_bytes(&spaced, &mut uid)? == 8).then_some(uid)
+    } else {
+        (hex::parse_bytes(value, &mut uid)? == 8).then(|| uid_from_msb_first(&uid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: [u8; 8] = [0x78, 0x56, 0x34, 0x12, 0x00, 0x1C, 0x02, 0xD0];
+
+    /// Feeds `bytes` as received over serial.
+    fn receive(bytes: &[u8]) -> (Vec<(usize, Line)>, TextImport) {
+        let mut import = TextImport::new();
+        let lines = bytes.iter().filter_map(|&b| import.push(b)).collect();
+        (lines, import)
+    }
+
+    #[test]
+    fn reads_its_own_dump_text() {
+        let mut data = ChipData {
+            uid: UID,
+            block_count: 16,
+            system: Some([0xFF, 0xFF, 0xFF, 0x7F]),
+            ..ChipData::default()
+        };
+        for (i, block) in data.blocks[..16].iter_mut().enumerate() {
+            *block = [i as u8, 0xA5, 0x0F, 0xF0];
+        }
+        let text = export(&data);
+        assert!(text.contains("UID: [78, 56, 34, 12, 00, 1C, 02, D0]\nBlocks: 16\n"));
+        assert!(text.contains("B015: 0F A5 0F F0\nB255: FF FF FF 7F\n"));
+
+        // As received over serial, with CRLF line ends and a log prefix
+        let serial = text.replace('\n', "\r\n").replace("B003", "INFO - B003");
+        let (lines, import) = receive(serial.as_bytes());
+        assert_eq!(lines.len(), text.lines().count());
+        assert_eq!(lines[1], (2, Line::Uid));
+        assert_eq!(lines[2], (3, Line::BlockCount(16)));
+        assert_eq!(lines[7], (8, Line::Block(3)));
+        assert_eq!(lines[20], (21, Line::System));
+        assert_eq!(lines[21], (22, Line::Skipped));
+        assert_eq!((import.blocks, import.errors), (16, 0));
+        assert_eq!(import.block(3), Some([3, 0xA5, 0x0F, 0xF0]));
+        assert_eq!(import.block(255), data.system);
+        let back = import.finish().unwrap();
+        assert_eq!(back.uid, UID);
+        assert_eq!(back.block_count, 16);
+        assert_eq!(back.blocks, data.blocks);
+        assert_eq!(back.system, data.system);
+    }
+
+    #[test]
+    fn reads_other_tools_rows() {
+        let mut import = TextImport::new();
+        assert_eq!(import.feed_line("UID: D0 02 1C 00 12 34 56 78"), Line::Uid);
+        assert_eq!(import.feed_line("Chip type: ST25TB04K"), Line::Skipped);
+        assert_eq!(
+            import.feed_line("[=] block# | data        | ascii"),
+            Line::Skipped
+        );
+        assert_eq!(
+            import.feed_line("[=]  0/0x00 | 01 02 03 04 | ...."),
+            Line::Block(0)
+        );
+        assert_eq!(import.feed_line("Block 1: 05060708"), Line::Block(1));
+        assert_eq!(import.feed_line("0x02: 09 0A 0B 0C"), Line::Block(2));
+        assert_eq!(import.feed_line("0D 0E 0F 10"), Line::Block(3));
+        assert_eq!(import.feed_line("blk 5: 11 12 13 14"), Line::Block(5));
+        let data = import.finish().unwrap();
+        assert_eq!(data.uid, UID);
+        assert_eq!(data.block_count, 6);
+        assert_eq!(data.blocks[2], [0x09, 0x0A, 0x0B, 0x0C]);
+        assert_eq!(data.blocks[3], [0x0D, 0x0E, 0x0F, 0x10]);
+        // Missing blocks are kept as unreadable
+        assert_eq!(data.blocks[4], [0xFF; 4]);
+        assert_ne!(data.flags[4] & FLAG_UNREADABLE, 0);
+        assert_eq!(data.flags[5] & FLAG_UNREADABLE, 0);
+    }
+
+    #[test]
+    fn declared_count_flags_missing_blocks() {
+        let mut import = TextImport::new();
+        import.feed_line("Blocks: 16");
+        import.feed_line("B000: 00 00 00 00");
+        let data = import.finish().unwrap();
+        assert_eq!(data.block_count, 16);
+        assert!((1..16).all(|i| data.flags[i] & FLAG_UNREADABLE != 0));
+    }
+
+    #[test]
+    fn eml_paste_ends_with_the_system_block() {
+        let mut import = TextImport::new();
+        for i in 0..17 {
+            assert_eq!(
+                import.feed_line(&alloc::format!("{:08X}", i)),
+                Line::Block(i)
+            );
+        }
+        let data = import.finish().unwrap();
+        assert_eq!(data.block_count, 16);
+        assert_eq!(data.system, Some([0, 0, 0, 16]));
+    }
+
+    #[test]
+    fn flipper_paste_is_converted_whole() {
+        let data = ChipData {
+            uid: UID,
+            block_count: 128,
+            ..ChipData::default()
+        };
+        let mut import = TextImport::new();
+        for line in crate::interop::flipper::export(&data).lines() {
+            assert_eq!(import.feed_line(line), Line::File);
+        }
+        assert_eq!(import.feed_line("END"), Line::End);
+        assert_eq!(import.finish().unwrap().uid, UID);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let mut import = TextImport::new();
+        assert_eq!(
+            import.feed_line("B300: 00 00 00 00"),
+            Line::Error(LineError::BadIndex)
+        );
+        assert_eq!(
+            import.feed_line("12x: 00 00 00 00"),
+            Line::Error(LineError::BadIndex)
+        );
+        assert_eq!(
+            import.feed_line("Blocks: 256"),
+            Line::Error(LineError::BadIndex)
+        );
+        assert_eq!(
+            import.feed_line("B001: 00 00 00"),
+            Line::Error(LineError::BadData)
+        );
+        assert_eq!(
+            import.feed_line("UID: [78, 56, 34]"),
+            Line::Error(LineError::BadUid)
+        );
+        assert_eq!(
+            import.feed_line("UID: D0021C00123456"),
+            Line::Error(LineError::BadUid)
+        );
+        assert_eq!(
+            import.feed_line("hello"),
+            Line::Error(LineError::Unrecognized)
+        );
+        assert_eq!(import.blocks, 0);
+        assert_eq!(import.finish().err(), Some(ImportError::BadBlockCount(0)));
+
+        // Over-long lines are dropped whole and the next one still counts
+        let mut bytes =
+            alloc::format!("B000: {}\n0F FF FF FF\n", "0".repeat(LINE_MAX)).into_bytes();
+        bytes.extend_from_slice(&[b'0', 0xFF, b'\n']);
+        let (lines, import) = receive(&bytes);
+        assert_eq!(
+            lines,
+            [
+                (1, Line::Error(LineError::TooLong)),
+                (2, Line::Block(0)),
+                (3, Line::Error(LineError::NotText)),
+            ]
+        );
+        assert_eq!((import.blocks, import.errors), (1, 2));
+    }
+}
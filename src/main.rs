@@ -3,10 +3,15 @@
 
 extern crate alloc;
 
+mod analysis;
 mod board;
 mod drivers;
+mod interop;
+mod profile;
 mod protocol;
+mod shell;
 mod ui;
+mod util;
 
 use esp_alloc as _;
 use esp_backtrace as _;
@@ -27,32 +32,380 @@ use esp_hal::{
 use log::info;
 
 use display_interface_spi::SPIInterface;
+use embedded_graphics::{draw_target::DrawTarget, pixelcolor::Rgb565};
 
 use core::cell::RefCell;
 use embedded_hal_bus::spi::{NoDelay, RefCellDevice};
+use embedded_sdmmc::SdCard;
+use esp_storage::FlashStorage;
 use mipidsi::{options::ColorInversion, Builder};
 
-use crate::board::pins;
+use crate::analysis::{checksum, Analysis, ByteClass, Diff};
+use crate::board::{flash, pins};
+use crate::drivers::storage::{
+    decode_dump, encode_dump, DumpStore, FixedTime, FlashStore, SdStorage, StorageError,
+    PROFILE_DIR,
+};
 use crate::drivers::{Audio, Pn532};
-use crate::protocol::st25tb::ChipData;
+use crate::interop::patch::{self, Patch};
+use crate::interop::{self, text, Format};
+use crate::profile::ProfileSet;
+use crate::protocol::host::{
+    self, Command as HostCommand, Frame, FrameReader, Info, Input as HostInput, Status,
+};
+use crate::protocol::st25tb::{ChipData, ChipType, LockError, FLAG_LOCKED};
 use crate::protocol::St25tb;
-use crate::ui::{ChipEditor, Display};
+use crate::shell::Shell;
+use crate::ui::editor::{ApplyError, RangeError};
+use crate::ui::library::{Command, Event, TextInput, DIGIT_CHARS, HEX_CHARS, TEXT_CHARS};
+use crate::ui::loader::{self, Outcome};
+use crate::ui::{ChipEditor, Compare, Display, Library, SerialLoad};
+use crate::util::quiet::Quiet;
+use crate::util::{clock, hex};
 
 static mut TX_DESCRIPTORS: [DmaDescriptor; 8] = [DmaDescriptor::EMPTY; 8];
 
-fn hex_char_to_nibble(c: u8) -> Option<u8> {
-    match c {
-        b'0'..=b'9' => Some(c - b'0'),
-        b'A'..=b'F' => Some(c - b'A' + 10),
-        b'a'..=b'f' => Some(c - b'a' + 10),
-        _ => None,
+/// Two-step confirmation for irreversible tag operations: `question`, then
+/// the same with "REALLY" before `action` is run on the tag.
+fn show_confirm<D>(display: &mut Display<D>, question: &str, warning: &str, action: &str, stage: u8)
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let mut line: heapless::String<32> = heapless::String::new();
+    if stage < 2 {
+        let (first, rest) = question.split_at(1);
+        let _ = core::fmt::write(
+            &mut line,
+            format_args!("{}{}?", first.to_ascii_uppercase(), rest),
+        );
+        display.show_lines(
+            &[line.as_str(), warning, "BTN:continue BAK:cancel"],
+            Some(1),
+        );
+    } else {
+        let _ = core::fmt::write(&mut line, format_args!("REALLY {}?", question));
+        let mut place: heapless::String<24> = heapless::String::new();
+        let _ = core::fmt::write(&mut place, format_args!("Place tag, BTN:{}", action));
+        display.show_lines(
+            &[
+                line.as_str(),
+                "Cannot be undone!",
+                place.as_str(),
+                "BAK:cancel",
+            ],
+            Some(1),
+        );
+    }
+}
+
+fn show_lock_confirm<D>(display: &mut Display<D>, first: usize, last: usize, stage: u8)
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let mut question: heapless::String<24> = heapless::String::new();
+    let _ = core::fmt::write(
+        &mut question,
+        format_args!("lock blocks {}-{}", first, last),
+    );
+    show_confirm(display, &question, "Locking is PERMANENT", "lock", stage);
+}
+
+/// "Apply Patch" sources: a serial paste, then the stored patches `names`.
+fn show_patch_sources<D>(display: &mut Display<D>, names: &[alloc::string::String], selected: usize)
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let mut items = alloc::vec!["Paste from serial"];
+    items.extend(names.iter().map(|n| n.as_str()));
+    display.show_list("Apply patch", &items, selected);
+}
+
+/// Writes `data` to the tag on the reader; returns the status to show.
+fn write_chip<I2C, IRQ, RST>(pn532: &mut Pn532<I2C, IRQ, RST>, data: &ChipData) -> &'static str
+where
+    I2C: embedded_hal::i2c::I2c,
+    IRQ: embedded_hal::digital::InputPin,
+    RST: embedded_hal::digital::OutputPin,
+{
+    match St25tb::new(pn532).write_full_chip(data) {
+        Ok(_) => {
+            info!("Chip written OK");
+            "Write OK!"
+        }
+        Err(e) => {
+            info!("Write error: {:?}", e);
+            "Write failed!"
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    esp_hal::time::Instant::now()
+        .duration_since_epoch()
+        .as_millis()
+}
+
+fn open_editor(data: ChipData, profiles: &ProfileSet) -> ChipEditor {
+    let mut editor = ChipEditor::new(data);
+    editor.profile = profiles.find_match(&editor.data).cloned();
+    match editor.profile {
+        Some(ref p) => {
+            info!("Profile: {}", p.name);
+            for field in p.bad_checksums(&editor.data) {
+                log::warn!("Checksum {} does not match", field.name);
+            }
+        }
+        None => info!("Profile: none matched"),
+    }
+    editor
+}
+
+/// Runs an `EDIT_ACTIONS` entry other than "Go to / find"; returns a line
+/// for the status screen when there is something to report.
+fn edit_action(ed: &mut ChipEditor, action: usize) -> Option<heapless::String<32>> {
+    let mut msg: heapless::String<32> = heapless::String::new();
+    let changed = match action {
+        1 | 2 => {
+            let done = if action == 1 { ed.undo() } else { ed.redo() };
+            if !done {
+                let _ = msg.push_str(if action == 1 {
+                    "Nothing to undo"
+                } else {
+                    "Nothing to redo"
+                });
+            }
+            Ok(0)
+        }
+        3 => ed.revert_block().map(|_| 0),
+        4 => {
+            let count = ed.modified_count();
+            ed.revert_all();
+            Ok(count)
+        }
+        5 => {
+            ed.toggle_mark();
+            Ok(0)
+        }
+        6 => {
+            let count = ed.copy();
+            let _ = core::fmt::write(&mut msg, format_args!("Copied {} blocks", count));
+            Ok(0)
+        }
+        7 => ed.paste(),
+        8 => ed.fill(&[[0xFF; 4]]),
+        9 => ed.fill_copied(),
+        10 => ed.swap().map(|_| 2),
+        _ => ed.clear_user_area(),
+    };
+    let _ = match changed {
+        Ok(0) => Ok(()),
+        Ok(count) => core::fmt::write(&mut msg, format_args!("{} blocks changed", count)),
+        Err(e) => range_error(&mut msg, e),
+    };
+    (!msg.is_empty()).then_some(msg)
+}
+
+/// Status line for an edit the editor refused.
+fn range_error(msg: &mut heapless::String<32>, error: RangeError) -> core::fmt::Result {
+    match error {
+        RangeError::Protected(block) => {
+            core::fmt::write(msg, format_args!("Block {} is protected", block))
+        }
+        RangeError::PastEnd => core::fmt::write(msg, format_args!("Paste runs past the end")),
+        RangeError::NothingCopied => core::fmt::write(msg, format_args!("Nothing copied")),
+        RangeError::NoMark => core::fmt::write(msg, format_args!("Mark a block first")),
+        RangeError::CountsUp(block) => {
+            core::fmt::write(msg, format_args!("Counter {} only counts down", block))
+        }
     }
 }
 
-fn parse_hex_byte(high: u8, low: u8) -> Option<u8> {
-    let h = hex_char_to_nibble(high)?;
-    let l = hex_char_to_nibble(low)?;
-    Some((h << 4) | l)
+/// Runs a `GOTO_ACTIONS` entry that needs no input; false when there is
+/// nothing in that direction.
+fn go_to(ed: &mut ChipEditor, action: usize) -> bool {
+    match action {
+        1 => ed.next_used(true),
+        2 => ed.next_used(false),
+        3 => ed.next_modified(true),
+        4 => ed.next_modified(false),
+        7 => ed.find_again(true),
+        _ => ed.find_again(false),
+    }
+}
+
+/// Runs the `GOTO_ACTIONS` entry `action` with the entered text; returns the
+/// reason when nothing was found.
+fn find_entered(ed: &mut ChipEditor, action: usize, text: &str) -> Option<&'static str> {
+    match action {
+        0 => match text.parse() {
+            Ok(block) if ed.jump_to(block) => None,
+            _ => Some("No such block"),
+        },
+        5 => {
+            let mut bytes = [0u8; FIND_MAX];
+            match hex::parse_bytes(text, &mut bytes) {
+                Some(0) => None,
+                Some(len) => (!ed.find(bytes[..len].to_vec())).then_some("Not found"),
+                None => Some("Odd number of digits"),
+            }
+        }
+        _ => (!text.is_empty() && !ed.find(text.as_bytes().to_vec())).then_some("Not found"),
+    }
+}
+
+/// Collects pasted lines until one reads `END`; `None` when Back cancels.
+fn read_paste(
+    usb_serial: &mut UsbSerialJtag<'_, esp_hal::Blocking>,
+    back_btn: &Input<'_>,
+    delay: &Delay,
+) -> Option<alloc::string::String> {
+    let mut text = alloc::string::String::new();
+    let mut line_buf: heapless::Vec<u8, 128> = heapless::Vec::new();
+    loop {
+        if let Ok(c) = usb_serial.read_byte() {
+            if c == b'\n' || c == b'\r' {
+                let line = core::str::from_utf8(&line_buf).unwrap_or("");
+                if line.trim().eq_ignore_ascii_case("END") {
+                    return Some(text);
+                }
+                text.push_str(line);
+                text.push('\n');
+                line_buf.clear();
+            } else if line_buf.len() < 128 {
+                let _ = line_buf.push(c);
+            }
+        }
+
+        if back_btn.is_low() {
+            info!("Load cancelled by user");
+            return None;
+        }
+
+        delay.delay_millis(1);
+    }
+}
+
+fn error_reply(req: &Frame, status: Status, error: impl core::fmt::Debug) -> Frame {
+    let mut msg: heapless::String<48> = heapless::String::new();
+    let _ = core::fmt::write(&mut msg, format_args!("{:?}", error));
+    req.reply(status, msg.as_bytes())
+}
+
+/// Handles one request from a host tool; see `protocol::host`.
+fn serve_host<I2C, IRQ, RST>(
+    req: &Frame,
+    pn532: &mut Pn532<I2C, IRQ, RST>,
+    editor: &mut Option<ChipEditor>,
+    profiles: &ProfileSet,
+    store: Option<&mut (dyn DumpStore + '_)>,
+    storage_kind: u8,
+) -> Frame
+where
+    I2C: embedded_hal::i2c::I2c,
+    IRQ: embedded_hal::digital::InputPin,
+    RST: embedded_hal::digital::OutputPin,
+{
+    let Some(command) = HostCommand::from_code(req.code) else {
+        return req.reply(Status::UnknownCommand, &[]);
+    };
+    let payload = req.payload.as_slice();
+    match command {
+        HostCommand::GetInfo => {
+            let info = Info {
+                protocol: host::PROTOCOL_VERSION,
+                storage: storage_kind,
+                dump_loaded: editor.is_some(),
+                pn532: pn532
+                    .get_firmware_version()
+                    .map_or([0; 3], |(ic, ver, rev)| [ic, ver, rev]),
+                firmware: env!("CARGO_PKG_VERSION").into(),
+            };
+            req.reply(Status::Ok, &info.encode())
+        }
+        HostCommand::ReadChip => match St25tb::new(pn532).read_full_chip() {
+            Ok(mut data) => {
+                info!("Host read, UID: {:02X?}", data.uid);
+                data.timestamp = clock::unix_time().unwrap_or(0);
+                let dump = encode_dump(&data);
+                *editor = Some(open_editor(data, profiles));
+                req.reply(Status::Ok, &dump)
+            }
+            Err(e) => error_reply(req, Status::Tag, e),
+        },
+        HostCommand::WriteChip => {
+            let decoded;
+            let data = if payload.is_empty() {
+                match editor.as_ref() {
+                    Some(ed) => &ed.data,
+                    None => return req.reply(Status::NoData, &[]),
+                }
+            } else {
+                match decode_dump(payload) {
+                    Ok(d) => {
+                        decoded = d;
+                        &decoded
+                    }
+                    Err(e) => return error_reply(req, Status::BadRequest, e),
+                }
+            };
+            match St25tb::new(pn532).write_full_chip(data) {
+                Ok(_) => req.reply(Status::Ok, &[]),
+                Err(e) => error_reply(req, Status::Tag, e),
+            }
+        }
+        HostCommand::GetDump if payload.is_empty() => match editor.as_ref() {
+            Some(ed) => req.reply(Status::Ok, &encode_dump(&ed.data)),
+            None => req.reply(Status::NoData, &[]),
+        },
+        HostCommand::PutDump => {
+            let Some((name, dump)) = host::decode_put(payload) else {
+                return req.reply(Status::BadRequest, &[]);
+            };
+            let data = match decode_dump(dump) {
+                Ok(d) => d,
+                Err(e) => return error_reply(req, Status::BadRequest, e),
+            };
+            if name.is_empty() {
+                *editor = Some(open_editor(data, profiles));
+                return req.reply(Status::Ok, &[]);
+            }
+            let Some(store) = store else {
+                return req.reply(Status::NoStorage, &[]);
+            };
+            match store.save_dump(name, &data) {
+                Ok(_) => req.reply(Status::Ok, &[]),
+                Err(e) => error_reply(req, Status::Storage, e),
+            }
+        }
+        HostCommand::GetDump | HostCommand::ListDumps | HostCommand::DeleteDump => {
+            let Some(store) = store else {
+                return req.reply(Status::NoStorage, &[]);
+            };
+            let Ok(name) = core::str::from_utf8(payload) else {
+                return req.reply(Status::BadRequest, &[]);
+            };
+            let result = match command {
+                HostCommand::GetDump => store.load_dump(name).map(|d| encode_dump(&d)),
+                HostCommand::ListDumps => store.list_info().map(|l| host::encode_list(&l)),
+                _ => store.delete_dump(name).map(|_| alloc::vec::Vec::new()),
+            };
+            match result {
+                Ok(out) => req.reply(Status::Ok, &out),
+                Err(e) => error_reply(req, Status::Storage, e),
+            }
+        }
+        HostCommand::Pn532 => match pn532.exchange(payload) {
+            Ok(response) => req.reply(Status::Ok, response),
+            Err(e) => error_reply(req, Status::Pn532, e),
+        },
+        HostCommand::SetTime => match <[u8; 4]>::try_from(payload) {
+            Ok(bytes) => {
+                clock::set_unix_time(u32::from_le_bytes(bytes));
+                req.reply(Status::Ok, &[])
+            }
+            Err(_) => req.reply(Status::BadRequest, &[]),
+        },
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -62,17 +415,86 @@ enum AppState {
     Viewing,
     Writing,
     Error,
+    Library,
+    /// Lock confirmation step (1 or 2) and the selected block's lock group,
+    /// first and last, as the tag on the reader lays it out.
+    LockConfirm(u8, usize, usize),
+    /// Write confirmation step (1 or 2) when the write counts counters down.
+    WriteConfirm(u8),
+    /// Picking the serial dump format (index into `DUMP_FORMATS`).
+    DumpFormat(usize),
+    /// Picking the patch to apply: 0 pastes one, then `patch_names`.
+    PatchSource(usize),
+    /// "Load Serial" receiving a paste (`serial_load`).
+    SerialLoad,
+    /// Library dump compared with the open one (`compare`).
+    Compare,
+    /// Viewer action list (index into `EDIT_ACTIONS`).
+    EditActions(usize),
+    /// Navigation list (index into `GOTO_ACTIONS`).
+    GoTo(usize),
+    /// Text entry for the `GOTO_ACTIONS` entry: block number or pattern.
+    FindInput(usize),
 }
 
-const MENU_ITEMS: [&str; 6] = [
+const MENU_ITEMS: [&str; 12] = [
     "Read Chip",
     "Write Chip",
     "Dump Serial",
     "Load Serial",
     "View Data",
+    "Load Profile",
+    "Lock Block",
+    "Save Dump",
+    "Library",
+    "Save Patch",
+    "Apply Patch",
     "Exit",
 ];
 
+/// Viewer actions, opened by pressing the encoder while holding Back.
+const EDIT_ACTIONS: [&str; 12] = [
+    "Go to / find",
+    "Undo",
+    "Redo",
+    "Revert block",
+    "Revert all",
+    "Mark range",
+    "Copy",
+    "Paste",
+    "Fill FF",
+    "Fill copied",
+    "Swap with mark",
+    "Clear user area",
+];
+
+const GOTO_ACTIONS: [&str; 9] = [
+    "Block number",
+    "Next used",
+    "Previous used",
+    "Next modified",
+    "Previous modified",
+    "Find hex",
+    "Find text",
+    "Find next",
+    "Find previous",
+];
+
+/// Longest search pattern, in bytes; its hex digits fill the input line.
+const FIND_MAX: usize = 12;
+
+/// Serial dump formats; `None` is the device's own `B000: ..` text.
+const DUMP_FORMATS: [Option<Format>; 4] = [
+    None,
+    Some(Format::Flipper),
+    Some(Format::ProxmarkEml),
+    Some(Format::ProxmarkJson),
+];
+const DUMP_FORMAT_LABELS: [&str; 4] = ["Text", "Flipper .nfc", "Proxmark .eml", "Proxmark .json"];
+
+/// Dumps held for the checksum search, each a `ChipData` on the heap.
+const CHECKSUM_DUMPS: usize = 4;
+
 const ENCODER_TABLE: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
 
 // Custom wake source for GPIO6 (back button)
@@ -160,16 +582,17 @@ fn main() -> ! {
 
     info!("Setting all SPI CS pins HIGH to prevent bus contention...");
     let display_cs = Output::new(peripherals.GPIO41, Level::High, OutputConfig::default());
-    let spi = Spi::new(
-        peripherals.SPI2,
-        esp_hal::spi::master::Config::default()
-            .with_frequency(Rate::from_mhz(40))
-            .with_mode(esp_hal::spi::Mode::_0),
-    )
-    .unwrap()
-    .with_sck(peripherals.GPIO11)
-    .with_mosi(peripherals.GPIO9)
-    .with_miso(peripherals.GPIO10);
+    let sd_cs = Output::new(peripherals.GPIO13, Level::High, OutputConfig::default());
+    // CC1101 radio shares the bus; keep it deselected so it never drives MISO
+    let _cc1101_cs = Output::new(peripherals.GPIO12, Level::High, OutputConfig::default());
+    let spi_config = esp_hal::spi::master::Config::default()
+        .with_frequency(Rate::from_mhz(40))
+        .with_mode(esp_hal::spi::Mode::_0);
+    let spi = Spi::new(peripherals.SPI2, spi_config)
+        .unwrap()
+        .with_sck(peripherals.GPIO11)
+        .with_mosi(peripherals.GPIO9)
+        .with_miso(peripherals.GPIO10);
 
     let spi_bus: &'static RefCell<Spi<'static, esp_hal::Blocking>> = {
         static mut SPI_BUS: Option<RefCell<Spi<'static, esp_hal::Blocking>>> = None;
@@ -203,6 +626,50 @@ fn main() -> ! {
         }
     };
 
+    info!("Init SD card...");
+    let sd_spi = RefCellDevice::new(spi_bus, sd_cs, Delay::new()).unwrap();
+    let sd_card = SdCard::new(sd_spi, Delay::new());
+    // Cards only accept the init sequence below 400 kHz. Afterwards the shared bus
+    // stays at 20 MHz, the fastest clock every card takes in default-speed mode.
+    let _ = spi_bus
+        .borrow_mut()
+        .apply_config(&spi_config.with_frequency(Rate::from_khz(400)));
+    let sd_present = match sd_card.num_bytes() {
+        Ok(bytes) => {
+            info!("SD card: {} MB", bytes / 1024 / 1024);
+            true
+        }
+        Err(e) => {
+            info!("No SD card: {:?}", e);
+            false
+        }
+    };
+    let _ = spi_bus
+        .borrow_mut()
+        .apply_config(&spi_config.with_frequency(Rate::from_mhz(20)));
+    let mut storage = SdStorage::new(sd_card, FixedTime);
+
+    info!("Mount flash dump store...");
+    let mut flash_store = match FlashStore::mount(
+        FlashStorage::new(peripherals.FLASH),
+        flash::DUMP_PARTITION_OFFSET,
+        flash::DUMP_PARTITION_SIZE,
+    ) {
+        Ok(store) => {
+            info!(
+                "Flash store: {} dumps, {}/{} bytes",
+                store.entries().len(),
+                store.used(),
+                store.capacity()
+            );
+            Some(store)
+        }
+        Err(e) => {
+            info!("Flash store unavailable: {:?}", e);
+            None
+        }
+    };
+
     info!("Init I2C at 100kHz...");
     let i2c_config = esp_hal::i2c::master::Config::default().with_frequency(Rate::from_khz(100));
     let mut i2c = I2c::new(peripherals.I2C0, i2c_config)
@@ -297,13 +764,53 @@ fn main() -> ! {
         InputConfig::default().with_pull(Pull::Up),
     );
 
+    let mut profiles = ProfileSet::builtin();
+    if sd_present {
+        for name in storage.list(PROFILE_DIR).unwrap_or_default() {
+            let Ok(bytes) = storage.read_file(PROFILE_DIR, &name) else {
+                continue;
+            };
+            match core::str::from_utf8(&bytes).map(|text| profiles.load_str(text)) {
+                Ok(Ok(count)) => info!("{}: {} profiles", name, count),
+                Ok(Err(e)) => info!("{}: error at line {}: {:?}", name, e.line, e.kind),
+                Err(_) => info!("{}: not UTF-8", name),
+            }
+        }
+    }
+    info!("{} cartridge profiles loaded", profiles.len());
+
+    // Dumps go to the SD card when one is inserted, otherwise to internal flash
+    let storage_kind = if sd_present {
+        1
+    } else if flash_store.is_some() {
+        2
+    } else {
+        0
+    };
+    let mut dump_store: Option<&mut dyn DumpStore> = if sd_present {
+        Some(&mut storage)
+    } else {
+        flash_store.as_mut().map(|s| s as &mut dyn DumpStore)
+    };
+
     let mut state = AppState::Menu;
     let mut menu_selected: usize = 0;
     let mut editor: Option<ChipEditor> = None;
+    let mut library: Option<Library> = None;
+    let mut library_cmd: Option<Command> = None;
+    let mut compare: Option<Compare> = None;
+    let mut host_reader = FrameReader::new();
+    let mut shell = Shell::new();
+    let mut serial_load: Option<SerialLoad> = None;
     let mut enc_state: u8 = ((enc_a.is_high() as u8) << 1) | (enc_b.is_high() as u8);
     let mut enc_delta: i8 = 0;
     let mut btn_pressed = false;
     let mut back_pressed = false;
+    // Back pressed in the viewer acts on release, and not at all when the
+    // encoder was used while it was held (true)
+    let mut viewer_back: Option<bool> = None;
+    let mut find_input: Option<TextInput> = None;
+    let mut patch_names: alloc::vec::Vec<alloc::string::String> = alloc::vec::Vec::new();
 
     display.show_menu(&MENU_ITEMS, menu_selected);
 
@@ -333,21 +840,91 @@ fn main() -> ! {
                     }
                     AppState::Viewing => {
                         if let Some(ref mut ed) = editor {
-                            if direction {
-                                ed.move_up();
+                            let mut clear = false;
+                            if viewer_back.is_some() {
+                                // Back held: rotate through the history
+                                viewer_back = Some(true);
+                                let done = if direction { ed.undo() } else { ed.redo() };
+                                if !done {
+                                    display.show_status(if direction {
+                                        "Nothing to undo"
+                                    } else {
+                                        "Nothing to redo"
+                                    });
+                                    delay.delay_millis(700);
+                                    clear = true;
+                                }
                             } else {
-                                ed.move_down();
+                                let moved = if direction {
+                                    ed.move_up()
+                                } else {
+                                    ed.move_down()
+                                };
+                                if let Err(e) = moved {
+                                    let mut msg: heapless::String<32> = heapless::String::new();
+                                    let _ = range_error(&mut msg, e);
+                                    display.show_status(&msg);
+                                    delay.delay_millis(700);
+                                    clear = true;
+                                }
                             }
-                            display.show_chip_data(
-                                &ed.data,
-                                ed.selected_block,
-                                ed.selected_byte,
-                                ed.selected_nibble,
-                                ed.edit_mode,
-                                false,
-                            );
+                            display.show_chip_data(ed, clear);
+                        }
+                    }
+                    AppState::EditActions(i) => {
+                        let i = if direction {
+                            i.saturating_sub(1)
+                        } else {
+                            (i + 1).min(EDIT_ACTIONS.len() - 1)
+                        };
+                        state = AppState::EditActions(i);
+                        display.show_list("Edit", &EDIT_ACTIONS, i);
+                    }
+                    AppState::GoTo(i) => {
+                        let i = if direction {
+                            i.saturating_sub(1)
+                        } else {
+                            (i + 1).min(GOTO_ACTIONS.len() - 1)
+                        };
+                        state = AppState::GoTo(i);
+                        display.show_list("Go to", &GOTO_ACTIONS, i);
+                    }
+                    AppState::FindInput(i) => {
+                        if let Some(ref mut input) = find_input {
+                            input.rotate(direction);
+                            display.show_text_input(GOTO_ACTIONS[i], input);
+                        }
+                    }
+                    AppState::Library => {
+                        if let Some(ref mut lib) = library {
+                            lib.rotate(direction);
+                            display.show_library(lib);
+                        }
+                    }
+                    AppState::Compare => {
+                        if let Some(ref mut cmp) = compare {
+                            cmp.rotate(direction);
+                            display.show_diff(cmp);
                         }
                     }
+                    AppState::DumpFormat(i) => {
+                        let i = if direction {
+                            i.saturating_sub(1)
+                        } else {
+                            (i + 1).min(DUMP_FORMATS.len() - 1)
+                        };
+                        state = AppState::DumpFormat(i);
+                        display.show_list("Dump format", &DUMP_FORMAT_LABELS, i);
+                    }
+                    AppState::PatchSource(i) => {
+                        let i = if direction {
+                            i.saturating_sub(1)
+                        } else {
+                            (i + 1).min(patch_names.len())
+                        };
+                        state = AppState::PatchSource(i);
+                        show_patch_sources(&mut display, &patch_names, i);
+                    }
                     _ => {}
                 }
             }
@@ -370,20 +947,14 @@ fn main() -> ! {
 
                             let mut st25tb = St25tb::new(&mut pn532);
                             match st25tb.read_full_chip() {
-                                Ok(data) => {
+                                Ok(mut data) => {
                                     info!("Chip read OK, UID: {:02X?}", data.uid);
+                                    data.timestamp = clock::unix_time().unwrap_or(0);
                                     audio.beep();
-                                    editor = Some(ChipEditor::new(data));
+                                    editor = Some(open_editor(data, &profiles));
                                     state = AppState::Viewing;
                                     if let Some(ref ed) = editor {
-                                        display.show_chip_data(
-                                            &ed.data,
-                                            ed.selected_block,
-                                            ed.selected_byte,
-                                            ed.selected_nibble,
-                                            ed.edit_mode,
-                                            true,
-                                        );
+                                        display.show_chip_data(ed, true);
                                     }
                                     break;
                                 }
@@ -400,23 +971,34 @@ fn main() -> ! {
                     }
                     1 => {
                         if let Some(ref ed) = editor {
-                            state = AppState::Writing;
-                            display.show_status("Writing chip...");
-
-                            let mut st25tb = St25tb::new(&mut pn532);
-                            match st25tb.write_full_chip(&ed.data) {
+                            display.show_status("Checking tag...");
+                            let lowered = St25tb::new(&mut pn532).counters_lowered(&ed.data);
+                            match lowered {
+                                Ok(lowered) if !lowered.is_empty() => {
+                                    info!("Write counts down blocks {:?}", lowered);
+                                    state = AppState::WriteConfirm(1);
+                                    show_confirm(
+                                        &mut display,
+                                        "count down counters",
+                                        "Counters never go back",
+                                        "write",
+                                        1,
+                                    );
+                                }
                                 Ok(_) => {
-                                    info!("Chip written OK");
-                                    display.show_status("Write OK!");
+                                    state = AppState::Writing;
+                                    display.show_status("Writing chip...");
+                                    display.show_status(write_chip(&mut pn532, &ed.data));
+                                    delay.delay_millis(1000);
+                                    state = AppState::Menu;
+                                    display.show_menu(&MENU_ITEMS, menu_selected);
                                 }
-                                Err(e) => {
-                                    info!("Write error: {:?}", e);
-                                    display.show_status("Write failed!");
+                                Err(_) => {
+                                    display.show_status("No tag found");
+                                    delay.delay_millis(1000);
+                                    display.show_menu(&MENU_ITEMS, menu_selected);
                                 }
                             }
-                            delay.delay_millis(1000);
-                            state = AppState::Menu;
-                            display.show_menu(&MENU_ITEMS, menu_selected);
                         } else {
                             display.show_status("No data to write!");
                             delay.delay_millis(1000);
@@ -424,29 +1006,9 @@ fn main() -> ! {
                         }
                     }
                     2 => {
-                        if let Some(ref ed) = editor {
-                            display.show_status("Dumping to Serial...");
-
-                            info!("=== RFID DUMP START ===");
-                            info!("UID: {:02X?}", ed.data.uid);
-                            info!("Blocks: {}", ed.data.block_count);
-                            info!("--- HEX DATA ---");
-                            for i in 0..ed.data.block_count {
-                                info!(
-                                    "B{:03}: {:02X} {:02X} {:02X} {:02X}",
-                                    i,
-                                    ed.data.blocks[i][0],
-                                    ed.data.blocks[i][1],
-                                    ed.data.blocks[i][2],
-                                    ed.data.blocks[i][3]
-                                );
-                            }
-                            info!("=== RFID DUMP END ===");
-
-                            display.show_status("Dump sent to Serial!");
-                            audio.beep();
-                            delay.delay_millis(1500);
-                            display.show_menu(&MENU_ITEMS, menu_selected);
+                        if editor.is_some() {
+                            state = AppState::DumpFormat(0);
+                            display.show_list("Dump format", &DUMP_FORMAT_LABELS, 0);
                         } else {
                             display.show_status("No data to dump!");
                             delay.delay_millis(1000);
@@ -454,135 +1016,190 @@ fn main() -> ! {
                         }
                     }
                     3 => {
-                        display.show_status("Paste dump, END to finish");
                         info!("=== PASTE DUMP NOW ===");
-                        info!("Format: B000: 0F FF FF FF");
+                        info!("Accepts Dump Serial output, hex rows (B000: 0F FF FF FF,");
+                        info!("0F FF FF FF), Proxmark tables and .eml/.json, Flipper .nfc");
+                        info!("Type END (or press BTN) when done");
+                        let load = SerialLoad::new(now_ms());
+                        display.show_load_progress(&load, loader::IDLE_TIMEOUT_MS / 1000);
+                        serial_load = Some(load);
+                        state = AppState::SerialLoad;
+                    }
+                    4 => {
+                        if let Some(ref ed) = editor {
+                            state = AppState::Viewing;
+                            display.show_chip_data(ed, true);
+                        } else {
+                            display.show_status("No data loaded!");
+                            delay.delay_millis(1000);
+                            display.show_menu(&MENU_ITEMS, menu_selected);
+                        }
+                    }
+                    5 => {
+                        display.show_status("Paste profile, END to finish");
+                        info!("=== PASTE PROFILE NOW ===");
                         info!("Type END when done");
 
-                        let mut data = ChipData::default();
-                        let mut line_buf: heapless::Vec<u8, 64> = heapless::Vec::new();
-                        let mut loading = true;
-                        let mut blocks_loaded = 0usize;
-
-                        while loading {
-                            if let Ok(c) = usb_serial.read_byte() {
-                                if c == b'\n' || c == b'\r' {
-                                    if line_buf.len() >= 3 {
-                                        if &line_buf[..3] == b"END" || &line_buf[..3] == b"end" {
-                                            loading = false;
-                                            continue;
-                                        }
-                                        if line_buf.len() >= 4 && line_buf[0] == b'B' {
-                                            if let (Some(h1), Some(h2), Some(h3)) = (
-                                                hex_char_to_nibble(line_buf[1]),
-                                                hex_char_to_nibble(line_buf[2]),
-                                                hex_char_to_nibble(line_buf[3]),
-                                            ) {
-                                                let block_idx = ((h1 as usize) * 100)
-                                                    + ((h2 as usize) * 10)
-                                                    + (h3 as usize);
-                                                if block_idx < 256 {
-                                                    let hex_start = line_buf
-                                                        .iter()
-                                                        .position(|&x| x == b':')
-                                                        .map(|p| p + 1);
-                                                    if let Some(start) = hex_start {
-                                                        let hex_chars: heapless::Vec<u8, 16> =
-                                                            line_buf[start..]
-                                                                .iter()
-                                                                .filter(|&&ch| ch != b' ')
-                                                                .cloned()
-                                                                .collect();
-                                                        if hex_chars.len() >= 8 {
-                                                            if let (
-                                                                Some(b0),
-                                                                Some(b1),
-                                                                Some(b2),
-                                                                Some(b3),
-                                                            ) = (
-                                                                parse_hex_byte(
-                                                                    hex_chars[0],
-                                                                    hex_chars[1],
-                                                                ),
-                                                                parse_hex_byte(
-                                                                    hex_chars[2],
-                                                                    hex_chars[3],
-                                                                ),
-                                                                parse_hex_byte(
-                                                                    hex_chars[4],
-                                                                    hex_chars[5],
-                                                                ),
-                                                                parse_hex_byte(
-                                                                    hex_chars[6],
-                                                                    hex_chars[7],
-                                                                ),
-                                                            ) {
-                                                                data.blocks[block_idx] =
-                                                                    [b0, b1, b2, b3];
-                                                                if block_idx >= data.block_count {
-                                                                    data.block_count =
-                                                                        block_idx + 1;
-                                                                }
-                                                                blocks_loaded += 1;
-                                                                info!("Loaded B{:03}: {:02X} {:02X} {:02X} {:02X}", block_idx, b0, b1, b2, b3);
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        if line_buf.len() >= 4 && &line_buf[..4] == b"UID:" {
-                                            info!("UID line detected (skipped)");
-                                        }
+                        if let Some(text) = read_paste(&mut usb_serial, &back_btn, &delay) {
+                            match profiles.load_str(&text) {
+                                Ok(count) => {
+                                    info!("=== PROFILES LOADED: {} ===", count);
+                                    if let Some(ref mut ed) = editor {
+                                        ed.profile = profiles.find_match(&ed.data).cloned();
                                     }
-                                    line_buf.clear();
-                                } else if line_buf.len() < 64 {
-                                    let _ = line_buf.push(c);
+                                    audio.beep();
+                                    let mut msg: heapless::String<32> = heapless::String::new();
+                                    let _ = core::fmt::write(
+                                        &mut msg,
+                                        format_args!("Loaded {} profiles!", count),
+                                    );
+                                    display.show_status(&msg);
+                                }
+                                Err(e) => {
+                                    info!("Profile error at line {}: {:?}", e.line, e.kind);
+                                    let mut msg: heapless::String<32> = heapless::String::new();
+                                    let _ = core::fmt::write(
+                                        &mut msg,
+                                        format_args!("Profile err line {}", e.line),
+                                    );
+                                    display.show_status(&msg);
                                 }
                             }
-
-                            if back_btn.is_low() {
-                                loading = false;
-                                info!("Load cancelled by user");
-                            }
-
-                            delay.delay_millis(1);
-                        }
-
-                        if blocks_loaded > 0 {
-                            info!("=== LOAD COMPLETE: {} blocks ===", blocks_loaded);
-                            editor = Some(ChipEditor::new(data));
-                            audio.beep();
-                            let mut msg: heapless::String<32> = heapless::String::new();
-                            let _ = core::fmt::write(
-                                &mut msg,
-                                format_args!("Loaded {} blocks!", blocks_loaded),
-                            );
-                            display.show_status(&msg);
                         } else {
-                            display.show_status("No data loaded");
+                            display.show_status("Profile load cancelled");
                         }
                         delay.delay_millis(1500);
                         display.show_menu(&MENU_ITEMS, menu_selected);
                     }
-                    4 => {
+                    6 => {
                         if let Some(ref ed) = editor {
-                            state = AppState::Viewing;
-                            display.show_chip_data(
-                                &ed.data,
-                                ed.selected_block,
-                                ed.selected_byte,
-                                ed.selected_nibble,
-                                ed.edit_mode,
-                                true,
-                            );
+                            // The group depends on the chip type, so it comes
+                            // from the tag that will be locked, not the dump
+                            display.show_status("Checking tag...");
+                            let group = match St25tb::new(&mut pn532).read_uid() {
+                                Ok(uid) if uid != ed.data.uid => Err("Different tag!"),
+                                Ok(uid) => ChipType::from_uid(&uid)
+                                    .lock_group(ed.selected_block)
+                                    .ok_or("Block not lockable"),
+                                Err(_) => Err("No tag found"),
+                            };
+                            match group {
+                                Ok((first, last)) => {
+                                    state = AppState::LockConfirm(1, first, last);
+                                    show_lock_confirm(&mut display, first, last, 1);
+                                }
+                                Err(msg) => {
+                                    display.show_status(msg);
+                                    delay.delay_millis(1000);
+                                    display.show_menu(&MENU_ITEMS, menu_selected);
+                                }
+                            }
                         } else {
                             display.show_status("No data loaded!");
                             delay.delay_millis(1000);
                             display.show_menu(&MENU_ITEMS, menu_selected);
                         }
                     }
-                    5 => {
+                    7 => {
+                        match (dump_store.as_deref_mut(), editor.as_ref()) {
+                            (Some(store), Some(ed)) => {
+                                display.show_status("Saving...");
+                                match store.save_revision(&ed.data) {
+                                    Ok(name) => {
+                                        info!("Saved {}", name);
+                                        audio.beep();
+                                        display.show_lines(&["Saved as", &name], None);
+                                    }
+                                    Err(e) => {
+                                        info!("Save error: {:?}", e);
+                                        display.show_status("Save failed!");
+                                    }
+                                }
+                            }
+                            (None, _) => display.show_status("No storage!"),
+                            (_, None) => display.show_status("No data to save!"),
+                        }
+                        delay.delay_millis(1000);
+                        display.show_menu(&MENU_ITEMS, menu_selected);
+                    }
+                    8 => {
+                        let entries = match dump_store.as_deref_mut() {
+                            Some(store) => store.list_info(),
+                            None => Err(StorageError::Device),
+                        };
+                        match entries {
+                            Ok(entries) => {
+                                let lib = library.insert(Library::new(entries));
+                                state = AppState::Library;
+                                display.show_library(lib);
+                            }
+                            Err(e) => {
+                                info!("List error: {:?}", e);
+                                display.show_status("No storage!");
+                                delay.delay_millis(1000);
+                                display.show_menu(&MENU_ITEMS, menu_selected);
+                            }
+                        }
+                    }
+                    9 => {
+                        match editor.as_ref() {
+                            Some(ed) if !ed.patch.is_empty() => {
+                                let text = ed.patch.text();
+                                {
+                                    let _quiet = Quiet::new();
+                                    let _ = usb_serial.write(b"=== PATCH START ===\n");
+                                    let _ = usb_serial.write(text.as_bytes());
+                                    let _ = usb_serial.write(b"=== PATCH END ===\n");
+                                }
+                                let mut msg: heapless::String<32> = heapless::String::new();
+                                let _ = core::fmt::write(
+                                    &mut msg,
+                                    format_args!("{} changed blocks", ed.patch.len()),
+                                );
+                                display.show_status("Saving...");
+                                let saved = match dump_store.as_deref_mut() {
+                                    Some(store) => store.save_new_patch(&text),
+                                    None => Err(StorageError::Device),
+                                };
+                                match saved {
+                                    Ok(name) => {
+                                        info!("Saved {}", name);
+                                        audio.beep();
+                                        display.show_lines(&[&msg, "Saved as", &name], None);
+                                    }
+                                    Err(e) => {
+                                        info!("Patch save error: {:?}", e);
+                                        display.show_lines(
+                                            &[&msg, "Save failed!", "Patch sent to serial"],
+                                            None,
+                                        );
+                                    }
+                                }
+                            }
+                            Some(_) => display.show_status("No edits to save!"),
+                            None => display.show_status("No data loaded!"),
+                        }
+                        delay.delay_millis(1500);
+                        display.show_menu(&MENU_ITEMS, menu_selected);
+                    }
+                    10 => {
+                        if editor.is_some() {
+                            patch_names = match dump_store.as_deref_mut() {
+                                Some(store) => store.list_patches().unwrap_or_else(|e| {
+                                    info!("Patch list error: {:?}", e);
+                                    alloc::vec::Vec::new()
+                                }),
+                                None => alloc::vec::Vec::new(),
+                            };
+                            state = AppState::PatchSource(0);
+                            show_patch_sources(&mut display, &patch_names, 0);
+                        } else {
+                            display.show_status("No data loaded!");
+                            delay.delay_millis(1500);
+                            display.show_menu(&MENU_ITEMS, menu_selected);
+                        }
+                    }
+                    11 => {
                         display.show_status("Hold BACK to wake");
                         delay.delay_millis(1000);
                         backlight.set_low();
@@ -625,16 +1242,229 @@ fn main() -> ! {
                     _ => {}
                 },
                 AppState::Viewing => {
-                    if let Some(ref mut ed) = editor {
+                    if viewer_back.is_some() {
+                        viewer_back = Some(true);
+                        state = AppState::EditActions(0);
+                        display.show_list("Edit", &EDIT_ACTIONS, 0);
+                    } else if let Some(ref mut ed) = editor {
                         ed.toggle_edit_mode();
-                        display.show_chip_data(
-                            &ed.data,
-                            ed.selected_block,
-                            ed.selected_byte,
-                            ed.selected_nibble,
-                            ed.edit_mode,
-                            false,
-                        );
+                        display.show_chip_data(ed, false);
+                    }
+                }
+                AppState::EditActions(0) => {
+                    state = AppState::GoTo(0);
+                    display.show_list("Go to", &GOTO_ACTIONS, 0);
+                }
+                AppState::GoTo(i @ (0 | 5 | 6)) => {
+                    if let Some(ref ed) = editor {
+                        let (initial, charset, len) = match i {
+                            0 => (alloc::string::String::new(), DIGIT_CHARS, 3),
+                            5 => {
+                                let mut text = alloc::string::String::new();
+                                for b in &ed.search {
+                                    let _ = core::fmt::write(&mut text, format_args!("{:02X}", b));
+                                }
+                                (text, HEX_CHARS, FIND_MAX * 2)
+                            }
+                            _ => (
+                                alloc::string::String::from_utf8_lossy(&ed.search).into_owned(),
+                                TEXT_CHARS,
+                                FIND_MAX,
+                            ),
+                        };
+                        let input = TextInput::new(&initial, charset, len);
+                        display.show_text_input(GOTO_ACTIONS[i], &input);
+                        find_input = Some(input);
+                        state = AppState::FindInput(i);
+                    }
+                }
+                AppState::GoTo(i) => {
+                    if let Some(ref mut ed) = editor {
+                        if !go_to(ed, i) {
+                            display.show_status("Nothing further");
+                            delay.delay_millis(700);
+                        }
+                        state = AppState::Viewing;
+                        display.show_chip_data(ed, true);
+                    }
+                }
+                AppState::FindInput(i) => {
+                    if let Some(ref mut input) = find_input {
+                        input.advance();
+                        display.show_text_input(GOTO_ACTIONS[i], input);
+                    }
+                }
+                AppState::EditActions(i) => {
+                    if let Some(ref mut ed) = editor {
+                        if let Some(msg) = edit_action(ed, i) {
+                            display.show_status(&msg);
+                            delay.delay_millis(700);
+                        }
+                        state = AppState::Viewing;
+                        display.show_chip_data(ed, true);
+                    }
+                }
+                AppState::WriteConfirm(stage) => {
+                    if let Some(ref ed) = editor {
+                        if stage < 2 {
+                            state = AppState::WriteConfirm(stage + 1);
+                            show_confirm(
+                                &mut display,
+                                "count down counters",
+                                "Counters never go back",
+                                "write",
+                                stage + 1,
+                            );
+                        } else {
+                            display.show_status("Writing chip...");
+                            display.show_status(write_chip(&mut pn532, &ed.data));
+                            delay.delay_millis(1000);
+                            state = AppState::Menu;
+                            display.show_menu(&MENU_ITEMS, menu_selected);
+                        }
+                    }
+                }
+                AppState::LockConfirm(stage, first, last) => {
+                    if let Some(ref mut ed) = editor {
+                        if stage < 2 {
+                            state = AppState::LockConfirm(stage + 1, first, last);
+                            show_lock_confirm(&mut display, first, last, stage + 1);
+                        } else {
+                            display.show_status("Locking...");
+                            let mut st25tb = St25tb::new(&mut pn532);
+                            match st25tb.lock_block(ed.selected_block, &ed.data.uid) {
+                                Ok((first, last)) => {
+                                    info!("Blocks {}-{} locked", first, last);
+                                    for b in first..=last {
+                                        ed.data.flags[b] |= FLAG_LOCKED;
+                                    }
+                                    audio.beep();
+                                    display.show_status("Locked!");
+                                }
+                                Err(LockError::WrongTag) => {
+                                    display.show_status("Different tag!");
+                                }
+                                Err(e) => {
+                                    info!("Lock error: {:?}", e);
+                                    display.show_status("Lock failed!");
+                                }
+                            }
+                            delay.delay_millis(1000);
+                            state = AppState::Menu;
+                            display.show_menu(&MENU_ITEMS, menu_selected);
+                        }
+                    }
+                }
+                AppState::Library => {
+                    if let Some(ref mut lib) = library {
+                        match lib.press() {
+                            Event::Run(cmd) => library_cmd = Some(cmd),
+                            _ => display.show_library(lib),
+                        }
+                    }
+                }
+                AppState::DumpFormat(i) => {
+                    if let Some(ref ed) = editor {
+                        display.show_status("Dumping to Serial...");
+                        let _quiet = Quiet::new();
+                        match DUMP_FORMATS[i] {
+                            None => {
+                                let _ = usb_serial.write(text::export(&ed.data).as_bytes());
+                            }
+                            Some(format) => {
+                                let label = format.label();
+                                let start = alloc::format!("=== {} START ===\n", label);
+                                let _ = usb_serial.write(start.as_bytes());
+                                let _ = usb_serial.write(&interop::export(&ed.data, format));
+                                let end = alloc::format!("=== {} END ===\n", label);
+                                let _ = usb_serial.write(end.as_bytes());
+                            }
+                        }
+                        display.show_status("Dump sent to Serial!");
+                        audio.beep();
+                        delay.delay_millis(1500);
+                    }
+                    state = AppState::Menu;
+                    display.show_menu(&MENU_ITEMS, menu_selected);
+                }
+                AppState::SerialLoad => {
+                    if let Some(ref mut load) = serial_load {
+                        load.request(Outcome::Finish);
+                    }
+                }
+                AppState::PatchSource(i) => {
+                    if let Some(ref mut ed) = editor {
+                        let mut msg: heapless::String<32> = heapless::String::new();
+                        let text = if i == 0 {
+                            display.show_status("Paste patch, END to finish");
+                            info!("=== PASTE PATCH NOW ===");
+                            info!("Type END when done");
+                            read_paste(&mut usb_serial, &back_btn, &delay)
+                        } else {
+                            let name = &patch_names[i - 1];
+                            let loaded = match dump_store.as_deref_mut() {
+                                Some(store) => store.load_patch(name),
+                                None => Err(StorageError::Device),
+                            };
+                            loaded
+                                .map_err(|e| info!("Patch load error {}: {:?}", name, e))
+                                .ok()
+                        };
+                        match text.map(|text| Patch::parse(&text)) {
+                            None => {
+                                let _ = msg.push_str(if i == 0 {
+                                    "Patch cancelled"
+                                } else {
+                                    "Load failed!"
+                                });
+                            }
+                            Some(Err(patch::ParseError::Line(line))) => {
+                                info!("Patch error at line {}", line);
+                                let _ = core::fmt::write(
+                                    &mut msg,
+                                    format_args!("Patch err line {}", line),
+                                );
+                            }
+                            Some(Ok(patch)) => match ed.apply_patch(&patch) {
+                                Ok(changed) => {
+                                    info!("Patch applied, {} blocks changed", changed);
+                                    audio.beep();
+                                    let _ = core::fmt::write(
+                                        &mut msg,
+                                        format_args!("Patched {} blocks", changed),
+                                    );
+                                }
+                                Err(ApplyError::Refused(e)) => {
+                                    info!("Patch not applied: {:?}", e);
+                                    let _ = range_error(&mut msg, e);
+                                }
+                                Err(ApplyError::Conflicts(conflicts)) => {
+                                    for conflict in &conflicts {
+                                        info!("Conflict {}", conflict.text());
+                                    }
+                                    info!("Patch not applied");
+                                    let _ = core::fmt::write(
+                                        &mut msg,
+                                        format_args!("{} conflicts, see log", conflicts.len()),
+                                    );
+                                }
+                            },
+                        }
+                        display.show_status(&msg);
+                    }
+                    delay.delay_millis(1500);
+                    state = AppState::Menu;
+                    display.show_menu(&MENU_ITEMS, menu_selected);
+                }
+                AppState::Compare => {
+                    let block = compare.as_ref().and_then(|c| c.selected_block().copied());
+                    if let (Some(d), Some(ed)) = (block, editor.as_mut()) {
+                        if d.index < ed.data.block_count {
+                            ed.selected_block = d.index;
+                            state = AppState::Viewing;
+                            compare = None;
+                            display.show_chip_data(ed, true);
+                        }
                     }
                 }
                 AppState::Error => {
@@ -652,25 +1482,54 @@ fn main() -> ! {
         if back_state && !back_pressed {
             back_pressed = true;
             match state {
-                AppState::Viewing => {
-                    if let Some(ref mut ed) = editor {
-                        if ed.edit_mode {
-                            ed.exit_edit_mode();
-                            display.show_chip_data(
-                                &ed.data,
-                                ed.selected_block,
-                                ed.selected_byte,
-                                ed.selected_nibble,
-                                ed.edit_mode,
-                                false,
-                            );
-                        } else {
-                            state = AppState::Menu;
-                            display.show_menu(&MENU_ITEMS, menu_selected);
+                AppState::Viewing => viewer_back = Some(false),
+                AppState::EditActions(_) | AppState::GoTo(_) => {
+                    if let Some(ref ed) = editor {
+                        state = AppState::Viewing;
+                        display.show_chip_data(ed, true);
+                    }
+                }
+                AppState::FindInput(i) => {
+                    if let (Some(input), Some(ed)) = (find_input.take(), editor.as_mut()) {
+                        if let Some(msg) = find_entered(ed, i, &input.finish()) {
+                            display.show_status(msg);
+                            delay.delay_millis(700);
                         }
+                        state = AppState::Viewing;
+                        display.show_chip_data(ed, true);
                     }
                 }
-                AppState::Error | AppState::Reading | AppState::Writing => {
+                AppState::Library => {
+                    if let Some(ref mut lib) = library {
+                        match lib.back() {
+                            Event::Exit => {
+                                state = AppState::Menu;
+                                display.show_menu(&MENU_ITEMS, menu_selected);
+                            }
+                            Event::Run(cmd) => library_cmd = Some(cmd),
+                            Event::None => display.show_library(lib),
+                        }
+                    }
+                }
+                AppState::SerialLoad => {
+                    if let Some(ref mut load) = serial_load {
+                        load.request(Outcome::Cancel);
+                    }
+                }
+                AppState::Compare => {
+                    compare = None;
+                    state = AppState::Library;
+                    if let Some(ref lib) = library {
+                        display.show_library(lib);
+                    }
+                }
+                AppState::Error
+                | AppState::Reading
+                | AppState::Writing
+                | AppState::LockConfirm(..)
+                | AppState::WriteConfirm(_)
+                | AppState::DumpFormat(_)
+                | AppState::PatchSource(_) => {
                     state = AppState::Menu;
                     display.show_menu(&MENU_ITEMS, menu_selected);
                 }
@@ -678,9 +1537,283 @@ fn main() -> ! {
             }
         }
         if !back_state {
+            if viewer_back.take() == Some(false) && state == AppState::Viewing {
+                if let Some(ref mut ed) = editor {
+                    if ed.edit_mode {
+                        ed.exit_edit_mode();
+                        display.show_chip_data(ed, false);
+                    } else if ed.mark.is_some() {
+                        ed.mark = None;
+                        display.show_chip_data(ed, false);
+                    } else {
+                        state = AppState::Menu;
+                        display.show_menu(&MENU_ITEMS, menu_selected);
+                    }
+                }
+            }
             back_pressed = false;
         }
 
+        if let (Some(cmd), Some(store)) = (library_cmd.take(), dump_store.as_deref_mut()) {
+            match cmd {
+                Command::Open(name) => match store.load_dump(&name) {
+                    Ok(data) => {
+                        info!("Loaded {}", name);
+                        audio.beep();
+                        editor = Some(open_editor(data, &profiles));
+                        state = AppState::Viewing;
+                        if let Some(ref ed) = editor {
+                            display.show_chip_data(ed, true);
+                        }
+                    }
+                    Err(e) => {
+                        info!("Load error: {:?}", e);
+                        display.show_status("Load failed!");
+                        delay.delay_millis(1000);
+                    }
+                },
+                Command::Diff(name) => match (store.load_dump(&name), editor.as_ref()) {
+                    (Ok(stored), Some(ed)) => {
+                        let diff = Diff::new(&stored, &ed.data);
+                        info!(
+                            "Diff {} vs open: {} blocks, {} bytes, {} bits",
+                            name,
+                            diff.blocks.len(),
+                            diff.bytes(),
+                            diff.bits()
+                        );
+                        let cmp = Compare::new(name, diff);
+                        display.show_diff(&cmp);
+                        compare = Some(cmp);
+                        state = AppState::Compare;
+                    }
+                    (Ok(_), None) => {
+                        display.show_status("Open a dump first");
+                        delay.delay_millis(1000);
+                    }
+                    (Err(e), _) => {
+                        info!("Load error: {:?}", e);
+                        display.show_status("Load failed!");
+                        delay.delay_millis(1000);
+                    }
+                },
+                Command::Analyze(names) => {
+                    display.show_status("Analyzing...");
+                    let mut analysis = Analysis::new();
+                    // The checksum search needs the dumps side by side
+                    let mut kept = alloc::vec::Vec::new();
+                    for name in &names {
+                        match store.load_dump(name) {
+                            Ok(data) => {
+                                analysis.add(&data);
+                                if kept.len() < CHECKSUM_DUMPS {
+                                    kept.push(data);
+                                }
+                            }
+                            Err(e) => info!("Load error {}: {:?}", name, e),
+                        }
+                    }
+                    display.show_status("Checksums...");
+                    let sums = checksum::search(&kept, false);
+                    let sums_report = checksum::report(kept.len(), &sums);
+                    drop(kept);
+                    info!(
+                        "Analyzed {} dumps of {} tags",
+                        analysis.dumps(),
+                        analysis.tags()
+                    );
+                    {
+                        let _quiet = Quiet::new();
+                        let _ = usb_serial.write(b"=== ANALYSIS START ===\n");
+                        let _ = usb_serial.write(analysis.map().as_bytes());
+                        let _ = usb_serial.write(b"\n");
+                        let _ = usb_serial.write(analysis.profile("Seed").as_bytes());
+                        let _ = usb_serial.write(b"\n");
+                        let _ = usb_serial.write(sums_report.as_bytes());
+                        let _ = usb_serial.write(b"=== ANALYSIS END ===\n");
+                    }
+                    let mut dumps: heapless::String<32> = heapless::String::new();
+                    let mut fixed: heapless::String<32> = heapless::String::new();
+                    let mut varying: heapless::String<32> = heapless::String::new();
+                    let _ = core::fmt::write(
+                        &mut dumps,
+                        format_args!("{} dumps, {} tags", analysis.dumps(), analysis.tags()),
+                    );
+                    let _ = core::fmt::write(
+                        &mut fixed,
+                        format_args!(
+                            "Const {} Unique {}",
+                            analysis.count(|c| matches!(c, ByteClass::Constant(_))),
+                            analysis.count(|c| *c == ByteClass::Unique)
+                        ),
+                    );
+                    // Counters and random bytes only show on a tag dumped twice
+                    let reread = analysis.reread_tags() > 0;
+                    if reread {
+                        let _ = core::fmt::write(
+                            &mut varying,
+                            format_args!(
+                                "Counter {} Random {}",
+                                analysis.count(|c| matches!(c, ByteClass::Counter { .. })),
+                                analysis.count(|c| *c == ByteClass::Random)
+                            ),
+                        );
+                    } else {
+                        let _ = varying.push_str("Dump a tag twice for counters");
+                    }
+                    let mut sent: heapless::String<32> = heapless::String::new();
+                    let _ = core::fmt::write(
+                        &mut sent,
+                        format_args!("{} checksums, see serial", sums.len()),
+                    );
+                    let warn = (!reread).then_some(2);
+                    display.show_lines(&[&dumps, &fixed, &varying, &sent], warn);
+                    delay.delay_millis(3000);
+                }
+                Command::Write(name) => {
+                    match store.load_dump(&name) {
+                        Ok(data) => {
+                            display.show_status("Writing chip...");
+                            let mut st25tb = St25tb::new(&mut pn532);
+                            match st25tb.write_full_chip(&data) {
+                                Ok(_) => {
+                                    info!("{} written to tag", name);
+                                    audio.beep();
+                                    display.show_status("Write OK!");
+                                }
+                                Err(e) => {
+                                    info!("Write error: {:?}", e);
+                                    display.show_status("Write failed!");
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            info!("Load error: {:?}", e);
+                            display.show_status("Load failed!");
+                        }
+                    }
+                    delay.delay_millis(1000);
+                }
+                Command::Rename { from, to } => {
+                    let result = store.rename_dump(&from, &to);
+                    info!("Rename {} -> {}: {:?}", from, to, result);
+                    let msg = match result {
+                        Ok(_) => "Renamed",
+                        Err(StorageError::Exists) => "Name already used!",
+                        Err(StorageError::InvalidName) => "Invalid name!",
+                        Err(_) => "Rename failed!",
+                    };
+                    display.show_status(msg);
+                    delay.delay_millis(1000);
+                }
+                Command::Delete(name) => {
+                    let result = store.delete_dump(&name);
+                    info!("Delete {}: {:?}", name, result);
+                    display.show_status(if result.is_ok() {
+                        "Deleted"
+                    } else {
+                        "Delete failed!"
+                    });
+                    delay.delay_millis(1000);
+                }
+            }
+            if state == AppState::Library {
+                if let Some(ref mut lib) = library {
+                    lib.reload(store.list_info().unwrap_or_default());
+                    display.show_library(lib);
+                }
+            }
+        }
+
+        let now = now_ms();
+        while let Ok(c) = usb_serial.read_byte() {
+            if let Some(ref mut load) = serial_load {
+                load.push(c, now);
+                continue;
+            }
+            match host_reader.push(c) {
+                HostInput::Frame(Ok(req)) => {
+                    let reply = serve_host(
+                        &req,
+                        &mut pn532,
+                        &mut editor,
+                        &profiles,
+                        dump_store.as_deref_mut(),
+                        storage_kind,
+                    );
+                    let _ = usb_serial.write(&reply.encode());
+                    if state == AppState::Viewing {
+                        if let Some(ref ed) = editor {
+                            display.show_chip_data(ed, true);
+                        }
+                    }
+                }
+                HostInput::Frame(Err(e)) => info!("Bad host frame: {:?}", e),
+                HostInput::Text(c) => {
+                    let open = editor.as_ref().map(|ed| &ed.data);
+                    if let Some(out) = shell.push(c, &mut pn532, open) {
+                        let _ = usb_serial.write(out.as_bytes());
+                    }
+                }
+                HostInput::None => {}
+            }
+        }
+
+        match serial_load.as_ref().and_then(|load| load.poll(now)) {
+            None => {
+                if let Some(ref mut load) = serial_load {
+                    if load.take_redraw(now) {
+                        display.show_load_progress(load, load.idle_left(now));
+                    }
+                }
+            }
+            Some(outcome) => {
+                let load = serial_load.take().unwrap();
+                let (blocks, errors) = (load.blocks(), load.rejected());
+                let keep = match outcome {
+                    Outcome::Finish => true,
+                    Outcome::Cancel => false,
+                    Outcome::TimedOut => load.has_data(),
+                };
+                // Consumes the load either way, so logging is back on below
+                let result = keep.then(|| load.finish());
+                match result {
+                    Some(Ok(data)) => {
+                        info!(
+                            "=== LOAD COMPLETE: {} blocks, {} bad lines ===",
+                            data.block_count, errors
+                        );
+                        let mut msg: heapless::String<32> = heapless::String::new();
+                        let _ = core::fmt::write(
+                            &mut msg,
+                            format_args!("Loaded {} blocks!", data.block_count),
+                        );
+                        editor = Some(open_editor(data, &profiles));
+                        audio.beep();
+                        display.show_status(&msg);
+                    }
+                    Some(Err(e)) => {
+                        info!(
+                            "Load failed: {:?} ({} blocks, {} bad lines)",
+                            e, blocks, errors
+                        );
+                        display.show_status("No data loaded");
+                    }
+                    None if outcome == Outcome::Cancel => {
+                        info!("Load cancelled by user");
+                        display.show_status("Load cancelled");
+                    }
+                    None => {
+                        info!("Load timed out, nothing received");
+                        display.show_status("Load timed out");
+                    }
+                }
+                delay.delay_millis(1500);
+                state = AppState::Menu;
+                display.show_menu(&MENU_ITEMS, menu_selected);
+            }
+        }
+
         delay.delay_millis(10);
     }
 }
@@ -0,0 +1,186 @@
+//! Cartridge profiles: named field layouts plus rules for recognising a dump
+
+pub mod date;
+pub mod parser;
+pub mod value;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::analysis::checksum::Algorithm;
+use crate::protocol::st25tb::{ChipData, ChipType};
+
+pub use parser::{ParseError, ParseErrorKind};
+
+const BUILTIN_PROFILES: &str = include_str!("../../profiles/builtin.ini");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Hex,
+    Decimal,
+    /// Day count relative to `epoch` (days since 1970-01-01).
+    Date {
+        epoch: i32,
+    },
+    Enum(Vec<(u32, String)>),
+    Flags(Vec<(u8, String)>),
+    /// Unary counter: each cleared bit is one consumed unit.
+    Otp,
+    /// `algorithm` over `len` bytes from byte `start` (block * 4 + byte).
+    Checksum {
+        algorithm: Algorithm,
+        start: usize,
+        len: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub block: usize,
+    pub offset: usize,
+    pub size: usize,
+    pub endian: Endian,
+    pub kind: FieldKind,
+}
+
+impl Field {
+    pub fn contains(&self, block: usize, byte: usize) -> bool {
+        let start = self.block * 4 + self.offset;
+        let pos = block * 4 + byte;
+        pos >= start && pos < start + self.size
+    }
+}
+
+/// Fixed block contents; bytes with a zero mask are wildcards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockMatch {
+    pub block: usize,
+    pub value: [u8; 4],
+    pub mask: [u8; 4],
+}
+
+impl BlockMatch {
+    pub fn matches(&self, data: &ChipData) -> bool {
+        if self.block >= data.block_count {
+            return false;
+        }
+        let block = &data.blocks[self.block];
+        (0..4).all(|i| block[i] & self.mask[i] == self.value[i] & self.mask[i])
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub chip_types: Vec<ChipType>,
+    /// Compared against the MSB-first UID, e.g. `D0 02`.
+    pub uid_prefix: Vec<u8>,
+    pub block_matches: Vec<BlockMatch>,
+    pub fields: Vec<Field>,
+}
+
+impl Profile {
+    pub fn matches(&self, data: &ChipData) -> bool {
+        if !self.chip_types.is_empty() && !self.chip_types.contains(&data.chip_type()) {
+            return false;
+        }
+        if !data.uid_msb_first().starts_with(&self.uid_prefix) {
+            return false;
+        }
+        self.block_matches.iter().all(|m| m.matches(data))
+    }
+
+    /// Number of rules a dump has to satisfy; more specific profiles win ties.
+    fn specificity(&self) -> usize {
+        let chip = if self.chip_types.is_empty() { 0 } else { 1 };
+        chip + self.uid_prefix.len() + self.block_matches.len() * 4
+    }
+
+    pub fn field_at(&self, block: usize, byte: usize) -> Option<&Field> {
+        self.fields.iter().find(|f| f.contains(block, byte))
+    }
+
+    /// Rewrites every checksum field from the bytes it covers, in profile
+    /// order (so a checksum may cover an earlier one). Returns how many
+    /// changed.
+    pub fn update_checksums(&self, data: &mut ChipData) -> usize {
+        let mut changed = 0;
+        for field in &self.fields {
+            if let Some(expected) = field.checksum(data) {
+                if field.read(data) != expected {
+                    field.write(data, expected);
+                    changed += 1;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Checksum fields whose stored value does not match the data.
+    pub fn bad_checksums<'a>(&'a self, data: &'a ChipData) -> impl Iterator<Item = &'a Field> {
+        self.fields
+            .iter()
+            .filter(|f| f.checksum(data).is_some_and(|v| v != f.read(data)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProfileSet {
+    pub profiles: Vec<Profile>,
+}
+
+impl ProfileSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builtin() -> Self {
+        let mut set = Self::new();
+        if let Err(e) = set.load_str(BUILTIN_PROFILES) {
+            log::error!("Built-in profiles invalid: {:?}", e);
+        }
+        set
+    }
+
+    /// Adds every profile in `text`, replacing existing ones with the same name.
+    pub fn load_str(&mut self, text: &str) -> Result<usize, ParseError> {
+        let parsed = parser::parse(text)?;
+        let count = parsed.len();
+        for profile in parsed {
+            self.insert(profile);
+        }
+        Ok(count)
+    }
+
+    pub fn insert(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    pub fn find_match(&self, data: &ChipData) -> Option<&Profile> {
+        let mut best: Option<&Profile> = None;
+        for profile in self.profiles.iter().filter(|p| p.matches(data)) {
+            if best.is_none_or(|b| profile.specificity() > b.specificity()) {
+                best = Some(profile);
+            }
+        }
+        best
+    }
+
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+}
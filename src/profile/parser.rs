@@ -0,0 +1,560 @@
+//! INI-style profile definitions
+//!
+//! ```text
+//! # comment
+//! [profile]
+//! name = EBS XI32001
+//! chip = ST25TB04K, SRIX4K
+//! uid_prefix = D0 02
+//! match = 20: ?? ?? FF 01
+//!
+//! [field date]
+//! block = 22
+//! offset = 0
+//! size = 2
+//! endian = big
+//! type = date
+//! epoch = 1992-01-01
+//! ```
+//!
+//! Field types: `hex`, `dec`, `date` (`epoch`), `enum` (`values = 0:Black, 1:Cyan`),
+//! `flags` (`bits = 0:Sealed, 7:Empty`), `otp` (unary bit counter) and
+//! `checksum` (`algorithm = CRC-16/KERMIT`, `covers = 8:0-9:3`; names and
+//! ranges as `rfid-host checksum` prints them, a whole block as `8`).
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::date::parse_date;
+use super::{BlockMatch, Endian, Field, FieldKind, Profile};
+use crate::analysis::checksum::{self, Algorithm};
+use crate::protocol::st25tb::ChipType;
+use crate::util::hex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseErrorKind {
+    UnknownSection,
+    UnknownKey,
+    InvalidValue,
+    FieldOutsideProfile,
+    MissingName,
+    MissingBlock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+struct FieldBuilder {
+    line: usize,
+    /// Line each key was last set on, for errors only found once the field
+    /// is complete.
+    key_lines: Vec<(String, usize)>,
+    name: String,
+    block: Option<usize>,
+    offset: usize,
+    size: usize,
+    endian: Endian,
+    kind: String,
+    epoch: i32,
+    values: Vec<(u32, String)>,
+    algorithm: Option<Algorithm>,
+    /// Covered bytes, first and last.
+    covers: Option<(usize, usize)>,
+}
+
+impl FieldBuilder {
+    fn new(line: usize, name: &str) -> Self {
+        Self {
+            line,
+            key_lines: Vec::new(),
+            name: String::from(name),
+            block: None,
+            offset: 0,
+            size: 1,
+            endian: Endian::Little,
+            kind: String::from("hex"),
+            epoch: 0,
+            values: Vec::new(),
+            algorithm: None,
+            covers: None,
+        }
+    }
+
+    fn set(&mut self, line: usize, key: &str, value: &str) -> Result<(), ParseErrorKind> {
+        match key {
+            "block" => self.block = Some(parse_num(value).ok_or(ParseErrorKind::InvalidValue)?),
+            "offset" => self.offset = parse_num(value).ok_or(ParseErrorKind::InvalidValue)?,
+            "size" => self.size = parse_num(value).ok_or(ParseErrorKind::InvalidValue)?,
+            "endian" => {
+                self.endian = match value {
+                    "le" | "little" => Endian::Little,
+                    "be" | "big" => Endian::Big,
+                    _ => return Err(ParseErrorKind::InvalidValue),
+                }
+            }
+            "type" => self.kind = String::from(value),
+            "epoch" => self.epoch = parse_date(value).ok_or(ParseErrorKind::InvalidValue)?,
+            "values" | "bits" => self.values = parse_labels(value)?,
+            "algorithm" => {
+                self.algorithm =
+                    Some(Algorithm::from_name(value).ok_or(ParseErrorKind::InvalidValue)?)
+            }
+            "covers" => self.covers = Some(parse_range(value).ok_or(ParseErrorKind::InvalidValue)?),
+            _ => return Err(ParseErrorKind::UnknownKey),
+        }
+        match self.key_lines.iter_mut().find(|(k, _)| k == key) {
+            Some((_, at)) => *at = line,
+            None => self.key_lines.push((String::from(key), line)),
+        }
+        Ok(())
+    }
+
+    /// The last line setting one of `keys`, or the section header when none
+    /// of them was given.
+    fn line_of(&self, keys: &[&str]) -> usize {
+        self.key_lines
+            .iter()
+            .filter(|(k, _)| keys.contains(&k.as_str()))
+            .map(|&(_, line)| line)
+            .max()
+            .unwrap_or(self.line)
+    }
+
+    fn build(mut self) -> Result<Field, ParseError> {
+        let values = core::mem::take(&mut self.values);
+        let invalid = |keys: &[&str]| ParseError {
+            line: self.line_of(keys),
+            kind: ParseErrorKind::InvalidValue,
+        };
+        let block = self.block.ok_or(ParseError {
+            line: self.line,
+            kind: ParseErrorKind::MissingBlock,
+        })?;
+        if self.offset > 3 {
+            return Err(invalid(&["offset"]));
+        }
+        if self.size == 0 || self.size > 4 {
+            return Err(invalid(&["size"]));
+        }
+        if block * 4 + self.offset + self.size > 1024 {
+            return Err(invalid(&["block", "offset", "size"]));
+        }
+        let kind = match self.kind.as_str() {
+            "hex" => FieldKind::Hex,
+            "dec" | "decimal" => FieldKind::Decimal,
+            "date" => FieldKind::Date { epoch: self.epoch },
+            "enum" => FieldKind::Enum(values),
+            "otp" => FieldKind::Otp,
+            "flags" => {
+                let mut bits = Vec::new();
+                for (bit, label) in values {
+                    if bit >= (self.size as u32) * 8 {
+                        return Err(invalid(&["bits", "values", "size"]));
+                    }
+                    bits.push((bit as u8, label));
+                }
+                FieldKind::Flags(bits)
+            }
+            "checksum" => {
+                let (Some(algorithm), Some((first, last))) = (self.algorithm, self.covers) else {
+                    return Err(invalid(&["type"]));
+                };
+                let pos = block * 4 + self.offset;
+                let len = last + 1 - first;
+                if algorithm.width() != self.size {
+                    return Err(invalid(&["algorithm", "size"]));
+                }
+                if len > checksum::MAX_RANGE || (pos <= last && first < pos + self.size) {
+                    return Err(invalid(&["covers", "block", "offset", "size"]));
+                }
+                FieldKind::Checksum {
+                    algorithm,
+                    start: first,
+                    len,
+                }
+            }
+            _ => return Err(invalid(&["type"])),
+        };
+        Ok(Field {
+            name: self.name,
+            block,
+            offset: self.offset,
+            size: self.size,
+            endian: self.endian,
+            kind,
+        })
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Profile>, ParseError> {
+    let mut profiles: Vec<Profile> = Vec::new();
+    let mut current: Option<(usize, Profile)> = None;
+    let mut field: Option<FieldBuilder> = None;
+
+    for (idx, raw) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let err = |kind| ParseError {
+            line: line_no,
+            kind,
+        };
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let section = section.trim();
+            if let Some(f) = field.take() {
+                push_field(&mut current, f)?;
+            }
+            if section == "profile" {
+                if let Some(done) = current.take() {
+                    profiles.push(finish_profile(done)?);
+                }
+                current = Some((line_no, Profile::default()));
+            } else if let Some(name) = section.strip_prefix("field ") {
+                if current.is_none() {
+                    return Err(err(ParseErrorKind::FieldOutsideProfile));
+                }
+                field = Some(FieldBuilder::new(line_no, name.trim()));
+            } else {
+                return Err(err(ParseErrorKind::UnknownSection));
+            }
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => return Err(err(ParseErrorKind::InvalidValue)),
+        };
+
+        if let Some(ref mut f) = field {
+            f.set(line_no, key, value).map_err(err)?;
+        } else if let Some((_, ref mut profile)) = current {
+            set_profile_key(profile, key, value).map_err(err)?;
+        } else {
+            return Err(err(ParseErrorKind::FieldOutsideProfile));
+        }
+    }
+
+    if let Some(f) = field.take() {
+        push_field(&mut current, f)?;
+    }
+    if let Some(done) = current.take() {
+        profiles.push(finish_profile(done)?);
+    }
+    Ok(profiles)
+}
+
+fn set_profile_key(profile: &mut Profile, key: &str, value: &str) -> Result<(), ParseErrorKind> {
+    match key {
+        "name" => profile.name = String::from(value),
+        "chip" => {
+            for name in value.split(',') {
+                let chip = ChipType::from_name(name.trim()).ok_or(ParseErrorKind::InvalidValue)?;
+                profile.chip_types.push(chip);
+            }
+        }
+        "uid_prefix" => {
+            let mut buf = [0u8; 8];
+            let n = hex::parse_bytes(value, &mut buf).ok_or(ParseErrorKind::InvalidValue)?;
+            profile.uid_prefix = buf[..n].to_vec();
+        }
+        "match" => profile.block_matches.push(parse_block_match(value)?),
+        _ => return Err(ParseErrorKind::UnknownKey),
+    }
+    Ok(())
+}
+
+fn parse_block_match(value: &str) -> Result<BlockMatch, ParseErrorKind> {
+    let (block, bytes) = value.split_once(':').ok_or(ParseErrorKind::InvalidValue)?;
+    let block: usize = parse_num(block.trim()).ok_or(ParseErrorKind::InvalidValue)?;
+    if block > 255 {
+        return Err(ParseErrorKind::InvalidValue);
+    }
+
+    let mut m = BlockMatch {
+        block,
+        value: [0; 4],
+        mask: [0; 4],
+    };
+    let mut count = 0;
+    for token in bytes.split_whitespace() {
+        if count >= 4 {
+            return Err(ParseErrorKind::InvalidValue);
+        }
+        if token != "??" {
+            let t = token.as_bytes();
+            if t.len() != 2 {
+                return Err(ParseErrorKind::InvalidValue);
+            }
+            m.value[count] = hex::byte(t[0], t[1]).ok_or(ParseErrorKind::InvalidValue)?;
+            m.mask[count] = 0xFF;
+        }
+        count += 1;
+    }
+    if count != 4 {
+        return Err(ParseErrorKind::InvalidValue);
+    }
+    Ok(m)
+}
+
+fn parse_labels(value: &str) -> Result<Vec<(u32, String)>, ParseErrorKind> {
+    let mut labels = Vec::new();
+    for item in value.split(',') {
+        let (num, label) = item.split_once(':').ok_or(ParseErrorKind::InvalidValue)?;
+        let num = parse_num(num.trim()).ok_or(ParseErrorKind::InvalidValue)?;
+        labels.push((num as u32, String::from(label.trim())));
+    }
+    Ok(labels)
+}
+
+/// `8:0-9:3`, optionally `B008:0-B009:3`; a bare block number is the whole
+/// block.
+fn parse_range(value: &str) -> Option<(usize, usize)> {
+    let pos = |s: &str, last: bool| -> Option<usize> {
+        let s = s.trim();
+        let s = s.strip_prefix(['B', 'b']).unwrap_or(s);
+        let (block, byte) = match s.split_once(':') {
+            Some((block, byte)) => (block, Some(parse_num(byte)?)),
+            None => (s, None),
+        };
+        let block = parse_num(block).filter(|&b| b < 256)?;
+        let byte = byte.unwrap_or(if last { 3 } else { 0 });
+        (byte < 4).then_some(block * 4 + byte)
+    };
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let (first, last) = (pos(first, false)?, pos(last, true)?);
+    (first <= last).then_some((first, last))
+}
+
+fn parse_num(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn push_field(current: &mut Option<(usize, Profile)>, f: FieldBuilder) -> Result<(), ParseError> {
+    let line = f.line;
+    let field = f.build()?;
+    match current {
+        Some((_, profile)) => {
+            profile.fields.push(field);
+            Ok(())
+        }
+        None => Err(ParseError {
+            line,
+            kind: ParseErrorKind::FieldOutsideProfile,
+        }),
+    }
+}
+
+fn finish_profile((line, profile): (usize, Profile)) -> Result<Profile, ParseError> {
+    if profile.name.is_empty() {
+        return Err(ParseError {
+            line,
+            kind: ParseErrorKind::MissingName,
+        });
+    }
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::date::days_from_civil;
+    use crate::profile::ProfileSet;
+    use crate::protocol::st25tb::ChipData;
+
+    fn ebs_dump() -> ChipData {
+        let mut data = ChipData {
+            uid: [0x78, 0x56, 0x34, 0x12, 0x00, 0x1C, 0x02, 0xD0],
+            block_count: 128,
+            ..ChipData::default()
+        };
+        data.blocks[20] = [0x27, 0x29, 0xFF, 0x01];
+        data.blocks[22] = [0x2E, 0x4A, 0x00, 0x00];
+        data
+    }
+
+    fn error(text: &str) -> ParseError {
+        parse(text).expect_err("parsed")
+    }
+
+    #[test]
+    fn parses_fields_and_rules() {
+        let text = "\
+# comment
+[profile]
+name = Test
+chip = ST25TB04K, srix4k
+uid_prefix = D0 02
+match = 0x14: ?? ?? FF 01
+
+[field date]
+block = 22
+size = 2
+endian = big
+type = date
+epoch = 1992-01-01
+
+[field colour]
+block = 23
+type = enum
+values = 0:Black, 1:Cyan
+";
+        let profiles = parse(text).unwrap();
+        assert_eq!(profiles.len(), 1);
+        let p = &profiles[0];
+        assert_eq!(p.name, "Test");
+        assert_eq!(p.chip_types, [ChipType::St25tb04k, ChipType::Srix4k]);
+        assert_eq!(p.uid_prefix, [0xD0, 0x02]);
+        assert_eq!(
+            p.block_matches,
+            [BlockMatch {
+                block: 20,
+                value: [0, 0, 0xFF, 0x01],
+                mask: [0, 0, 0xFF, 0xFF],
+            }]
+        );
+        let date = &p.fields[0];
+        assert_eq!((date.block, date.offset, date.size), (22, 0, 2));
+        assert_eq!(date.endian, Endian::Big);
+        assert_eq!(
+            date.kind,
+            FieldKind::Date {
+                epoch: days_from_civil(1992, 1, 1)
+            }
+        );
+        assert_eq!(
+            p.fields[1].kind,
+            FieldKind::Enum(alloc::vec![(0, "Black".into()), (1, "Cyan".into())])
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let at = |line, kind| ParseError { line, kind };
+        assert_eq!(
+            error("[profile]\nname = X\ncolour = red\n"),
+            at(3, ParseErrorKind::UnknownKey)
+        );
+        assert_eq!(
+            error("[field a]\nblock = 1\n"),
+            at(1, ParseErrorKind::FieldOutsideProfile)
+        );
+        assert_eq!(
+            error("[profile]\nname = X\n[tag]\n"),
+            at(3, ParseErrorKind::UnknownSection)
+        );
+        assert_eq!(
+            error("[profile]\n[field a]\nsize = 1\n"),
+            at(2, ParseErrorKind::MissingBlock)
+        );
+        assert_eq!(
+            error("[profile]\nname = X\nmatch = 20: FF FF\n"),
+            at(3, ParseErrorKind::InvalidValue)
+        );
+        assert_eq!(
+            error("[profile]\nname = X\n[field a]\nblock = 1\nsize = 5\n"),
+            at(5, ParseErrorKind::InvalidValue)
+        );
+        // Errors found once the field is complete point at the offending key
+        assert_eq!(
+            error("[profile]\nname = X\n[field a]\ntype = flags\nblock = 1\nbits = 9:Hi\n"),
+            at(6, ParseErrorKind::InvalidValue)
+        );
+        assert_eq!(
+            error("[profile]\nname = X\n[field a]\ntype = bcd\nblock = 1\n"),
+            at(4, ParseErrorKind::InvalidValue)
+        );
+        assert_eq!(
+            error("[profile]\nname = X\n[field a]\nblock = 255\nsize = 4\noffset = 2\n"),
+            at(6, ParseErrorKind::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn parses_checksum_fields() {
+        let field = |keys: &str| {
+            let text = alloc::format!("[profile]\nname = X\n[field sum]\nblock = 24\n{keys}");
+            parse(&text).map(|mut p| p.remove(0).fields.remove(0).kind)
+        };
+        assert_eq!(
+            field("type = checksum\nalgorithm = sum8\ncovers = B020:0-21\n"),
+            Ok(FieldKind::Checksum {
+                algorithm: Algorithm::Sum8,
+                start: 80,
+                len: 8,
+            })
+        );
+        assert_eq!(
+            field("size = 2\ntype = checksum\nalgorithm = CRC-16/KERMIT\ncovers = 8:0-9:3\n"),
+            Ok(FieldKind::Checksum {
+                algorithm: Algorithm::from_name("CRC-16/KERMIT").unwrap(),
+                start: 32,
+                len: 8,
+            })
+        );
+        let invalid = |line| {
+            Err(ParseError {
+                line,
+                kind: ParseErrorKind::InvalidValue,
+            })
+        };
+        assert_eq!(field("type = checksum\nalgorithm = md5\n"), invalid(6));
+        // Both keys are needed, the width must match the size and the field
+        // may not cover its own bytes
+        assert_eq!(field("type = checksum\nalgorithm = xor8\n"), invalid(5));
+        assert_eq!(
+            field("type = checksum\nalgorithm = CRC-16/KERMIT\ncovers = 8\n"),
+            invalid(6)
+        );
+        assert_eq!(
+            field("type = checksum\nalgorithm = xor8\ncovers = 23-24\n"),
+            invalid(7)
+        );
+    }
+
+    #[test]
+    fn builtin_profile_matches_ebs_dumps() {
+        let profiles = ProfileSet::builtin();
+        assert!(!profiles.is_empty());
+        let mut data = ebs_dump();
+        let ebs = profiles.find_match(&data).expect("no match");
+        assert_eq!(ebs.name, "EBS XI32001");
+        let date = ebs.fields.iter().find(|f| f.name == "use_before").unwrap();
+        let mut text = String::new();
+        date.format_value(date.read(&data), &mut text).unwrap();
+        assert_eq!(text, "2024-06-11");
+
+        data.blocks[20][3] = 0x02;
+        assert!(profiles.find_match(&data).is_none());
+    }
+
+    #[test]
+    fn more_specific_profile_wins() {
+        let mut profiles = ProfileSet::builtin();
+        profiles
+            .load_str("[profile]\nname = Any ST\nuid_prefix = D0\n")
+            .unwrap();
+        assert_eq!(
+            profiles.find_match(&ebs_dump()).unwrap().name,
+            "EBS XI32001"
+        );
+        // Loading a profile under an existing name replaces it
+        let count = profiles.len();
+        profiles
+            .load_str(
+                "[profile]\nname = Any ST\nchip = ST25TB04K\nuid_prefix = D0 02\n\
+                 match = 22: 2E 4A 00 00\nmatch = 20: 27 ?? ?? ??\n",
+            )
+            .unwrap();
+        assert_eq!(profiles.len(), count);
+        assert_eq!(profiles.find_match(&ebs_dump()).unwrap().name, "Any ST");
+    }
+}
@@ -0,0 +1,344 @@
+//! Reading, writing and formatting profile field values
+
+use core::fmt::{self, Write};
+
+use super::date::{civil_from_days, days_from_civil, days_in_month};
+use super::{Endian, Field, FieldKind};
+use crate::analysis::checksum;
+use crate::protocol::st25tb::ChipData;
+
+impl Field {
+    fn byte_pos(&self, i: usize) -> (usize, usize) {
+        let pos = self.block * 4 + self.offset + i;
+        (pos / 4, pos % 4)
+    }
+
+    pub fn max_value(&self) -> u32 {
+        if self.size >= 4 {
+            u32::MAX
+        } else {
+            (1u32 << (self.size * 8)) - 1
+        }
+    }
+
+    /// Digits of the largest value, for decimal fields.
+    pub fn digits(&self) -> usize {
+        let mut digits = 1;
+        let mut v = self.max_value() / 10;
+        while v > 0 {
+            digits += 1;
+            v /= 10;
+        }
+        digits
+    }
+
+    /// Parts edited one at a time: nibbles, digits, year/month/day or flags.
+    pub fn parts(&self) -> usize {
+        match &self.kind {
+            FieldKind::Hex => self.size * 2,
+            FieldKind::Decimal => self.digits(),
+            FieldKind::Date { .. } => 3,
+            FieldKind::Flags(bits) => bits.len().max(1),
+            FieldKind::Enum(_) | FieldKind::Otp | FieldKind::Checksum { .. } => 1,
+        }
+    }
+
+    /// `value` with `part` stepped by `delta`. Nibbles, months, days and enum
+    /// choices wrap around, digits and years stop at the field's range, flags
+    /// toggle.
+    pub fn adjust(&self, value: u32, part: usize, delta: i32) -> u32 {
+        let max = self.max_value();
+        match &self.kind {
+            FieldKind::Hex => {
+                let shift = (self.size * 2 - 1 - part) * 4;
+                let nibble = ((value >> shift) & 0x0F) as i32;
+                let nibble = (nibble + delta) & 0x0F;
+                (value & !(0x0F << shift)) | ((nibble as u32) << shift)
+            }
+            FieldKind::Decimal => {
+                let step = 10u32.pow((self.digits() - 1 - part) as u32);
+                if delta > 0 {
+                    value.saturating_add(step).min(max)
+                } else {
+                    value.saturating_sub(step)
+                }
+            }
+            FieldKind::Date { epoch } => {
+                let (mut y, mut m, mut d) = civil_from_days(epoch.wrapping_add(value as i32));
+                match part {
+                    0 => y += delta,
+                    1 => m = ((m as i32 - 1 + delta).rem_euclid(12) + 1) as u32,
+                    _ => {
+                        let dim = days_in_month(y, m) as i32;
+                        d = ((d as i32 - 1 + delta).rem_euclid(dim) + 1) as u32;
+                    }
+                }
+                d = d.min(days_in_month(y, m));
+                let days = days_from_civil(y, m, d) - epoch;
+                if days < 0 {
+                    0
+                } else {
+                    (days as u32).min(max)
+                }
+            }
+            FieldKind::Enum(choices) => {
+                if choices.is_empty() {
+                    value
+                } else {
+                    let next = match choices.iter().position(|(v, _)| *v == value) {
+                        Some(i) => (i as i32 + delta).rem_euclid(choices.len() as i32) as usize,
+                        None => 0,
+                    };
+                    choices[next].0
+                }
+            }
+            FieldKind::Flags(bits) => match bits.get(part) {
+                Some((bit, _)) => value ^ (1 << bit),
+                None => value,
+            },
+            // Only changed bit by bit through the OTP view
+            FieldKind::Otp => value,
+            // Follows the bytes it covers
+            FieldKind::Checksum { .. } => value,
+        }
+    }
+
+    pub fn read(&self, data: &ChipData) -> u32 {
+        let mut value = 0u32;
+        for i in 0..self.size {
+            let (block, byte) = self.byte_pos(i);
+            let b = data.blocks[block][byte] as u32;
+            value |= match self.endian {
+                Endian::Little => b << (i * 8),
+                Endian::Big => b << ((self.size - 1 - i) * 8),
+            };
+        }
+        value
+    }
+
+    pub fn write(&self, data: &mut ChipData, value: u32) {
+        for i in 0..self.size {
+            let (block, byte) = self.byte_pos(i);
+            let shift = match self.endian {
+                Endian::Little => i * 8,
+                Endian::Big => (self.size - 1 - i) * 8,
+            };
+            data.blocks[block][byte] = (value >> shift) as u8;
+        }
+    }
+
+    /// What a checksum field should hold, `None` for other fields or when the
+    /// field or its coverage lies past the end of the dump.
+    pub fn checksum(&self, data: &ChipData) -> Option<u32> {
+        let FieldKind::Checksum {
+            algorithm,
+            start,
+            len,
+        } = &self.kind
+        else {
+            return None;
+        };
+        let end = (start + len).max(self.block * 4 + self.offset + self.size);
+        if end > data.block_count * 4 {
+            return None;
+        }
+        let bytes = (*start..start + len).map(|pos| data.blocks[pos / 4][pos % 4]);
+        let mut covered = [0u8; checksum::MAX_RANGE];
+        for (slot, byte) in covered.iter_mut().zip(bytes) {
+            *slot = byte;
+        }
+        Some(algorithm.compute(&covered[..*len]))
+    }
+
+    /// Blocks touched by this field, first and last inclusive.
+    pub fn blocks(&self) -> (usize, usize) {
+        (self.byte_pos(0).0, self.byte_pos(self.size - 1).0)
+    }
+
+    pub fn format_value<W: Write>(&self, value: u32, out: &mut W) -> fmt::Result {
+        match &self.kind {
+            FieldKind::Hex | FieldKind::Checksum { .. } => {
+                write!(out, "{:0width$X}", value, width = self.size * 2)
+            }
+            FieldKind::Decimal => write!(out, "{}", value),
+            FieldKind::Date { epoch } => {
+                let (y, m, d) = civil_from_days(epoch.wrapping_add(value as i32));
+                write!(out, "{:04}-{:02}-{:02}", y, m, d)
+            }
+            FieldKind::Enum(choices) => match choices.iter().find(|(v, _)| *v == value) {
+                Some((_, label)) => out.write_str(label),
+                None => write!(out, "?({})", value),
+            },
+            FieldKind::Otp => {
+                let bits = self.size as u32 * 8;
+                let used = bits - value.count_ones();
+                write!(out, "{}/{} used", used, bits)
+            }
+            FieldKind::Flags(bits) => {
+                let mut any = false;
+                for (bit, label) in bits {
+                    if value & (1 << bit) != 0 {
+                        if any {
+                            out.write_char('|')?;
+                        }
+                        out.write_str(label)?;
+                        any = true;
+                    }
+                }
+                if !any {
+                    out.write_char('-')?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn field(block: usize, offset: usize, size: usize, endian: Endian, kind: FieldKind) -> Field {
+        Field {
+            name: String::from("f"),
+            block,
+            offset,
+            size,
+            endian,
+            kind,
+        }
+    }
+
+    fn text(field: &Field, value: u32) -> String {
+        let mut out = String::new();
+        field.format_value(value, &mut out).unwrap();
+        out
+    }
+
+    fn date(epoch: i32) -> Field {
+        field(8, 0, 2, Endian::Big, FieldKind::Date { epoch })
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> u32 {
+        (days_from_civil(year, month, day) - days_from_civil(1992, 1, 1)) as u32
+    }
+
+    #[test]
+    fn reads_and_writes_both_byte_orders() {
+        let mut data = ChipData {
+            block_count: 16,
+            ..ChipData::default()
+        };
+        // Three bytes from the last byte of block 8 on, across the boundary
+        let le = field(8, 3, 3, Endian::Little, FieldKind::Hex);
+        le.write(&mut data, 0x00AB_CDEF);
+        assert_eq!(data.blocks[8], [0, 0, 0, 0xEF]);
+        assert_eq!(data.blocks[9], [0xCD, 0xAB, 0, 0]);
+        assert_eq!(le.read(&data), 0xAB_CDEF);
+        assert_eq!(le.blocks(), (8, 9));
+
+        let be = field(8, 3, 3, Endian::Big, FieldKind::Hex);
+        assert_eq!(be.read(&data), 0xEF_CDAB);
+        be.write(&mut data, 0x12_3456);
+        assert_eq!(
+            (data.blocks[8][3], data.blocks[9]),
+            (0x12, [0x34, 0x56, 0, 0])
+        );
+        // Bits past the field's size are dropped
+        le.write(&mut data, 0xFF12_3456);
+        assert_eq!(le.read(&data), 0x12_3456);
+        assert_eq!(le.max_value(), 0xFF_FFFF);
+        assert_eq!(
+            field(0, 0, 4, Endian::Big, FieldKind::Hex).max_value(),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn formats_each_kind() {
+        let hex = field(8, 0, 2, Endian::Little, FieldKind::Hex);
+        assert_eq!(text(&hex, 0x0A), "000A");
+        let dec = field(8, 0, 2, Endian::Little, FieldKind::Decimal);
+        assert_eq!(text(&dec, 1234), "1234");
+        assert_eq!(text(&date(days_from_civil(1992, 1, 1)), 0), "1992-01-01");
+        assert_eq!(
+            text(&date(days_from_civil(1992, 1, 1)), day(2024, 6, 11)),
+            "2024-06-11"
+        );
+
+        let colours = vec![(0, String::from("Black")), (3, String::from("Cyan"))];
+        let colour = field(8, 0, 1, Endian::Little, FieldKind::Enum(colours));
+        assert_eq!(text(&colour, 3), "Cyan");
+        assert_eq!(text(&colour, 2), "?(2)");
+
+        let bits = vec![(0, String::from("Sealed")), (7, String::from("Empty"))];
+        let flags = field(8, 0, 1, Endian::Little, FieldKind::Flags(bits));
+        assert_eq!(text(&flags, 0x81), "Sealed|Empty");
+        assert_eq!(text(&flags, 0x80), "Empty");
+        assert_eq!(text(&flags, 0x7E), "-");
+
+        let otp = field(3, 0, 4, Endian::Little, FieldKind::Otp);
+        assert_eq!(text(&otp, 0xFFFF_FFF0), "4/32 used");
+    }
+
+    #[test]
+    fn steps_hex_and_decimal_parts() {
+        let hex = field(8, 0, 2, Endian::Little, FieldKind::Hex);
+        assert_eq!(hex.parts(), 4);
+        // Nibbles wrap without carrying into their neighbour
+        assert_eq!(hex.adjust(0x12FF, 3, 1), 0x12F0);
+        assert_eq!(hex.adjust(0x12FF, 0, -2), 0xF2FF);
+
+        let dec = field(8, 0, 1, Endian::Little, FieldKind::Decimal);
+        assert_eq!((dec.digits(), dec.parts()), (3, 3));
+        assert_eq!(dec.adjust(42, 1, 1), 52);
+        assert_eq!(dec.adjust(42, 0, 1), 142);
+        // Digits stop at the ends of the range
+        assert_eq!(dec.adjust(242, 0, 1), 255);
+        assert_eq!(dec.adjust(42, 0, -1), 0);
+        let wide = field(8, 0, 4, Endian::Little, FieldKind::Decimal);
+        assert_eq!(wide.digits(), 10);
+        assert_eq!(wide.adjust(u32::MAX - 1, 9, 1), u32::MAX);
+    }
+
+    #[test]
+    fn steps_dates_within_the_field() {
+        let f = date(days_from_civil(1992, 1, 1));
+        assert_eq!(f.parts(), 3);
+        let jan31 = day(2024, 1, 31);
+        assert_eq!(f.adjust(jan31, 0, 1), day(2025, 1, 31));
+        // Months and days wrap without changing the year or month
+        assert_eq!(f.adjust(day(2024, 12, 15), 1, 1), day(2024, 1, 15));
+        assert_eq!(f.adjust(jan31, 2, 1), day(2024, 1, 1));
+        assert_eq!(f.adjust(day(2024, 1, 1), 2, -1), jan31);
+        // A day past the end of the new month is clamped to it
+        assert_eq!(f.adjust(jan31, 1, 1), day(2024, 2, 29));
+        assert_eq!(f.adjust(day(2024, 2, 29), 0, 1), day(2025, 2, 28));
+        // Nothing before the epoch or past the field's range
+        assert_eq!(f.adjust(day(1992, 6, 1), 0, -1), 0);
+        assert_eq!(f.adjust(0xFFF0, 0, 1), 0xFFFF);
+    }
+
+    #[test]
+    fn steps_choices_and_flags() {
+        let colours = vec![(0, String::from("Black")), (3, String::from("Cyan"))];
+        let colour = field(8, 0, 1, Endian::Little, FieldKind::Enum(colours));
+        assert_eq!(colour.adjust(0, 0, 1), 3);
+        assert_eq!(colour.adjust(3, 0, 1), 0);
+        assert_eq!(colour.adjust(0, 0, -1), 3);
+        // An unknown value starts over at the first choice
+        assert_eq!(colour.adjust(2, 0, 1), 0);
+
+        let bits = vec![(0, String::from("Sealed")), (7, String::from("Empty"))];
+        let flags = field(8, 0, 1, Endian::Little, FieldKind::Flags(bits));
+        assert_eq!(flags.parts(), 2);
+        assert_eq!(flags.adjust(0x01, 1, 1), 0x81);
+        assert_eq!(flags.adjust(0x81, 0, -1), 0x80);
+
+        // Changed through the OTP view and the checksum rules only
+        let otp = field(3, 0, 4, Endian::Little, FieldKind::Otp);
+        assert_eq!(otp.adjust(0xFFFF_FFF0, 0, -1), 0xFFFF_FFF0);
+    }
+}
@@ -0,0 +1,421 @@
+//! Request/response protocol for host tools on the USB Serial/JTAG link
+//!
+//! Frames share the link with log text. Each one is COBS-encoded and sent
+//! between zero bytes, which never occur in text:
+//!
+//! ```text
+//! 00 | COBS( code | seq | payload.. | CRC-32 LE ) | 00
+//! ```
+//!
+//! A request's `code` is a `Command`; the response echoes `seq` with
+//! `code | RESPONSE` and starts its payload with a `Status`. Error responses
+//! carry a short UTF-8 message after the status. Dumps travel in the binary
+//! dump file format (`drivers::storage::format`).
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::drivers::storage::DumpInfo;
+use crate::protocol::st25tb::ChipType;
+use crate::util::cobs;
+use crate::util::crc::crc32;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const RESPONSE: u8 = 0x80;
+/// Largest decoded frame; a 256-block dump plus header fits.
+pub const FRAME_MAX: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// -> `Info`
+    GetInfo = 0x01,
+    /// Reads the tag on the antenna into the editor -> dump
+    ReadChip = 0x02,
+    /// Dump, or empty for the editor's dump -> nothing
+    WriteChip = 0x03,
+    /// Stored dump name, or empty for the editor's dump -> dump
+    GetDump = 0x04,
+    /// name length (u8), name, dump; an empty name loads it into the editor
+    PutDump = 0x05,
+    /// -> count (u16), then per dump: name length (u8), name, UID (LSB first),
+    /// chip type code, block count (u16), timestamp (u32)
+    ListDumps = 0x06,
+    /// Stored dump name -> nothing
+    DeleteDump = 0x07,
+    /// PN532 command code and parameters -> response data
+    Pn532 = 0x08,
+    /// Unix time (u32) for the read dates of dumps -> nothing
+    SetTime = 0x09,
+}
+
+impl Command {
+    const ALL: [Command; 9] = [
+        Command::GetInfo,
+        Command::ReadChip,
+        Command::WriteChip,
+        Command::GetDump,
+        Command::PutDump,
+        Command::ListDumps,
+        Command::DeleteDump,
+        Command::Pn532,
+        Command::SetTime,
+    ];
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|&c| c as u8 == code)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok = 0,
+    UnknownCommand = 1,
+    BadRequest = 2,
+    /// No dump loaded in the editor.
+    NoData = 3,
+    /// Tag missing or not answering.
+    Tag = 4,
+    Storage = 5,
+    /// No storage available (no SD card, flash store not mounted).
+    NoStorage = 6,
+    Pn532 = 7,
+}
+
+impl Status {
+    pub fn from_code(code: u8) -> Option<Self> {
+        [
+            Status::Ok,
+            Status::UnknownCommand,
+            Status::BadRequest,
+            Status::NoData,
+            Status::Tag,
+            Status::Storage,
+            Status::NoStorage,
+            Status::Pn532,
+        ]
+        .into_iter()
+        .find(|&s| s as u8 == code)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameError {
+    Cobs,
+    TooShort,
+    TooLong,
+    BadCrc,
+}
+
+/// A decoded frame, request or response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub code: u8,
+    pub seq: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Wire bytes, delimiters included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(self.payload.len() + 6);
+        body.push(self.code);
+        body.push(self.seq);
+        body.extend_from_slice(&self.payload);
+        let crc = crc32(&body);
+        body.extend_from_slice(&crc.to_le_bytes());
+
+        let mut out = cobs::encode(&body);
+        out.insert(0, 0);
+        out.push(0);
+        out
+    }
+
+    /// Decodes the bytes between two delimiters.
+    pub fn decode(encoded: &[u8]) -> Result<Self, FrameError> {
+        let body = cobs::decode(encoded).ok_or(FrameError::Cobs)?;
+        if body.len() < 6 {
+            return Err(FrameError::TooShort);
+        }
+        let (data, crc) = body.split_at(body.len() - 4);
+        if crc32(data) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Err(FrameError::BadCrc);
+        }
+        Ok(Self {
+            code: data[0],
+            seq: data[1],
+            payload: data[2..].to_vec(),
+        })
+    }
+
+    /// Response to this request.
+    pub fn reply(&self, status: Status, payload: &[u8]) -> Frame {
+        let mut out = Vec::with_capacity(payload.len() + 1);
+        out.push(status as u8);
+        out.extend_from_slice(payload);
+        Frame {
+            code: self.code | RESPONSE,
+            seq: self.seq,
+            payload: out,
+        }
+    }
+}
+
+/// What a received byte turned out to be.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// Part of a frame still being received.
+    None,
+    /// Outside any frame.
+    Text(u8),
+    Frame(Result<Frame, FrameError>),
+}
+
+/// Splits the incoming byte stream into frames and plain text. A zero byte
+/// opens a frame and the next zero after some content closes it.
+#[derive(Default)]
+pub struct FrameReader {
+    buf: Vec<u8>,
+    in_frame: bool,
+    overflow: bool,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, byte: u8) -> Input {
+        if !self.in_frame {
+            if byte == 0 {
+                self.in_frame = true;
+                return Input::None;
+            }
+            return Input::Text(byte);
+        }
+        if byte != 0 {
+            if self.buf.len() < FRAME_MAX + FRAME_MAX / 254 + 8 {
+                self.buf.push(byte);
+            } else {
+                self.overflow = true;
+            }
+            return Input::None;
+        }
+        if self.buf.is_empty() {
+            // back-to-back delimiters
+            return Input::None;
+        }
+        self.in_frame = false;
+        let encoded = core::mem::take(&mut self.buf);
+        if core::mem::take(&mut self.overflow) {
+            return Input::Frame(Err(FrameError::TooLong));
+        }
+        Input::Frame(Frame::decode(&encoded))
+    }
+}
+
+/// `GetInfo` response payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Info {
+    pub protocol: u8,
+    /// 0 none, 1 SD card, 2 internal flash
+    pub storage: u8,
+    pub dump_loaded: bool,
+    /// PN532 IC, version and revision, zero if it did not answer.
+    pub pn532: [u8; 3],
+    pub firmware: String,
+}
+
+impl Info {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(self.protocol);
+        out.push(self.storage);
+        out.push(self.dump_loaded as u8);
+        out.extend_from_slice(&self.pn532);
+        out.extend_from_slice(self.firmware.as_bytes());
+        out
+    }
+
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 6 {
+            return None;
+        }
+        Some(Self {
+            protocol: payload[0],
+            storage: payload[1],
+            dump_loaded: payload[2] != 0,
+            pn532: [payload[3], payload[4], payload[5]],
+            firmware: String::from_utf8_lossy(&payload[6..]).into(),
+        })
+    }
+}
+
+pub fn encode_list(entries: &[DumpInfo]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for e in entries {
+        out.push(e.name.len() as u8);
+        out.extend_from_slice(e.name.as_bytes());
+        out.extend_from_slice(&e.uid);
+        out.push(e.chip_type.code());
+        out.extend_from_slice(&(e.block_count as u16).to_le_bytes());
+        out.extend_from_slice(&e.timestamp.to_le_bytes());
+    }
+    out
+}
+
+pub fn decode_list(payload: &[u8]) -> Option<Vec<DumpInfo>> {
+    let count = u16::from_le_bytes([*payload.first()?, *payload.get(1)?]) as usize;
+    let mut entries = Vec::with_capacity(count);
+    let mut rest = &payload[2..];
+    for _ in 0..count {
+        let len = *rest.first()? as usize;
+        let name = core::str::from_utf8(rest.get(1..1 + len)?).ok()?;
+        let fixed = rest.get(1 + len..1 + len + 15)?;
+        let mut uid = [0u8; 8];
+        uid.copy_from_slice(&fixed[..8]);
+        entries.push(DumpInfo {
+            name: name.into(),
+            uid,
+            chip_type: ChipType::from_code(fixed[8]),
+            block_count: u16::from_le_bytes([fixed[9], fixed[10]]) as usize,
+            timestamp: u32::from_le_bytes([fixed[11], fixed[12], fixed[13], fixed[14]]),
+        });
+        rest = &rest[1 + len + 15..];
+    }
+    Some(entries)
+}
+
+/// `PutDump` payload.
+pub fn encode_put(name: &str, dump: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + name.len() + dump.len());
+    out.push(name.len() as u8);
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(dump);
+    out
+}
+
+pub fn decode_put(payload: &[u8]) -> Option<(&str, &[u8])> {
+    let len = *payload.first()? as usize;
+    let name = core::str::from_utf8(payload.get(1..1 + len)?).ok()?;
+    Some((name, &payload[1 + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn read_all(
+        reader: &mut FrameReader,
+        bytes: &[u8],
+    ) -> (Vec<u8>, Vec<Result<Frame, FrameError>>) {
+        let (mut text, mut frames) = (Vec::new(), Vec::new());
+        for &b in bytes {
+            match reader.push(b) {
+                Input::None => {}
+                Input::Text(c) => text.push(c),
+                Input::Frame(f) => frames.push(f),
+            }
+        }
+        (text, frames)
+    }
+
+    #[test]
+    fn frames_between_log_text() {
+        let request = Frame {
+            code: Command::GetDump as u8,
+            seq: 7,
+            payload: vec![0x00, 0x01, 0x00, 0xFF],
+        };
+        let response = request.reply(Status::Ok, &[0; 300]);
+        let mut stream = b"I (12) boot\r\n".to_vec();
+        stream.extend(request.encode());
+        stream.extend(b"log");
+        stream.extend(response.encode());
+        // Back-to-back delimiters between frames are skipped
+        stream.push(0);
+        stream.extend(request.encode());
+
+        let (text, frames) = read_all(&mut FrameReader::new(), &stream);
+        assert_eq!(text, b"I (12) boot\r\nlog");
+        assert_eq!(
+            frames,
+            [Ok(request.clone()), Ok(response.clone()), Ok(request)]
+        );
+        assert_eq!(response.code, Command::GetDump as u8 | RESPONSE);
+        assert_eq!(response.payload[0], Status::Ok as u8);
+    }
+
+    #[test]
+    fn rejects_damaged_frames() {
+        let frame = Frame {
+            code: 1,
+            seq: 2,
+            payload: vec![3, 4, 5],
+        };
+        let mut wire = frame.encode();
+        wire[4] ^= 0x01;
+        let (_, frames) = read_all(&mut FrameReader::new(), &wire);
+        assert_eq!(frames, [Err(FrameError::BadCrc)]);
+        assert_eq!(
+            Frame::decode(&[0x03, 0x01, 0x02]),
+            Err(FrameError::TooShort)
+        );
+        assert_eq!(Frame::decode(&[0x09, 0x01]), Err(FrameError::Cobs));
+
+        let mut reader = FrameReader::new();
+        let huge = vec![0x01; FRAME_MAX * 2];
+        let (_, frames) = read_all(&mut reader, &[&[0][..], &huge, &[0]].concat());
+        assert_eq!(frames, [Err(FrameError::TooLong)]);
+        // The reader recovers for the next frame
+        let (_, frames) = read_all(&mut reader, &frame.encode());
+        assert_eq!(frames, [Ok(frame)]);
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        for command in Command::ALL {
+            assert_eq!(Command::from_code(command as u8), Some(command));
+        }
+        assert_eq!(Command::from_code(0), None);
+        assert_eq!(Status::from_code(Status::Ok as u8), Some(Status::Ok));
+    }
+
+    #[test]
+    fn payloads_round_trip() {
+        let info = Info {
+            protocol: PROTOCOL_VERSION,
+            storage: 1,
+            dump_loaded: true,
+            pn532: [0x32, 1, 6],
+            firmware: "0.1.0".into(),
+        };
+        assert_eq!(Info::decode(&info.encode()), Some(info));
+
+        let list = vec![
+            DumpInfo {
+                name: "D00230123456789A-001.DMP".into(),
+                uid: [1, 2, 3, 4, 5, 6, 7, 8],
+                chip_type: ChipType::St25tb04k,
+                block_count: 128,
+                timestamp: 1_760_000_000,
+            },
+            DumpInfo {
+                name: "x".into(),
+                uid: [0; 8],
+                chip_type: ChipType::Sri512,
+                block_count: 16,
+                timestamp: 0,
+            },
+        ];
+        let encoded = encode_list(&list);
+        assert_eq!(decode_list(&encoded), Some(list));
+        assert_eq!(decode_list(&encoded[..encoded.len() - 1]), None);
+        assert_eq!(decode_list(&[0, 0]), Some(vec![]));
+
+        let put = encode_put("name.dmp", b"ST25...");
+        assert_eq!(decode_put(&put), Some(("name.dmp", &b"ST25..."[..])));
+        assert_eq!(decode_put(&[9, b'a']), None);
+    }
+}
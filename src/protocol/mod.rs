@@ -1,3 +1,4 @@
+pub mod host;
 pub mod st25tb;
 
 pub use st25tb::St25tb;
@@ -1,4 +1,5 @@
 use crate::drivers::{pn532::Pn532Error, Pn532};
+use alloc::vec::Vec;
 use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal::i2c::I2c;
 
@@ -15,8 +16,22 @@ pub struct ChipData {
     pub uid: [u8; 8],
     pub blocks: [[u8; 4]; 256],
     pub block_count: usize,
+    pub flags: [u8; 256],
+    /// System block 255 (OTP_Lock_Reg and friends), if it could be read.
+    pub system: Option<[u8; 4]>,
+    /// Unix time of the read, 0 if the clock was not set.
+    pub timestamp: u32,
 }
 
+/// Counter block read back different values in a row (interrupted decrement).
+pub const FLAG_TORN: u8 = 0x01;
+/// Write-protected by the lock register.
+pub const FLAG_LOCKED: u8 = 0x02;
+/// Did not answer READ_BLOCK; contents are unknown.
+pub const FLAG_UNREADABLE: u8 = 0x04;
+/// OTP or counter block (1 -> 0 only); set in saved dumps for other tools.
+pub const FLAG_OTP: u8 = 0x08;
+
 impl Default for ChipData {
     fn default() -> Self {
         Self {
@@ -24,10 +39,249 @@ impl Default for ChipData {
             uid: [0u8; 8],
             blocks: [[0u8; 4]; 256],
             block_count: 0,
+            flags: [0u8; 256],
+            system: None,
+            timestamp: 0,
+        }
+    }
+}
+
+impl ChipData {
+    /// UID as printed on labels and by other tools (the tag sends it LSB first).
+    pub fn uid_msb_first(&self) -> [u8; 8] {
+        let mut uid = self.uid;
+        uid.reverse();
+        uid
+    }
+
+    pub fn chip_type(&self) -> ChipType {
+        ChipType::from_uid(&self.uid)
+    }
+
+    /// Sets `FLAG_LOCKED` on every block the system block's lock bits protect.
+    pub fn apply_lock_register(&mut self) {
+        let Some(system) = self.system else {
+            return;
+        };
+        let chip = self.chip_type();
+        for block in 0..self.block_count {
+            if chip.is_locked(&system, block) {
+                self.flags[block] |= FLAG_LOCKED;
+            } else {
+                self.flags[block] &= !FLAG_LOCKED;
+            }
+        }
+    }
+
+    /// Contents of `index` (`SYSTEM_BLOCK` for the system block), `None` when
+    /// it is past the end or could not be read.
+    pub fn block(&self, index: usize) -> Option<[u8; 4]> {
+        if index == SYSTEM_BLOCK {
+            self.system
+        } else if index < self.block_count && self.flags[index] & FLAG_UNREADABLE == 0 {
+            Some(self.blocks[index])
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self, block: usize) -> bool {
+        self.flags[block] & FLAG_LOCKED != 0
+    }
+
+    /// Value of a binary counter block (blocks 5 and 6), stored LSB first.
+    pub fn counter(&self, block: usize) -> u32 {
+        u32::from_le_bytes(self.blocks[block])
+    }
+}
+
+/// Counters start at `0xFFFFFFFF` and only count down.
+pub fn counter_used(value: u32) -> u32 {
+    u32::MAX - value
+}
+
+/// Decrementing any of bits b21-b31 of block 6 reloads the resettable OTP area (blocks 0-4).
+pub fn counter_reloads_otp(block: usize, old: u32, new: u32) -> bool {
+    block == 6 && (old ^ new) >> 21 != 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CounterState {
+    pub value: u32,
+    /// False when two consecutive reads disagree, i.e. anti-tearing is still settling.
+    pub stable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CounterWrite {
+    Applied {
+        reloaded_otp: bool,
+    },
+    /// Anti-tearing kept the previous value.
+    Unchanged,
+    /// Read back neither the old nor the requested value.
+    Torn(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChipType {
+    St25tb512At,
+    St25tb512Ac,
+    Sri512,
+    St25tb02k,
+    St25tb04k,
+    Srix4k,
+    Unknown,
+}
+
+impl ChipType {
+    const ALL: [ChipType; 6] = [
+        ChipType::St25tb512At,
+        ChipType::St25tb512Ac,
+        ChipType::Sri512,
+        ChipType::St25tb02k,
+        ChipType::St25tb04k,
+        ChipType::Srix4k,
+    ];
+
+    /// Stable number used in dump files.
+    pub fn code(&self) -> u8 {
+        match self {
+            ChipType::Unknown => 0,
+            ChipType::St25tb512At => 1,
+            ChipType::St25tb512Ac => 2,
+            ChipType::Sri512 => 3,
+            ChipType::St25tb02k => 4,
+            ChipType::St25tb04k => 5,
+            ChipType::Srix4k => 6,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|t| t.code() == code)
+            .unwrap_or(ChipType::Unknown)
+    }
+
+    /// Decodes the 6-bit product code that follows the `D0 02` manufacturer prefix.
+    pub fn from_uid(uid: &[u8; 8]) -> Self {
+        if uid[7] != 0xD0 || uid[6] != 0x02 {
+            return ChipType::Unknown;
+        }
+        match uid[5] >> 2 {
+            0x0 | 0x3 => ChipType::Srix4k,
+            0x4 => ChipType::Sri512,
+            0x6 => ChipType::St25tb512Ac,
+            0x7 => ChipType::St25tb04k,
+            0xC => ChipType::St25tb512At,
+            0xF => ChipType::St25tb02k,
+            _ => ChipType::Unknown,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChipType::St25tb512At => "ST25TB512-AT",
+            ChipType::St25tb512Ac => "ST25TB512-AC",
+            ChipType::Sri512 => "SRI512",
+            ChipType::St25tb02k => "ST25TB02K",
+            ChipType::St25tb04k => "ST25TB04K",
+            ChipType::Srix4k => "SRIX4K",
+            ChipType::Unknown => "UNKNOWN",
+        }
+    }
+
+    pub fn block_count(&self) -> usize {
+        match self {
+            ChipType::St25tb512At | ChipType::St25tb512Ac | ChipType::Sri512 => 16,
+            ChipType::St25tb02k => 64,
+            ChipType::St25tb04k | ChipType::Srix4k | ChipType::Unknown => 128,
+        }
+    }
+
+    /// Bit of the system block (b0 = LSB of its first byte) that write-protects `block`
+    /// when cleared. The 512-bit parts have one bit per block in b16-b31; the larger
+    /// parts lock 16-block groups with b24-b31, the first group starting at block 7.
+    pub fn lock_bit(&self, block: usize) -> Option<u32> {
+        match self {
+            ChipType::St25tb512At | ChipType::St25tb512Ac | ChipType::Sri512 => {
+                (block < 16).then(|| 16 + block as u32)
+            }
+            _ => (block >= 7 && block < self.block_count()).then(|| 24 + (block / 16) as u32),
+        }
+    }
+
+    /// Blocks sharing `block`'s lock bit, first and last inclusive.
+    pub fn lock_group(&self, block: usize) -> Option<(usize, usize)> {
+        let bit = self.lock_bit(block)?;
+        let mut first = block;
+        while first > 0 && self.lock_bit(first - 1) == Some(bit) {
+            first -= 1;
+        }
+        let mut last = block;
+        while self.lock_bit(last + 1) == Some(bit) {
+            last += 1;
+        }
+        Some((first, last))
+    }
+
+    pub fn is_locked(&self, system: &[u8; 4], block: usize) -> bool {
+        match self.lock_bit(block) {
+            Some(bit) => u32::from_le_bytes(*system) & (1 << bit) == 0,
+            None => false,
+        }
+    }
+
+    pub fn block_kind(&self, block: usize) -> BlockKind {
+        match block {
+            0..=4 => BlockKind::ResettableOtp,
+            5 | 6 => BlockKind::Counter,
+            SYSTEM_BLOCK => BlockKind::System,
+            _ => BlockKind::User,
         }
     }
 }
 
+pub const SYSTEM_BLOCK: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockKind {
+    /// Bits only go 1 -> 0 until the counter in block 6 reloads them.
+    ResettableOtp,
+    Counter,
+    User,
+    System,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockError {
+    /// The tag on the reader is not the one the dump came from.
+    WrongTag,
+    /// The tag's chip type has no lock bit for the block.
+    NotLockable,
+    Pn532(Pn532Error),
+}
+
+impl From<Pn532Error> for LockError {
+    fn from(e: Pn532Error) -> Self {
+        LockError::Pn532(e)
+    }
+}
+
+/// OTP cells only accept writes that clear bits.
+pub fn otp_transition_ok(old: &[u8; 4], new: &[u8; 4]) -> bool {
+    (0..4).all(|i| new[i] & !old[i] == 0)
+}
+
 pub struct St25tb<'a, I2C, IRQ, RST> {
     pn532: &'a mut Pn532<I2C, IRQ, RST>,
     chip_id: u8,
@@ -44,7 +298,11 @@ where
     }
 
     fn delay_ms(&self, ms: u32) {
+        #[cfg(target_os = "none")]
         esp_hal::rom::ets_delay_us(ms * 1000);
+        // The host tool builds this module too
+        #[cfg(not(target_os = "none"))]
+        std::thread::sleep(core::time::Duration::from_millis(ms as u64));
     }
 
     pub fn initiate(&mut self, force: bool) -> Result<u8, Pn532Error> {
@@ -57,7 +315,7 @@ where
 
         for attempt in 0..5 {
             match self.pn532.communicate_thru(&cmd) {
-                Ok(response) if response.len() >= 1 => {
+                Ok(response) if !response.is_empty() => {
                     self.chip_id = response[0];
                     if force {
                         let _ = self.pn532.rf_configuration_retries(0x00);
@@ -80,7 +338,7 @@ where
         let cmd = [CMD_SELECT, id];
         let response = self.pn532.communicate_thru(&cmd)?;
 
-        if response.len() >= 1 && response[0] == id {
+        if !response.is_empty() && response[0] == id {
             Ok(())
         } else {
             Err(Pn532Error::InvalidResponse)
@@ -157,15 +415,36 @@ where
             ..Default::default()
         };
 
+        // Known parts are read in full, marking blocks that fail; otherwise the
+        // first failing block is taken as the end of memory.
+        let chip_type = data.chip_type();
+        let mut failures = 0;
         for i in 0u16..256 {
-            match self.read_block(i as u8) {
+            let known = chip_type != ChipType::Unknown;
+            if known && i as usize >= chip_type.block_count() {
+                break;
+            }
+            let mut result = self.read_block(i as u8);
+            if result.is_err() && known {
+                result = self.read_block(i as u8);
+            }
+            match result {
                 Ok(block) => {
+                    failures = 0;
                     data.blocks[i as usize] = block;
                     data.block_count = (i + 1) as usize;
                     if block != [0xFF, 0xFF, 0xFF, 0xFF] {
                         log::info!("Block {:3}: {:02X?}", i, block);
                     }
                 }
+                // A run of failures means the tag left the field
+                Err(e) if known && failures >= 3 => return Err(e),
+                Err(_) if known => {
+                    failures += 1;
+                    log::warn!("Block {:3}: unreadable", i);
+                    data.flags[i as usize] |= FLAG_UNREADABLE;
+                    data.block_count = (i + 1) as usize;
+                }
                 Err(_) => {
                     log::info!("Total blocks: {}", i);
                     break;
@@ -173,11 +452,187 @@ where
             }
         }
 
+        for i in [5usize, 6] {
+            if i >= data.block_count {
+                continue;
+            }
+            match self.read_counter(i as u8) {
+                Ok(state) if state.stable && state.value == data.counter(i) => {}
+                Ok(state) => {
+                    log::warn!(
+                        "Counter {} unstable: {:08X} then {:08X}",
+                        i,
+                        data.counter(i),
+                        state.value
+                    );
+                    data.flags[i] |= FLAG_TORN;
+                }
+                Err(_) => data.flags[i] |= FLAG_TORN,
+            }
+        }
+
+        match self.read_block(SYSTEM_BLOCK as u8) {
+            Ok(system) => {
+                log::info!("System block: {:02X?}", system);
+                data.system = Some(system);
+                data.apply_lock_register();
+                let locked = data.flags.iter().filter(|&&f| f & FLAG_LOCKED != 0).count();
+                if locked > 0 {
+                    log::warn!("{} blocks are write-protected", locked);
+                }
+            }
+            Err(_) => log::warn!("System block unreadable, lock state unknown"),
+        }
+
         let _ = self.completion();
         let _ = self.pn532.rf_field(false);
         Ok(data)
     }
 
+    /// UID of the tag on the reader, with a field cycle of its own.
+    pub fn read_uid(&mut self) -> Result<[u8; 8], Pn532Error> {
+        let _ = self.pn532.rf_field(false);
+        self.delay_ms(100);
+        let _ = self.pn532.rf_field(true);
+        self.delay_ms(200);
+
+        let result = self
+            .initiate(true)
+            .and_then(|_| self.select(None))
+            .and_then(|_| self.get_uid());
+        let _ = self.completion();
+        let _ = self.pn532.rf_field(false);
+        result
+    }
+
+    /// Permanently write-protects the lock group containing `block`, on the
+    /// tag with `uid` only, and returns the group as the tag's chip type
+    /// lays it out. Irreversible.
+    pub fn lock_block(&mut self, block: usize, uid: &[u8; 8]) -> Result<(usize, usize), LockError> {
+        let _ = self.pn532.rf_field(false);
+        self.delay_ms(100);
+        let _ = self.pn532.rf_field(true);
+        self.delay_ms(200);
+
+        let result = self.lock_selected(block, uid);
+        let _ = self.completion();
+        let _ = self.pn532.rf_field(false);
+        result
+    }
+
+    /// [`lock_block`](Self::lock_block) with the field on, which the caller
+    /// turns off on every path.
+    fn lock_selected(&mut self, block: usize, uid: &[u8; 8]) -> Result<(usize, usize), LockError> {
+        self.initiate(true)?;
+        self.select(None)?;
+        let tag_uid = self.get_uid()?;
+        if tag_uid != *uid {
+            return Err(LockError::WrongTag);
+        }
+        let chip = ChipType::from_uid(&tag_uid);
+        let (Some(bit), Some(group)) = (chip.lock_bit(block), chip.lock_group(block)) else {
+            return Err(LockError::NotLockable);
+        };
+
+        let system = self.read_block(SYSTEM_BLOCK as u8)?;
+        let locked = (u32::from_le_bytes(system) & !(1 << bit)).to_le_bytes();
+        log::warn!("Locking block {} (system bit b{})", block, bit);
+        self.write_block(SYSTEM_BLOCK as u8, &locked)?;
+
+        match self.read_block(SYSTEM_BLOCK as u8) {
+            Ok(v) if chip.is_locked(&v, block) => Ok(group),
+            _ => Err(Pn532Error::InvalidResponse.into()),
+        }
+    }
+
+    pub fn read_counter(&mut self, block: u8) -> Result<CounterState, Pn532Error> {
+        if block != 5 && block != 6 {
+            return Err(Pn532Error::InvalidArgument);
+        }
+        let first = u32::from_le_bytes(self.read_block(block)?);
+        let second = u32::from_le_bytes(self.read_block(block)?);
+        Ok(CounterState {
+            value: second,
+            stable: first == second,
+        })
+    }
+
+    /// Counts a binary counter down by `amount`; the chip ignores any attempt to count up.
+    pub fn decrement_counter(
+        &mut self,
+        block: u8,
+        amount: u32,
+    ) -> Result<CounterWrite, Pn532Error> {
+        let current = self.read_counter(block)?;
+        if !current.stable {
+            return Ok(CounterWrite::Torn(current.value));
+        }
+        if amount == 0 {
+            return Ok(CounterWrite::Applied {
+                reloaded_otp: false,
+            });
+        }
+        let target = current
+            .value
+            .checked_sub(amount)
+            .ok_or(Pn532Error::InvalidArgument)?;
+
+        self.write_block(block, &target.to_le_bytes())?;
+        let after = self.read_counter(block)?;
+
+        let result = if !after.stable {
+            CounterWrite::Torn(after.value)
+        } else if after.value == target {
+            CounterWrite::Applied {
+                reloaded_otp: counter_reloads_otp(block as usize, current.value, target),
+            }
+        } else if after.value == current.value {
+            CounterWrite::Unchanged
+        } else {
+            CounterWrite::Torn(after.value)
+        };
+        log::info!(
+            "Counter {}: {:08X} -> {:08X}: {:?}",
+            block,
+            current.value,
+            target,
+            result
+        );
+        Ok(result)
+    }
+
+    /// Counter blocks that writing `data` would count down on the tag on the
+    /// reader, with a field cycle of its own. Counting down is irreversible.
+    pub fn counters_lowered(&mut self, data: &ChipData) -> Result<Vec<usize>, Pn532Error> {
+        let _ = self.pn532.rf_field(false);
+        self.delay_ms(100);
+        let _ = self.pn532.rf_field(true);
+        self.delay_ms(200);
+
+        let result = self
+            .initiate(true)
+            .and_then(|_| self.select(None))
+            .and_then(|_| self.get_uid())
+            .and_then(|uid| {
+                let chip = ChipType::from_uid(&uid);
+                let mut lowered = Vec::new();
+                for i in 0..data.block_count.min(chip.block_count()) {
+                    if chip.block_kind(i) != BlockKind::Counter
+                        || data.flags[i] & FLAG_UNREADABLE != 0
+                    {
+                        continue;
+                    }
+                    if data.counter(i) < u32::from_le_bytes(self.read_block(i as u8)?) {
+                        lowered.push(i);
+                    }
+                }
+                Ok(lowered)
+            });
+        let _ = self.completion();
+        let _ = self.pn532.rf_field(false);
+        result
+    }
+
     pub fn write_full_chip(&mut self, data: &ChipData) -> Result<(), Pn532Error> {
         let _ = self.pn532.rf_field(false);
         self.delay_ms(100);
@@ -187,21 +642,59 @@ where
         self.initiate(true)?;
         self.select(None)?;
 
-        let mut changed_blocks = alloc::vec::Vec::new();
-        for i in 0..data.block_count {
-            if i == 0 || i == 5 || i == 6 {
+        // Lock state of the tag being written, not of the tag the dump came from
+        let chip = ChipType::from_uid(&self.get_uid()?);
+        let system = self.read_block(SYSTEM_BLOCK as u8).ok();
+
+        // Counters go first: counting block 6 down past b21 reloads OTP
+        // blocks 0-4, which would wipe anything written to them before. Each
+        // block is compared with the tag just before it is written.
+        let is_counter = |i: &usize| chip.block_kind(*i) == BlockKind::Counter;
+        let counters = (1..data.block_count).filter(is_counter);
+        let order = counters.chain((1..data.block_count).filter(|i| !is_counter(i)));
+
+        let mut changed_blocks = Vec::new();
+        let mut write_errors = 0usize;
+        for i in order {
+            if data.flags[i] & FLAG_UNREADABLE != 0 {
                 continue;
             }
 
             let current = self.read_block(i as u8).unwrap_or([0xFF; 4]);
-            if current != data.blocks[i] {
-                changed_blocks.push(i);
-                log::info!(
-                    "Block {} changed: {:02X?} -> {:02X?}",
-                    i,
-                    current,
-                    data.blocks[i]
-                );
+            if current == data.blocks[i] {
+                continue;
+            }
+            if system.is_some_and(|sys| chip.is_locked(&sys, i)) {
+                log::warn!("Block {} is locked, skipped", i);
+                continue;
+            }
+            match chip.block_kind(i) {
+                BlockKind::ResettableOtp if !otp_transition_ok(&current, &data.blocks[i]) => {
+                    log::warn!("Block {} is OTP, bits set to 1 will not stick", i);
+                }
+                BlockKind::Counter => {
+                    let old = u32::from_le_bytes(current);
+                    let new = data.counter(i);
+                    if new > old {
+                        log::warn!("Counter {} can only count down, skipped", i);
+                        continue;
+                    }
+                    if counter_reloads_otp(i, old, new) {
+                        log::warn!("Counter {} decrement reloads OTP blocks 0-4", i);
+                    }
+                }
+                _ => {}
+            }
+            changed_blocks.push(i);
+            log::info!(
+                "Block {} changed: {:02X?} -> {:02X?}",
+                i,
+                current,
+                data.blocks[i]
+            );
+            if self.write_block(i as u8, &data.blocks[i]).is_err() {
+                log::warn!("Write block {} failed", i);
+                write_errors += 1;
             }
         }
 
@@ -212,15 +705,6 @@ where
             return Ok(());
         }
 
-        let mut write_errors = 0usize;
-        for &i in &changed_blocks {
-            log::info!("Writing block {}...", i);
-            if self.write_block(i as u8, &data.blocks[i]).is_err() {
-                log::warn!("Write block {} failed", i);
-                write_errors += 1;
-            }
-        }
-
         let _ = self.completion();
         self.delay_ms(200);
 
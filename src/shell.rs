@@ -0,0 +1,252 @@
+//! Line console on the USB serial port for single tag operations
+//!
+//! Text outside host protocol frames is collected into lines and run as
+//! commands. There is no echo, history or line editing; a terminal with local
+//! echo works best. Output fields are tab-separated so results paste cleanly
+//! into spreadsheets or `cut`, and every reply ends with a `>` prompt line.
+//!
+//! `initiate`, `select` and `uid`/`rb`/`wb` map one-to-one onto the ST25TB
+//! commands and expect the field to be on (`field on`); `dump` and `diff` run
+//! a full read with its own field cycle. `log off` keeps the device's log lines
+//! out of the output until `log on`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::i2c::I2c;
+
+use crate::analysis::Diff;
+use crate::drivers::Pn532;
+use crate::protocol::st25tb::{
+    otp_transition_ok, BlockKind, ChipData, ChipType, St25tb, SYSTEM_BLOCK,
+};
+use crate::util::hex;
+use crate::util::quiet::Quiet;
+
+const LINE_MAX: usize = 128;
+
+const HELP: &str = "\
+help\tthis list\r
+fw\tPN532 firmware version\r
+field on|off\tRF field\r
+initiate\tstart anticollision, prints the chip ID\r
+select [id]\tselect the chip, ID in hex (default: last initiated)\r
+uid\tUID, MSB first\r
+rb <n>\tread block n (255 = system block)\r
+wb <n> <hex>\twrite 4 bytes to block n, then read back (not 255; OTP\r
+\tblocks 0-4 only clear bits, counters 5-6 only count down)\r
+dump\tread the whole tag\r
+diff [json]\tread the whole tag and compare with the open dump\r
+log on|off\tdevice log lines on this port (default on)\r
+";
+
+#[derive(Default)]
+pub struct Shell {
+    line: Vec<u8>,
+    overflow: bool,
+    after_cr: bool,
+    /// From the last `initiate`, used by a bare `select`.
+    chip_id: u8,
+    /// Set by `log off`.
+    quiet: Option<Quiet>,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collects one received byte; at each line end runs the line and returns
+    /// the output. `open` is the dump in the editor, for `diff`.
+    pub fn push<I2C, IRQ, RST>(
+        &mut self,
+        byte: u8,
+        pn532: &mut Pn532<I2C, IRQ, RST>,
+        open: Option<&ChipData>,
+    ) -> Option<String>
+    where
+        I2C: I2c,
+        IRQ: InputPin,
+        RST: OutputPin,
+    {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let result = if core::mem::take(&mut self.overflow) {
+                    Err("line too long".into())
+                } else {
+                    let line = String::from_utf8_lossy(&line);
+                    let mut words = line.split_whitespace();
+                    let command = words.next()?;
+                    let args: Vec<&str> = words.collect();
+                    let mut out = String::new();
+                    self.command(command, &args, pn532, open, &mut out)
+                        .map(|_| out)
+                };
+                let mut out = result.unwrap_or_else(|msg| alloc::format!("error\t{}\r\n", msg));
+                out.push_str(">\r\n");
+                Some(out)
+            }
+            _ if self.line.len() >= LINE_MAX => {
+                self.overflow = true;
+                None
+            }
+            _ => {
+                self.line.push(byte);
+                None
+            }
+        }
+    }
+
+    fn command<I2C, IRQ, RST>(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        pn532: &mut Pn532<I2C, IRQ, RST>,
+        open: Option<&ChipData>,
+        out: &mut String,
+    ) -> Result<(), String>
+    where
+        I2C: I2c,
+        IRQ: InputPin,
+        RST: OutputPin,
+    {
+        let failed = |e: crate::drivers::pn532::Pn532Error| alloc::format!("{:?}", e);
+        match (command, args) {
+            ("help", []) => out.push_str(HELP),
+            ("fw", []) => {
+                let (ic, ver, rev) = pn532.get_firmware_version().map_err(failed)?;
+                let _ = write!(out, "PN532\tIC {:02X}\t{}.{}\r\n", ic, ver, rev);
+            }
+            ("field", [state @ ("on" | "off")]) => {
+                pn532.rf_field(*state == "on").map_err(failed)?;
+                let _ = write!(out, "field\t{}\r\n", state);
+            }
+            ("log", ["on"]) => {
+                self.quiet = None;
+                out.push_str("log\ton\r\n");
+            }
+            ("log", ["off"]) => {
+                self.quiet.get_or_insert_with(Quiet::new);
+                out.push_str("log\toff\r\n");
+            }
+            ("initiate", []) => {
+                self.chip_id = St25tb::new(pn532).initiate(true).map_err(failed)?;
+                let _ = write!(out, "chip_id\t{:02X}\r\n", self.chip_id);
+            }
+            ("select", [] | [_]) => {
+                let id = match args.first() {
+                    Some(arg) => u8::from_str_radix(arg.trim_start_matches("0x"), 16)
+                        .map_err(|_| "bad chip ID")?,
+                    None => self.chip_id,
+                };
+                St25tb::new(pn532).select(Some(id)).map_err(failed)?;
+                self.chip_id = id;
+                let _ = write!(out, "selected\t{:02X}\r\n", id);
+            }
+            ("uid", []) => {
+                let uid = St25tb::new(pn532).get_uid().map_err(failed)?;
+                let mut msb = uid;
+                msb.reverse();
+                out.push_str("uid\t");
+                push_hex(out, &msb);
+                let _ = write!(out, "\t{}\r\n", ChipType::from_uid(&uid).name());
+            }
+            ("rb", [n]) => {
+                let block = block_number(n)?;
+                let data = St25tb::new(pn532).read_block(block).map_err(failed)?;
+                push_block(out, block as usize, Some(&data));
+            }
+            ("wb", [n, data @ ..]) if !data.is_empty() => {
+                let block = block_number(n)?;
+                let mut bytes = [0u8; 4];
+                match hex::parse_bytes(&data.join(" "), &mut bytes) {
+                    Some(4) => {}
+                    _ => return Err("need 4 hex bytes".into()),
+                }
+                // Locking is irreversible, so it stays behind the editor's
+                // confirm screen
+                if block as usize == SYSTEM_BLOCK {
+                    return Err("system block is written from the editor only".into());
+                }
+                let mut tag = St25tb::new(pn532);
+                let chip = ChipType::from_uid(&tag.get_uid().map_err(failed)?);
+                let current = tag.read_block(block).map_err(failed)?;
+                match chip.block_kind(block as usize) {
+                    BlockKind::ResettableOtp if !otp_transition_ok(&current, &bytes) => {
+                        return Err("OTP block, bits can only go 1 -> 0".into());
+                    }
+                    BlockKind::Counter
+                        if u32::from_le_bytes(bytes) > u32::from_le_bytes(current) =>
+                    {
+                        return Err("counter can only count down".into());
+                    }
+                    _ => {}
+                }
+                tag.write_block(block, &bytes).map_err(failed)?;
+                let back = tag.read_block(block).map_err(failed)?;
+                push_block(out, block as usize, Some(&back));
+                if back != bytes {
+                    return Err("read back differs".into());
+                }
+            }
+            ("dump", []) => {
+                let data = St25tb::new(pn532).read_full_chip().map_err(failed)?;
+                for i in (0..data.block_count).chain(data.system.map(|_| SYSTEM_BLOCK)) {
+                    push_block(out, i, data.block(i).as_ref());
+                }
+            }
+            ("diff", [] | ["json"]) => {
+                let open = open.ok_or("no dump open")?;
+                let tag = St25tb::new(pn532).read_full_chip().map_err(failed)?;
+                let diff = Diff::new(open, &tag);
+                let text = if args.is_empty() {
+                    diff.text()
+                } else {
+                    diff.json()
+                };
+                out.push_str(&text.replace('\n', "\r\n"));
+            }
+            _ => return Err("unknown command or arguments, try help".into()),
+        }
+        Ok(())
+    }
+}
+
+/// Decimal or `0x` hex.
+fn number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(h) => u32::from_str_radix(h, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn block_number(s: &str) -> Result<u8, String> {
+    number(s)
+        .filter(|&n| n <= SYSTEM_BLOCK as u32)
+        .map(|n| n as u8)
+        .ok_or_else(|| "bad block number".into())
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for (i, b) in bytes.iter().enumerate() {
+        let _ = write!(out, "{}{:02X}", if i > 0 { " " } else { "" }, b);
+    }
+}
+
+fn push_opt(out: &mut String, data: Option<&[u8; 4]>) {
+    match data {
+        Some(d) => push_hex(out, d),
+        None => out.push_str("-- -- -- --"),
+    }
+}
+
+fn push_block(out: &mut String, i: usize, data: Option<&[u8; 4]>) {
+    let _ = write!(out, "B{:03}\t", i);
+    push_opt(out, data);
+    out.push_str("\r\n");
+}
@@ -0,0 +1,35 @@
+//! Side-by-side diff of a stored dump (left) against the open one (right)
+
+use alloc::string::String;
+
+use crate::analysis::{BlockDiff, Diff};
+
+pub struct Compare {
+    /// Stored dump on the left.
+    pub name: String,
+    pub diff: Diff,
+    /// Index into `diff.blocks`.
+    pub selected: usize,
+}
+
+impl Compare {
+    pub fn new(name: String, diff: Diff) -> Self {
+        Self {
+            name,
+            diff,
+            selected: 0,
+        }
+    }
+
+    pub fn rotate(&mut self, up: bool) {
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        } else if self.selected + 1 < self.diff.blocks.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn selected_block(&self) -> Option<&BlockDiff> {
+        self.diff.blocks.get(self.selected)
+    }
+}
@@ -1,4 +1,9 @@
-use crate::protocol::st25tb::ChipData;
+use super::compare::Compare;
+use super::editor::{ChipEditor, OtpEdit};
+use super::library::{self, Library, Mode, TextInput, ACTIONS, HEADER_ROWS};
+use super::loader::SerialLoad;
+use crate::profile::date::civil_from_days;
+use crate::protocol::st25tb::FLAG_UNREADABLE;
 use core::fmt::Write;
 use embedded_graphics::{
     mono_font::{ascii::FONT_6X10, MonoTextStyle},
@@ -13,6 +18,7 @@ pub struct Display<D> {
     driver: D,
     width: u32,
     height: u32,
+    otp_shown: bool,
 }
 
 impl<D> Display<D>
@@ -24,6 +30,7 @@ where
             driver,
             width,
             height,
+            otp_shown: false,
         }
     }
 
@@ -46,15 +53,38 @@ where
         let _ = Text::new(msg, Point::new(10, 20), style).draw(&mut self.driver);
     }
 
-    pub fn show_chip_data(
-        &mut self,
-        data: &ChipData,
-        selected_block: usize,
-        selected_byte: usize,
-        selected_nibble: usize,
-        edit_mode: bool,
-        force_clear: bool,
-    ) {
+    pub fn show_lines(&mut self, lines: &[&str], highlight: Option<usize>) {
+        self.clear();
+        let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let warn_style = MonoTextStyle::new(&FONT_6X10, Rgb565::RED);
+        for (i, line) in lines.iter().enumerate() {
+            let line_style = if highlight == Some(i) {
+                warn_style
+            } else {
+                style
+            };
+            let y = 20 + (i as i32) * 14;
+            let _ = Text::new(line, Point::new(10, y), line_style).draw(&mut self.driver);
+        }
+    }
+
+    pub fn show_chip_data(&mut self, editor: &ChipEditor, force_clear: bool) {
+        let data = &editor.data;
+        let selected_block = editor.selected_block;
+        let selected_byte = editor.selected_byte;
+        let selected_nibble = editor.selected_nibble;
+        let edit_mode = editor.edit_mode;
+        let field_text = editor.field_text().or_else(|| editor.counter_text());
+
+        if let Some(otp) = editor.otp {
+            let clear = force_clear || !self.otp_shown;
+            self.otp_shown = true;
+            self.show_otp(editor, &otp, clear);
+            return;
+        }
+        let force_clear = force_clear || self.otp_shown;
+        self.otp_shown = false;
+
         if force_clear {
             self.clear();
         }
@@ -64,6 +94,10 @@ where
         let selected_style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
         let edit_style = MonoTextStyle::new(&FONT_6X10, Rgb565::GREEN);
         let dim_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+        let lock_style = MonoTextStyle::new(&FONT_6X10, Rgb565::RED);
+        let modified_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_ORANGE);
+        let range_style = MonoTextStyle::new(&FONT_6X10, Rgb565::MAGENTA);
+        let hit_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_LIME);
 
         self.clear_area(0, 0, self.width, 12);
         let mut header: String<48> = String::new();
@@ -77,7 +111,7 @@ where
         );
         let _ = Text::new(&header, Point::new(5, 10), header_style).draw(&mut self.driver);
 
-        let visible_rows = 25;
+        let visible_rows = 24;
         let half = visible_rows / 2;
 
         let start = if selected_block <= half {
@@ -94,7 +128,7 @@ where
             let block = &data.blocks[i];
             let is_selected = i == selected_block;
 
-            self.clear_area(0, y - 9, 145, 11);
+            self.clear_area(0, y - 9, self.width, 11);
 
             let mut idx_str: String<8> = String::new();
             let _ = write!(idx_str, "{:3}:", i);
@@ -107,7 +141,12 @@ where
                 normal_style
             };
 
-            let _ = Text::new(&idx_str, Point::new(2, y), line_style).draw(&mut self.driver);
+            let idx_style = if !is_selected && editor.in_range(i) {
+                range_style
+            } else {
+                line_style
+            };
+            let _ = Text::new(&idx_str, Point::new(2, y), idx_style).draw(&mut self.driver);
 
             for b in 0..4 {
                 let x = 28 + (b as i32) * 18;
@@ -140,7 +179,9 @@ where
                     let mut byte_str: String<4> = String::new();
                     let _ = write!(byte_str, "{:02X}", block[b]);
 
-                    let byte_style = if is_selected && !edit_mode {
+                    let byte_style = if editor.is_hit(i, b) {
+                        hit_style
+                    } else if is_selected && !edit_mode {
                         selected_style
                     } else if block == &[0xFF, 0xFF, 0xFF, 0xFF] {
                         dim_style
@@ -167,9 +208,27 @@ where
             let _ = ascii.push('|');
             let _ = Text::new(&ascii, Point::new(105, y), line_style).draw(&mut self.driver);
 
+            if data.flags[i] & FLAG_UNREADABLE != 0 {
+                let _ = Text::new("?", Point::new(148, y), lock_style).draw(&mut self.driver);
+            } else if data.is_locked(i) {
+                let _ = Text::new("L", Point::new(148, y), lock_style).draw(&mut self.driver);
+            } else if editor.checksum_bad(i) {
+                let _ = Text::new("!", Point::new(148, y), lock_style).draw(&mut self.driver);
+            }
+            if editor.is_modified(i) {
+                let _ = Text::new("*", Point::new(154, y), modified_style).draw(&mut self.driver);
+            }
+
             y += 11;
         }
 
+        let field_y = 288;
+        self.clear_area(0, field_y - 9, self.width, 12);
+        if let Some(text) = field_text.as_deref() {
+            let field_style = if edit_mode { edit_style } else { normal_style };
+            let _ = Text::new(text, Point::new(5, field_y), field_style).draw(&mut self.driver);
+        }
+
         let decoder_y = 300;
         self.clear_area(0, decoder_y - 9, self.width, 12);
         let _ = Text::new("Decode:", Point::new(5, decoder_y), header_style).draw(&mut self.driver);
@@ -204,26 +263,121 @@ where
         let _ = Text::new(&dec_str, Point::new(168, decoder_y), dim_style).draw(&mut self.driver);
 
         let hint_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+        let mut range: String<32> = String::new();
         let hint = if edit_mode {
-            "ROT:val BTN:byte BAK:done"
+            "ROT:val BTN:next BAK:done"
+        } else if editor.mark.is_some() {
+            let (first, last) = editor.range();
+            let _ = write!(range, "Range {}-{} BAK:unmark", first, last);
+            &range
         } else {
             "ROT:blk BTN:edit BAK:menu"
         };
+        self.clear_area(0, self.height as i32 - 12, self.width, 12);
         let _ = Text::new(hint, Point::new(5, self.height as i32 - 3), hint_style)
             .draw(&mut self.driver);
     }
 
+    fn show_otp(&mut self, editor: &ChipEditor, otp: &OtpEdit, force_clear: bool) {
+        if force_clear {
+            self.clear();
+        }
+
+        let header_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CYAN);
+        let normal_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let warn_style = MonoTextStyle::new(&FONT_6X10, Rgb565::RED);
+        let dim_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+
+        self.clear_area(0, 0, self.width, 12);
+        let mut header: String<32> = String::new();
+        let _ = write!(header, "OTP Blk:{:3} {} bits", otp.block(), otp.bits());
+        let _ = Text::new(&header, Point::new(5, 10), header_style).draw(&mut self.driver);
+
+        // One row per byte, bit 7 on the left like the binary notation in the research notes
+        let cell = 13u32;
+        let pitch = 15;
+        for byte in 0..otp.len {
+            let y = 24 + (byte as i32) * 20;
+            let pos = otp.start + byte;
+            self.clear_area(0, y - 2, self.width, cell + 4);
+
+            let mut label: String<8> = String::new();
+            let _ = write!(label, "{:3}.{}", pos / 4, pos % 4);
+            let _ = Text::new(&label, Point::new(2, y + 10), dim_style).draw(&mut self.driver);
+
+            for col in 0..8 {
+                let bit = byte * 8 + (7 - col);
+                let x = 40 + (col as i32) * pitch;
+                let fill = if editor.otp_bit(otp, bit) {
+                    Rgb565::GREEN
+                } else {
+                    Rgb565::CSS_DIM_GRAY
+                };
+                let rect = Rectangle::new(Point::new(x, y), Size::new(cell, cell));
+                let _ = rect
+                    .into_styled(PrimitiveStyle::with_fill(fill))
+                    .draw(&mut self.driver);
+                if bit == otp.bit {
+                    let outline = if otp.armed {
+                        Rgb565::RED
+                    } else {
+                        Rgb565::YELLOW
+                    };
+                    let _ = rect
+                        .into_styled(PrimitiveStyle::with_stroke(outline, 2))
+                        .draw(&mut self.driver);
+                }
+            }
+        }
+
+        let (used, remaining) = editor.otp_count(otp);
+        let info_y = 24 + (otp.len as i32) * 20 + 14;
+        self.clear_area(0, info_y - 9, self.width, 36);
+
+        let mut counts: String<32> = String::new();
+        let _ = write!(counts, "Used:{} Left:{}", used, remaining);
+        let _ = Text::new(&counts, Point::new(5, info_y), normal_style).draw(&mut self.driver);
+
+        let mut status: String<32> = String::new();
+        let status_style = if otp.armed {
+            let _ = write!(status, "Burn bit {}? BTN:yes BAK:no", otp.bit);
+            warn_style
+        } else if editor.otp_bit(otp, otp.bit) {
+            let _ = write!(status, "Bit {} unused", otp.bit);
+            normal_style
+        } else {
+            let _ = write!(status, "Bit {} used (locked)", otp.bit);
+            dim_style
+        };
+        let _ = Text::new(&status, Point::new(5, info_y + 14), status_style).draw(&mut self.driver);
+
+        let _ = Text::new(
+            "ROT:bit BTN:burn BAK:done",
+            Point::new(5, self.height as i32 - 3),
+            dim_style,
+        )
+        .draw(&mut self.driver);
+    }
+
     pub fn show_menu(&mut self, items: &[&str], selected: usize) {
+        self.show_list("ST25TB Reader", items, selected);
+    }
+
+    pub fn show_list(&mut self, title: &str, items: &[&str], selected: usize) {
         self.clear();
 
         let title_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CYAN);
-        let _ = Text::new("ST25TB Reader", Point::new(40, 15), title_style).draw(&mut self.driver);
+        let title_x = (self.width as i32 - title.len() as i32 * 6).max(0) / 2;
+        let _ = Text::new(title, Point::new(title_x, 15), title_style).draw(&mut self.driver);
 
         let normal_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
         let selected_style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
 
+        let visible_rows = ((self.height as usize).saturating_sub(40)) / 16;
+        let start = selected.saturating_sub(visible_rows.saturating_sub(1));
+
         let mut y = 40;
-        for (i, item) in items.iter().enumerate() {
+        for (i, item) in items.iter().enumerate().skip(start).take(visible_rows) {
             let style = if i == selected {
                 selected_style
             } else {
@@ -238,6 +392,312 @@ where
         }
     }
 
+    pub fn show_library(&mut self, lib: &Library) {
+        let name = lib.selected_entry().map(|e| e.name.as_str()).unwrap_or("");
+        match &lib.mode {
+            Mode::Actions(i) => return self.show_list(name, &ACTIONS, *i),
+            Mode::ConfirmDelete => {
+                return self.show_lines(
+                    &[name, "Delete this dump?", "BTN:delete BAK:cancel"],
+                    Some(1),
+                )
+            }
+            Mode::Rename(input) => return self.show_text_input("Rename", input),
+            Mode::Filter(input) => return self.show_text_input("UID prefix", input),
+            Mode::Browse => {}
+        }
+
+        self.clear();
+        let title_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CYAN);
+        let normal_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let selected_style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
+        let dim_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+
+        let mut title: String<24> = String::new();
+        let _ = write!(title, "Library {}/{}", lib.visible.len(), lib.entries.len());
+        let title_x = (self.width as i32 - title.len() as i32 * 6).max(0) / 2;
+        let _ = Text::new(&title, Point::new(title_x, 15), title_style).draw(&mut self.driver);
+
+        let pick = |row: usize| {
+            if row == lib.selected {
+                (selected_style, "> ")
+            } else {
+                (normal_style, "  ")
+            }
+        };
+
+        let mut line: String<32> = String::new();
+        let (style, prefix) = pick(0);
+        let _ = write!(line, "{}Sort: {}", prefix, lib.sort.label());
+        let _ = Text::new(&line, Point::new(5, 35), style).draw(&mut self.driver);
+        line.clear();
+        let (style, prefix) = pick(1);
+        let filter = if lib.filter.is_empty() {
+            "-"
+        } else {
+            lib.filter.as_str()
+        };
+        let _ = write!(line, "{}UID: {}", prefix, filter);
+        let _ = Text::new(&line, Point::new(5, 49), style).draw(&mut self.driver);
+
+        if lib.visible.is_empty() {
+            let _ = Text::new("No dumps", Point::new(20, 75), dim_style).draw(&mut self.driver);
+        }
+
+        // Three lines per dump: name, chip type and read date, UID
+        let visible_rows = (self.height as usize).saturating_sub(70) / 36;
+        let selected = lib.selected.saturating_sub(HEADER_ROWS);
+        let start = selected.saturating_sub(visible_rows.saturating_sub(1));
+        let mut y = 75;
+        for (row, &idx) in lib
+            .visible
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(visible_rows)
+        {
+            let entry = &lib.entries[idx];
+            let (style, prefix) = pick(row + HEADER_ROWS);
+            line.clear();
+            let _ = write!(line, "{}{}", prefix, entry.name);
+            let _ = Text::new(&line, Point::new(5, y), style).draw(&mut self.driver);
+            line.clear();
+            let _ = write!(line, " {} ", entry.chip_type.name());
+            if entry.timestamp == 0 {
+                let _ = line.push_str("date unknown");
+            } else {
+                let (year, month, day) = civil_from_days((entry.timestamp / 86400) as i32);
+                let _ = write!(line, "{:04}-{:02}-{:02}", year, month, day);
+            }
+            let _ = Text::new(&line, Point::new(5, y + 11), dim_style).draw(&mut self.driver);
+            line.clear();
+            let _ = write!(line, " {}", library::uid_hex(&entry.uid));
+            let _ = Text::new(&line, Point::new(5, y + 22), dim_style).draw(&mut self.driver);
+            y += 36;
+        }
+
+        let _ = Text::new(
+            "ROT:move BTN:select BAK:menu",
+            Point::new(5, self.height as i32 - 3),
+            dim_style,
+        )
+        .draw(&mut self.driver);
+    }
+
+    pub fn show_text_input(&mut self, title: &str, input: &TextInput) {
+        self.clear();
+        let title_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CYAN);
+        let normal_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let cursor_style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
+        let dim_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+
+        let title_x = (self.width as i32 - title.len() as i32 * 6).max(0) / 2;
+        let _ = Text::new(title, Point::new(title_x, 15), title_style).draw(&mut self.driver);
+
+        let mut x = 10;
+        for (i, &c) in input.text.iter().enumerate() {
+            let mut ch: String<1> = String::new();
+            let _ = ch.push(c as char);
+            let style = if i == input.cursor {
+                let _ = Rectangle::new(Point::new(x, 38), Size::new(6, 2))
+                    .into_styled(PrimitiveStyle::with_fill(Rgb565::YELLOW))
+                    .draw(&mut self.driver);
+                cursor_style
+            } else {
+                normal_style
+            };
+            let _ = Text::new(&ch, Point::new(x, 35), style).draw(&mut self.driver);
+            x += 6;
+        }
+
+        let mut hint: String<32> = String::new();
+        let _ = write!(hint, "'{}' = no character", library::INPUT_GAP as char);
+        let _ = Text::new(&hint, Point::new(5, 60), dim_style).draw(&mut self.driver);
+        let _ = Text::new(
+            "ROT:char BTN:next BAK:done",
+            Point::new(5, self.height as i32 - 3),
+            dim_style,
+        )
+        .draw(&mut self.driver);
+    }
+
+    pub fn show_load_progress(&mut self, load: &SerialLoad, idle_secs: u64) {
+        self.clear();
+        let title_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CYAN);
+        let normal_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let warn_style = MonoTextStyle::new(&FONT_6X10, Rgb565::RED);
+        let dim_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+
+        let title = "Load Serial";
+        let title_x = (self.width as i32 - title.len() as i32 * 6).max(0) / 2;
+        let _ = Text::new(title, Point::new(title_x, 15), title_style).draw(&mut self.driver);
+
+        let mut y = 35;
+        let mut line: String<40> = String::new();
+        let _ = write!(line, "Lines {}", load.lines);
+        let _ = Text::new(&line, Point::new(5, y), normal_style).draw(&mut self.driver);
+        y += 14;
+
+        line.clear();
+        let _ = write!(line, "Blocks {}", load.blocks());
+        if let Some(expected) = load.expected {
+            let _ = write!(line, "/{}", expected);
+        }
+        if load.file_lines > 0 {
+            let _ = write!(line, " File {}", load.file_lines);
+        }
+        let _ = Text::new(&line, Point::new(5, y), normal_style).draw(&mut self.driver);
+        y += 14;
+
+        line.clear();
+        let _ = write!(line, "Rejected {}", load.rejected());
+        let style = if load.rejected() > 0 {
+            warn_style
+        } else {
+            normal_style
+        };
+        let _ = Text::new(&line, Point::new(5, y), style).draw(&mut self.driver);
+        y += 14;
+
+        if let Some((n, e)) = load.last_error {
+            line.clear();
+            let _ = write!(line, "L{}: {:?}", n, e);
+            let _ = Text::new(&line, Point::new(5, y), warn_style).draw(&mut self.driver);
+        }
+        y += 24;
+
+        for (i, block) in load.preview.iter() {
+            line.clear();
+            let _ = write!(
+                line,
+                "B{:03} {:02X} {:02X} {:02X} {:02X}",
+                i, block[0], block[1], block[2], block[3]
+            );
+            let _ = Text::new(&line, Point::new(5, y), normal_style).draw(&mut self.driver);
+            y += 12;
+        }
+
+        line.clear();
+        let _ = write!(line, "Idle timeout {}s", idle_secs);
+        let _ = Text::new(&line, Point::new(5, self.height as i32 - 17), dim_style)
+            .draw(&mut self.driver);
+        let _ = Text::new(
+            "END/BTN:done BAK:cancel",
+            Point::new(5, self.height as i32 - 3),
+            dim_style,
+        )
+        .draw(&mut self.driver);
+    }
+
+    pub fn show_diff(&mut self, compare: &Compare) {
+        self.clear();
+        let title_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CYAN);
+        let normal_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let selected_style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
+        let old_style = MonoTextStyle::new(&FONT_6X10, Rgb565::RED);
+        let new_style = MonoTextStyle::new(&FONT_6X10, Rgb565::GREEN);
+        let dim_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_GRAY);
+        let diff = &compare.diff;
+
+        let mut line: String<40> = String::new();
+        let _ = write!(
+            line,
+            "Diff {}blk {}B {}bit",
+            diff.blocks.len(),
+            diff.bytes(),
+            diff.bits()
+        );
+        let title_x = (self.width as i32 - line.len() as i32 * 6).max(0) / 2;
+        let _ = Text::new(&line, Point::new(title_x, 15), title_style).draw(&mut self.driver);
+
+        let mut y = 30;
+        if diff.old_uid != diff.new_uid {
+            let _ = Text::new("UID differs", Point::new(4, y), old_style).draw(&mut self.driver);
+            y += 12;
+        }
+        if diff.old_count != diff.new_count {
+            line.clear();
+            let _ = write!(line, "Blocks {} -> {}", diff.old_count, diff.new_count);
+            let _ = Text::new(&line, Point::new(4, y), old_style).draw(&mut self.driver);
+            y += 12;
+        }
+
+        // Columns: index, stored dump, open dump
+        line.clear();
+        let name = compare.name.get(..11).unwrap_or(&compare.name);
+        let _ = write!(line, "    {:<12}open", name);
+        let _ = Text::new(&line, Point::new(4, y), dim_style).draw(&mut self.driver);
+        y += 14;
+
+        if diff.blocks.is_empty() {
+            let _ = Text::new("No block differs", Point::new(4, y), normal_style)
+                .draw(&mut self.driver);
+        }
+
+        let visible_rows = (self.height as i32 - y - 50).max(12) as usize / 12;
+        let start = compare
+            .selected
+            .saturating_sub(visible_rows.saturating_sub(1));
+        for (row, d) in diff
+            .blocks
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(visible_rows)
+        {
+            let style = if row == compare.selected {
+                selected_style
+            } else {
+                normal_style
+            };
+            line.clear();
+            let _ = write!(line, "{:03}", d.index);
+            let _ = Text::new(&line, Point::new(4, y), style).draw(&mut self.driver);
+
+            let mask = d.byte_mask();
+            for (column, block, changed_style) in [(4, d.old, old_style), (16, d.new, new_style)] {
+                for b in 0..4 {
+                    line.clear();
+                    let style = match block {
+                        Some(bytes) => {
+                            let _ = write!(line, "{:02X}", bytes[b]);
+                            if mask & 1 << b != 0 {
+                                changed_style
+                            } else {
+                                normal_style
+                            }
+                        }
+                        None => {
+                            let _ = line.push_str("--");
+                            dim_style
+                        }
+                    };
+                    let x = 4 + (column + b as i32 * 3) * 6;
+                    let _ = Text::new(&line, Point::new(x, y), style).draw(&mut self.driver);
+                }
+            }
+            y += 12;
+        }
+
+        // Flipped bits of the selected block
+        if let Some(d) = compare.selected_block() {
+            let flips = d.flips();
+            let bottom = self.height as i32 - 31;
+            for (i, pair) in flips.chunks(2).enumerate() {
+                line.clear();
+                let _ = write!(line, "^{} {:08b} {:08b}", i * 2, pair[0], pair[1]);
+                let _ = Text::new(&line, Point::new(4, bottom + i as i32 * 11), dim_style)
+                    .draw(&mut self.driver);
+            }
+        }
+        let _ = Text::new(
+            "ROT:move BTN:view BAK:back",
+            Point::new(4, self.height as i32 - 3),
+            dim_style,
+        )
+        .draw(&mut self.driver);
+    }
+
     pub fn driver_mut(&mut self) -> &mut D {
         &mut self.driver
     }
@@ -1,4 +1,59 @@
-use crate::protocol::st25tb::ChipData;
+use super::history::History;
+use crate::interop::patch::{Conflict, Patch};
+use crate::profile::{Field, FieldKind, Profile};
+use crate::protocol::st25tb::{
+    counter_used, BlockKind, ChipData, FLAG_LOCKED, FLAG_TORN, FLAG_UNREADABLE,
+};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use heapless::String;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldEdit {
+    pub field: usize,
+    pub part: usize,
+}
+
+/// Bit-level view of an OTP area; `armed` means the next press burns `bit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OtpEdit {
+    pub start: usize,
+    pub len: usize,
+    pub bit: usize,
+    pub armed: bool,
+}
+
+/// Why an edit or block range operation did nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeError {
+    /// A target block is not user memory: OTP, counter, system, an `otp`
+    /// profile field, unreadable or locked.
+    Protected(usize),
+    /// The paste would run past the last block.
+    PastEnd,
+    NothingCopied,
+    NoMark,
+    /// The edit would count this counter block up, which the chip refuses.
+    CountsUp(usize),
+}
+
+/// Why a patch was not applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyError {
+    /// Blocks that no longer hold what the patch was recorded against.
+    Conflicts(Vec<Conflict>),
+    Refused(RangeError),
+}
+
+impl OtpEdit {
+    pub fn block(&self) -> usize {
+        self.start / 4
+    }
+
+    pub fn bits(&self) -> usize {
+        self.len * 8
+    }
+}
 
 pub struct ChipEditor {
     pub data: ChipData,
@@ -6,40 +61,123 @@ pub struct ChipEditor {
     pub selected_byte: usize,
     pub selected_nibble: usize,
     pub edit_mode: bool,
+    pub profile: Option<Profile>,
+    pub field_edit: Option<FieldEdit>,
+    pub otp: Option<OtpEdit>,
+    /// Every edit since the dump was opened.
+    pub patch: Patch,
+    pub history: History,
+    /// Blocks as opened, for reverting and the modified markers.
+    original: Vec<[u8; 4]>,
+    /// Other end of the block range, which runs to `selected_block`.
+    pub mark: Option<usize>,
+    clipboard: Vec<[u8; 4]>,
+    /// Last searched bytes, for finding the next match.
+    pub search: Vec<u8>,
+    /// Byte position and length of the match shown, until the cursor moves.
+    pub hit: Option<(usize, usize)>,
 }
 
 impl ChipEditor {
     pub fn new(data: ChipData) -> Self {
         Self {
+            original: data.blocks[..data.block_count].to_vec(),
             data,
             selected_block: 0,
             selected_byte: 0,
             selected_nibble: 0,
             edit_mode: false,
+            profile: None,
+            field_edit: None,
+            otp: None,
+            patch: Patch::new(),
+            history: History::new(),
+            mark: None,
+            clipboard: Vec::new(),
+            search: Vec::new(),
+            hit: None,
         }
     }
 
-    pub fn move_up(&mut self) {
-        if self.edit_mode {
-            self.increment_nibble();
+    /// Moves the cursor up, or steps the value under it in edit mode; fails
+    /// when that edit was refused.
+    pub fn move_up(&mut self) -> Result<(), RangeError> {
+        self.hit = None;
+        if let Some(ref mut otp) = self.otp {
+            otp.bit = otp.bit.saturating_sub(1);
+            otp.armed = false;
+        } else if self.edit_mode {
+            if self.field_edit.is_some() {
+                return self.edit(|ed| ed.adjust_field(1));
+            } else {
+                return self.edit(Self::increment_nibble);
+            }
         } else if self.selected_block > 0 {
             self.selected_block -= 1;
         }
+        Ok(())
     }
 
-    pub fn move_down(&mut self) {
-        if self.edit_mode {
-            self.decrement_nibble();
+    pub fn move_down(&mut self) -> Result<(), RangeError> {
+        self.hit = None;
+        if let Some(ref mut otp) = self.otp {
+            otp.bit = (otp.bit + 1).min(otp.bits() - 1);
+            otp.armed = false;
+        } else if self.edit_mode {
+            if self.field_edit.is_some() {
+                return self.edit(|ed| ed.adjust_field(-1));
+            } else {
+                return self.edit(Self::decrement_nibble);
+            }
         } else if self.selected_block < self.data.block_count - 1 {
             self.selected_block += 1;
         }
+        Ok(())
     }
 
     pub fn toggle_edit_mode(&mut self) {
-        if !self.edit_mode {
+        self.history.seal();
+        self.hit = None;
+        if let Some(otp) = self.otp {
+            self.press_otp(otp);
+        } else if !self.edit_mode {
             self.edit_mode = true;
+            if let Some((start, len)) = self.otp_region(self.selected_block) {
+                let mut otp = OtpEdit {
+                    start,
+                    len,
+                    bit: 0,
+                    armed: false,
+                };
+                otp.bit = (0..otp.bits())
+                    .find(|&bit| self.otp_bit(&otp, bit))
+                    .unwrap_or(0);
+                self.otp = Some(otp);
+                return;
+            }
             self.selected_byte = 0;
             self.selected_nibble = 0;
+            self.field_edit = self
+                .field_in_block(self.selected_block)
+                .map(|field| FieldEdit { field, part: 0 });
+            if let Some(field) = self.edited_field() {
+                if field.block == self.selected_block {
+                    self.selected_byte = field.offset;
+                }
+            }
+        } else if let Some(fe) = self.field_edit {
+            let parts = self.edited_field().map_or(0, Field::parts);
+            if fe.part + 1 < parts {
+                self.field_edit = Some(FieldEdit {
+                    part: fe.part + 1,
+                    ..fe
+                });
+            } else {
+                // Past the last part: fall back to raw nibble editing
+                self.field_edit = None;
+                self.selected_byte = 0;
+                self.selected_nibble = 0;
+            }
         } else {
             if self.selected_nibble == 0 {
                 self.selected_nibble = 1;
@@ -50,7 +188,64 @@ impl ChipEditor {
     }
 
     pub fn exit_edit_mode(&mut self) {
+        self.history.seal();
+        if let Some(ref mut otp) = self.otp {
+            if otp.armed {
+                otp.armed = false;
+                return;
+            }
+        }
         self.edit_mode = false;
+        self.field_edit = None;
+        self.otp = None;
+    }
+
+    /// OTP counter area containing `block`: an `otp` profile field, else the chip's OTP blocks.
+    fn otp_region(&self, block: usize) -> Option<(usize, usize)> {
+        if let Some(profile) = self.profile.as_ref() {
+            for field in profile.fields.iter() {
+                let (first, last) = field.blocks();
+                if field.kind == FieldKind::Otp && block >= first && block <= last {
+                    return Some((field.block * 4 + field.offset, field.size));
+                }
+            }
+        }
+        if self.data.chip_type().block_kind(block) == BlockKind::ResettableOtp {
+            return Some((block * 4, 4));
+        }
+        None
+    }
+
+    /// True while the bit is still unused (1).
+    pub fn otp_bit(&self, otp: &OtpEdit, bit: usize) -> bool {
+        let pos = otp.start + bit / 8;
+        self.data.blocks[pos / 4][pos % 4] & (1 << (bit % 8)) != 0
+    }
+
+    /// `(used, remaining)` bits of the OTP area.
+    pub fn otp_count(&self, otp: &OtpEdit) -> (usize, usize) {
+        let remaining = (0..otp.bits()).filter(|&b| self.otp_bit(otp, b)).count();
+        (otp.bits() - remaining, remaining)
+    }
+
+    fn press_otp(&mut self, otp: OtpEdit) {
+        if !self.otp_bit(&otp, otp.bit) {
+            // Already burned; 0 -> 1 is not a legal OTP transition
+            return;
+        }
+        if !otp.armed {
+            self.otp = Some(OtpEdit { armed: true, ..otp });
+            return;
+        }
+        let pos = otp.start + otp.bit / 8;
+        // Clearing a bit never counts a counter up, so this is not refused
+        let _ = self.edit(|ed| ed.data.blocks[pos / 4][pos % 4] &= !(1 << (otp.bit % 8)));
+        self.history.seal();
+        log::info!("OTP block {} bit {} burned", pos / 4, otp.bit);
+        self.otp = Some(OtpEdit {
+            armed: false,
+            ..otp
+        });
     }
 
     pub fn next_byte(&mut self) {
@@ -58,6 +253,431 @@ impl ChipEditor {
         self.selected_nibble = 0;
     }
 
+    fn field_in_block(&self, block: usize) -> Option<usize> {
+        let profile = self.profile.as_ref()?;
+        profile.fields.iter().position(|f| {
+            let (first, last) = f.blocks();
+            block >= first && block <= last
+        })
+    }
+
+    fn edited_field(&self) -> Option<&Field> {
+        let fe = self.field_edit?;
+        self.profile.as_ref()?.fields.get(fe.field)
+    }
+
+    /// `name=value` for the field under the cursor, with the part being edited in brackets.
+    pub fn field_text(&self) -> Option<String<40>> {
+        let profile = self.profile.as_ref()?;
+        let (idx, part) = match self.field_edit {
+            Some(fe) => (fe.field, Some(fe.part)),
+            None => (self.field_in_block(self.selected_block)?, None),
+        };
+        let field = profile.fields.get(idx)?;
+        let value = field.read(&self.data);
+
+        let mut text: String<40> = String::new();
+        let _ = write!(text, "{}=", field.name);
+
+        let Some(part) = part else {
+            let _ = field.format_value(value, &mut text);
+            if field.checksum(&self.data).is_some_and(|v| v != value) {
+                let _ = text.push_str(" BAD");
+            }
+            return Some(text);
+        };
+
+        let mut formatted: String<32> = String::new();
+        let (start, end) = match &field.kind {
+            FieldKind::Hex => {
+                let _ = field.format_value(value, &mut formatted);
+                (part, part + 1)
+            }
+            FieldKind::Decimal => {
+                let _ = write!(formatted, "{:0width$}", value, width = field.digits());
+                (part, part + 1)
+            }
+            FieldKind::Date { .. } => {
+                let _ = field.format_value(value, &mut formatted);
+                match part {
+                    0 => (0, 4),
+                    1 => (5, 7),
+                    _ => (8, 10),
+                }
+            }
+            FieldKind::Enum(_) | FieldKind::Otp | FieldKind::Checksum { .. } => {
+                let _ = field.format_value(value, &mut formatted);
+                (0, formatted.len())
+            }
+            FieldKind::Flags(bits) => {
+                if let Some((bit, label)) = bits.get(part) {
+                    let set = value & (1 << bit) != 0;
+                    let _ = write!(formatted, "{}:{}", label, if set { 1 } else { 0 });
+                }
+                (0, formatted.len())
+            }
+        };
+        let end = end.min(formatted.len());
+        let start = start.min(end);
+        let _ = write!(
+            text,
+            "{}[{}]{}",
+            &formatted[..start],
+            &formatted[start..end],
+            &formatted[end..]
+        );
+        Some(text)
+    }
+
+    /// Decoded binary counter for blocks 5 and 6 when no profile field covers them.
+    pub fn counter_text(&self) -> Option<String<40>> {
+        let block = self.selected_block;
+        if self.data.chip_type().block_kind(block) != BlockKind::Counter {
+            return None;
+        }
+        let value = self.data.counter(block);
+        let mut text: String<40> = String::new();
+        let _ = write!(text, "cnt{}={} (-{})", block, value, counter_used(value));
+        if self.data.flags[block] & FLAG_TORN != 0 {
+            let _ = text.push_str(" TORN");
+        }
+        Some(text)
+    }
+
+    fn adjust_field(&mut self, delta: i32) {
+        let Some(fe) = self.field_edit else {
+            return;
+        };
+        let Some(field) = self.profile.as_ref().and_then(|p| p.fields.get(fe.field)) else {
+            return;
+        };
+        let value = field.adjust(field.read(&self.data), fe.part, delta);
+        field.write(&mut self.data, value);
+    }
+
+    /// Runs `change` on the data and brings the profile's checksum fields up
+    /// to date, recording both in `patch` and `history`. Counters only count
+    /// down, so a change that raises one is undone and refused, unless it
+    /// puts the counter back as opened.
+    fn edit(&mut self, change: impl FnOnce(&mut Self)) -> Result<(), RangeError> {
+        let mut result = Ok(());
+        self.track(|ed| {
+            let before = ed.data.blocks;
+            change(ed);
+            let raised = ed.counter_blocks().find(|&b| {
+                ed.data.counter(b) > u32::from_le_bytes(before[b])
+                    && ed.data.blocks[b] != ed.original[b]
+            });
+            if let Some(block) = raised {
+                ed.data.blocks = before;
+                result = Err(RangeError::CountsUp(block));
+                return;
+            }
+            if let Some(profile) = ed.profile.as_ref() {
+                profile.update_checksums(&mut ed.data);
+            }
+        });
+        result
+    }
+
+    fn counter_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        let chip = self.data.chip_type();
+        (0..self.data.block_count).filter(move |&b| chip.block_kind(b) == BlockKind::Counter)
+    }
+
+    fn track(&mut self, change: impl FnOnce(&mut Self)) {
+        let before = self.data.blocks;
+        change(self);
+        let count = self.data.block_count;
+        let after = &self.data.blocks[..count];
+        self.patch
+            .record_blocks(&before[..count], after, self.profile.as_ref());
+        self.history.push(&before[..count], after);
+    }
+
+    /// Steps back through the history; false when there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.replay(History::undo)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.replay(History::redo)
+    }
+
+    fn replay(&mut self, step: fn(&mut History, &mut [[u8; 4]]) -> bool) -> bool {
+        let before = self.data.blocks;
+        if !step(&mut self.history, &mut self.data.blocks) {
+            return false;
+        }
+        let count = self.data.block_count;
+        self.patch.record_blocks(
+            &before[..count],
+            &self.data.blocks[..count],
+            self.profile.as_ref(),
+        );
+        true
+    }
+
+    pub fn is_modified(&self, block: usize) -> bool {
+        self.original
+            .get(block)
+            .is_some_and(|b| *b != self.data.blocks[block])
+    }
+
+    pub fn modified_count(&self) -> usize {
+        (0..self.original.len())
+            .filter(|&b| self.is_modified(b))
+            .count()
+    }
+
+    /// Puts the selected block back as opened, as one undoable edit.
+    /// Checksums covering it are recomputed.
+    pub fn revert_block(&mut self) -> Result<(), RangeError> {
+        let block = self.selected_block;
+        self.edit_step(|ed| ed.data.blocks[block] = ed.original[block])
+    }
+
+    /// Puts every block back as opened, checksums included, as one undoable
+    /// edit.
+    pub fn revert_all(&mut self) {
+        let count = self.original.len();
+        self.history.seal();
+        self.track(|ed| ed.data.blocks[..count].copy_from_slice(&ed.original));
+        self.history.seal();
+    }
+
+    /// Applies a patch made on another dump as one edit, or nothing when any
+    /// change conflicts.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<usize, ApplyError> {
+        let conflicts = patch.conflicts(&self.data);
+        if !conflicts.is_empty() {
+            return Err(ApplyError::Conflicts(conflicts));
+        }
+        let mut changed = 0;
+        self.edit_step(|ed| changed = patch.apply(&mut ed.data, false).unwrap_or(0))
+            .map_err(ApplyError::Refused)?;
+        Ok(changed)
+    }
+
+    /// First and last block of the marked range, or the selected block alone.
+    pub fn range(&self) -> (usize, usize) {
+        let block = self.selected_block;
+        let mark = self.mark.unwrap_or(block);
+        (mark.min(block), mark.max(block))
+    }
+
+    pub fn in_range(&self, block: usize) -> bool {
+        let (first, last) = self.range();
+        self.mark.is_some() && block >= first && block <= last
+    }
+
+    /// Starts a range at the selected block, or drops the one there is.
+    pub fn toggle_mark(&mut self) {
+        self.mark = match self.mark {
+            Some(_) => None,
+            None => Some(self.selected_block),
+        };
+    }
+
+    /// Blocks range operations leave alone, from the chip memory map, the
+    /// profile's `otp` fields and the lock register.
+    pub fn protected(&self, block: usize) -> bool {
+        self.data.chip_type().block_kind(block) != BlockKind::User
+            || self.otp_region(block).is_some()
+            || self.data.flags[block] & (FLAG_UNREADABLE | FLAG_LOCKED) != 0
+    }
+
+    fn check_writable(&self, first: usize, last: usize) -> Result<(), RangeError> {
+        match (first..=last).find(|&b| self.protected(b)) {
+            Some(block) => Err(RangeError::Protected(block)),
+            None => Ok(()),
+        }
+    }
+
+    /// Copies the range and drops the mark; returns the block count.
+    pub fn copy(&mut self) -> usize {
+        let (first, last) = self.range();
+        self.clipboard = self.data.blocks[first..=last].to_vec();
+        self.mark = None;
+        self.clipboard.len()
+    }
+
+    /// Writes the copied blocks from the selected block on, as one edit.
+    pub fn paste(&mut self) -> Result<usize, RangeError> {
+        if self.clipboard.is_empty() {
+            return Err(RangeError::NothingCopied);
+        }
+        let first = self.selected_block;
+        let last = first + self.clipboard.len() - 1;
+        if last >= self.data.block_count {
+            return Err(RangeError::PastEnd);
+        }
+        self.check_writable(first, last)?;
+        let blocks = core::mem::take(&mut self.clipboard);
+        let result = self.edit_step(|ed| ed.data.blocks[first..=last].copy_from_slice(&blocks));
+        self.clipboard = blocks;
+        result.map(|_| last - first + 1)
+    }
+
+    /// Repeats `pattern` over the range, as one edit.
+    pub fn fill(&mut self, pattern: &[[u8; 4]]) -> Result<usize, RangeError> {
+        if pattern.is_empty() {
+            return Err(RangeError::NothingCopied);
+        }
+        let (first, last) = self.range();
+        self.check_writable(first, last)?;
+        self.edit_step(|ed| {
+            for (block, value) in (first..=last).zip(pattern.iter().cycle()) {
+                ed.data.blocks[block] = *value;
+            }
+        })?;
+        self.mark = None;
+        Ok(last - first + 1)
+    }
+
+    /// [`fill`](Self::fill) with the copied blocks as the pattern.
+    pub fn fill_copied(&mut self) -> Result<usize, RangeError> {
+        let pattern = core::mem::take(&mut self.clipboard);
+        let result = self.fill(&pattern);
+        self.clipboard = pattern;
+        result
+    }
+
+    /// Erases every block of user memory to `FF FF FF FF`, as one edit;
+    /// returns the number that changed.
+    pub fn clear_user_area(&mut self) -> Result<usize, RangeError> {
+        let blocks: Vec<usize> = (0..self.data.block_count)
+            .filter(|&b| !self.protected(b) && self.data.blocks[b] != [0xFF; 4])
+            .collect();
+        self.edit_step(|ed| {
+            for &block in &blocks {
+                ed.data.blocks[block] = [0xFF; 4];
+            }
+        })?;
+        Ok(blocks.len())
+    }
+
+    /// Exchanges the marked block with the selected one, as one edit.
+    pub fn swap(&mut self) -> Result<(), RangeError> {
+        let (a, b) = (self.mark.ok_or(RangeError::NoMark)?, self.selected_block);
+        self.check_writable(a, a)?;
+        self.check_writable(b, b)?;
+        self.edit_step(|ed| ed.data.blocks.swap(a, b))?;
+        self.mark = None;
+        Ok(())
+    }
+
+    /// [`edit`](Self::edit) as an undo step of its own.
+    fn edit_step(&mut self, change: impl FnOnce(&mut Self)) -> Result<(), RangeError> {
+        self.history.seal();
+        let result = self.edit(change);
+        self.history.seal();
+        result
+    }
+
+    /// Moves the cursor to `block`, leaving edit mode; false when there is no
+    /// such block.
+    pub fn jump_to(&mut self, block: usize) -> bool {
+        if block >= self.data.block_count {
+            return false;
+        }
+        self.history.seal();
+        self.edit_mode = false;
+        self.field_edit = None;
+        self.otp = None;
+        self.hit = None;
+        self.selected_block = block;
+        self.selected_byte = 0;
+        self.selected_nibble = 0;
+        true
+    }
+
+    /// Jumps to the nearest block after (or before) the cursor for which
+    /// `wanted` holds.
+    fn jump_next(&mut self, forward: bool, wanted: impl Fn(&Self, usize) -> bool) -> bool {
+        let block = self.selected_block;
+        let found = if forward {
+            (block + 1..self.data.block_count).find(|&b| wanted(self, b))
+        } else {
+            (0..block).rev().find(|&b| wanted(self, b))
+        };
+        found.is_some_and(|b| self.jump_to(b))
+    }
+
+    /// Next or previous block that is not `FF FF FF FF`.
+    pub fn next_used(&mut self, forward: bool) -> bool {
+        self.jump_next(forward, |ed, b| {
+            ed.data.blocks[b] != [0xFF; 4] && ed.data.flags[b] & FLAG_UNREADABLE == 0
+        })
+    }
+
+    pub fn next_modified(&mut self, forward: bool) -> bool {
+        self.jump_next(forward, Self::is_modified)
+    }
+
+    /// Looks for `pattern` anywhere in the dump, also across block borders,
+    /// from the cursor on.
+    pub fn find(&mut self, pattern: Vec<u8>) -> bool {
+        self.search = pattern;
+        let from = self.selected_block * 4;
+        self.find_from(from, true)
+    }
+
+    /// The match after (or before) the one shown.
+    pub fn find_again(&mut self, forward: bool) -> bool {
+        let at = self.hit.map_or(self.selected_block * 4, |(pos, _)| pos);
+        if forward {
+            self.find_from(at + 1, true)
+        } else {
+            at > 0 && self.find_from(at - 1, false)
+        }
+    }
+
+    fn find_from(&mut self, from: usize, forward: bool) -> bool {
+        let len = self.search.len();
+        let end = self.data.block_count * 4;
+        if len == 0 || len > end {
+            return false;
+        }
+        let last = end - len;
+        let matches = |pos: usize| {
+            (pos / 4..=(pos + len - 1) / 4).all(|b| self.data.flags[b] & FLAG_UNREADABLE == 0)
+                && (0..len).all(|i| {
+                    let at = pos + i;
+                    self.data.blocks[at / 4][at % 4] == self.search[i]
+                })
+        };
+        let found = if forward {
+            (from..=last).find(|&pos| matches(pos))
+        } else {
+            (0..=from.min(last)).rev().find(|&pos| matches(pos))
+        };
+        let Some(pos) = found else {
+            return false;
+        };
+        self.jump_to(pos / 4);
+        self.selected_byte = pos % 4;
+        self.hit = Some((pos, len));
+        true
+    }
+
+    /// True when the byte is part of the match shown.
+    pub fn is_hit(&self, block: usize, byte: usize) -> bool {
+        let at = block * 4 + byte;
+        self.hit
+            .is_some_and(|(pos, len)| at >= pos && at < pos + len)
+    }
+
+    /// True when a checksum field in `block` does not match the data.
+    pub fn checksum_bad(&self, block: usize) -> bool {
+        self.profile.as_ref().is_some_and(|p| {
+            p.bad_checksums(&self.data).any(|f| {
+                let (first, last) = f.blocks();
+                block >= first && block <= last
+            })
+        })
+    }
+
     fn increment_nibble(&mut self) {
         let block = &mut self.data.blocks[self.selected_block];
         let byte = &mut block[self.selected_byte];
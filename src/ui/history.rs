@@ -0,0 +1,207 @@
+//! Undo/redo for the chip editor
+//!
+//! A step holds every block one edit changed, checksum fields included, so
+//! undoing it restores the dump exactly. Rotating on the same part of a value
+//! extends the last step instead of adding one per detent; `seal` ends that.
+
+use alloc::vec::Vec;
+
+/// The oldest steps are dropped past either limit, which keeps the history
+/// under 5 KB of heap. A step over a whole 128-block tag counts 128 edits.
+pub const MAX_STEPS: usize = 64;
+pub const MAX_EDITS: usize = 384;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BlockEdit {
+    block: u8,
+    before: [u8; 4],
+    after: [u8; 4],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    undo: Vec<Vec<BlockEdit>>,
+    redo: Vec<Vec<BlockEdit>>,
+    /// The last step may still grow.
+    open: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the difference between `before` and `after` as a step, or merges
+    /// it into the open step when that changed the same blocks.
+    pub fn push(&mut self, before: &[[u8; 4]], after: &[[u8; 4]]) {
+        let step: Vec<BlockEdit> = before
+            .iter()
+            .zip(after)
+            .enumerate()
+            .filter(|(_, (b, a))| b != a)
+            .map(|(block, (b, a))| BlockEdit {
+                block: block as u8,
+                before: *b,
+                after: *a,
+            })
+            .collect();
+        if step.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        if let Some(last) = self.undo.last_mut().filter(|_| self.open) {
+            if last.len() == step.len() && last.iter().zip(&step).all(|(l, s)| l.block == s.block) {
+                for (l, s) in last.iter_mut().zip(&step) {
+                    l.after = s.after;
+                }
+                return;
+            }
+        }
+        let mut kept = step.len() + self.undo.iter().map(Vec::len).sum::<usize>();
+        while (kept > MAX_EDITS || self.undo.len() == MAX_STEPS) && !self.undo.is_empty() {
+            kept -= self.undo.remove(0).len();
+        }
+        self.undo.push(step);
+        self.open = true;
+    }
+
+    /// The next edit starts a new step.
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    /// Puts the blocks of the last step back; false when there is none.
+    pub fn undo(&mut self, blocks: &mut [[u8; 4]]) -> bool {
+        self.open = false;
+        let Some(step) = self.undo.pop() else {
+            return false;
+        };
+        for edit in &step {
+            blocks[edit.block as usize] = edit.before;
+        }
+        self.redo.push(step);
+        true
+    }
+
+    pub fn redo(&mut self, blocks: &mut [[u8; 4]]) -> bool {
+        self.open = false;
+        let Some(step) = self.redo.pop() else {
+            return false;
+        };
+        for edit in &step {
+            blocks[edit.block as usize] = edit.after;
+        }
+        self.undo.push(step);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks() -> Vec<[u8; 4]> {
+        alloc::vec![[0xFF; 4]; 16]
+    }
+
+    /// Applies `change` to `blocks` and records it.
+    fn edit(history: &mut History, blocks: &mut [[u8; 4]], change: impl FnOnce(&mut [[u8; 4]])) {
+        let before = blocks.to_vec();
+        change(blocks);
+        history.push(&before, blocks);
+    }
+
+    #[test]
+    fn open_step_merges_edits_of_the_same_blocks() {
+        let (mut history, mut data) = (History::new(), blocks());
+        let start = data.clone();
+        edit(&mut history, &mut data, |b| b[8][0] = 0xFE);
+        edit(&mut history, &mut data, |b| b[8][0] = 0xFD);
+        edit(&mut history, &mut data, |b| b[8][1] = 0x00);
+        assert!(history.undo(&mut data));
+        assert_eq!(data, start);
+        assert!(!history.undo(&mut data));
+
+        // Sealed, or touching other blocks, the next edit is a step of its own
+        assert!(history.redo(&mut data));
+        edit(&mut history, &mut data, |b| b[9][0] = 0x01);
+        history.seal();
+        edit(&mut history, &mut data, |b| b[9][0] = 0x02);
+        assert!(history.undo(&mut data));
+        assert_eq!(data[9][0], 0x01);
+        assert!(history.undo(&mut data));
+        assert_eq!(data[9][0], 0xFF);
+        assert_eq!(data[8], [0xFD, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_steps() {
+        let (mut history, mut data) = (History::new(), blocks());
+        let mut states = alloc::vec![data.clone()];
+        for i in 0..3 {
+            edit(&mut history, &mut data, |b| {
+                b[i] = [i as u8; 4];
+                b[15][i] = 0;
+            });
+            history.seal();
+            states.push(data.clone());
+        }
+        for state in states.iter().rev().skip(1) {
+            assert!(history.undo(&mut data));
+            assert_eq!(&data, state);
+        }
+        for state in &states[1..] {
+            assert!(history.redo(&mut data));
+            assert_eq!(&data, state);
+        }
+        assert!(!history.redo(&mut data));
+
+        // A new edit after undoing drops what could be redone
+        history.undo(&mut data);
+        edit(&mut history, &mut data, |b| b[4] = [4; 4]);
+        assert!(!history.redo(&mut data));
+        // An edit that changes nothing is no step
+        edit(&mut history, &mut data, |_| {});
+        assert!(history.undo(&mut data));
+        assert_eq!(data[4], [0xFF; 4]);
+    }
+
+    #[test]
+    fn keeps_the_newest_steps() {
+        let (mut history, mut data) = (History::new(), blocks());
+        for i in 0..MAX_STEPS + 5 {
+            edit(&mut history, &mut data, |b| b[i % 2][0] = i as u8);
+            history.seal();
+        }
+        let mut undone = 0;
+        while history.undo(&mut data) {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_STEPS);
+        assert_eq!(data[0][0], 4);
+        assert_eq!(data[1][0], 3);
+    }
+
+    #[test]
+    fn large_steps_count_every_block() {
+        let mut history = History::new();
+        let mut data = alloc::vec![[0xFF; 4]; 128];
+        let per_step = MAX_EDITS / 128;
+        for i in 0..per_step + 2 {
+            edit(&mut history, &mut data, |b| b.fill([i as u8; 4]));
+            history.seal();
+        }
+        let mut undone = 0;
+        while history.undo(&mut data) {
+            undone += 1;
+        }
+        assert_eq!(undone, per_step);
+        assert_eq!(data, alloc::vec![[1; 4]; 128]);
+
+        // Edits that were undone stay within the cap while redone
+        while history.redo(&mut data) {}
+        edit(&mut history, &mut data, |b| b[0] = [0; 4]);
+        assert!(history.undo(&mut data));
+        assert_eq!(data, alloc::vec![[per_step as u8 + 1; 4]; 128]);
+    }
+}
@@ -0,0 +1,331 @@
+//! Dump library: stored dumps with sorting, a UID prefix filter and per-dump actions
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::drivers::storage::{DumpInfo, DUMP_NAME_LEN};
+
+/// Rows above the dump list: sort order and UID filter.
+pub const HEADER_ROWS: usize = 2;
+
+pub const ACTIONS: [&str; 6] = [
+    "Open",
+    "Diff with open",
+    "Analyze listed",
+    "Write to tag",
+    "Rename",
+    "Delete",
+];
+
+/// Placeholder for "no character"; stripped when an input is finished.
+pub const INPUT_GAP: u8 = b'_';
+
+const NAME_CHARS: &[u8] = b"_ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-.";
+pub const HEX_CHARS: &[u8] = b"_0123456789ABCDEF";
+pub const DIGIT_CHARS: &[u8] = b"_0123456789";
+/// Printable ASCII for text search, most likely characters first.
+pub const TEXT_CHARS: &[u8] =
+    b"_ ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-.,:;/!?#%&*+=()@";
+const NAME_MAX: usize = DUMP_NAME_LEN;
+const FILTER_MAX: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    /// Store order, newest first
+    Recent,
+    Name,
+    Uid,
+}
+
+impl SortKey {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortKey::Recent => "Recent",
+            SortKey::Name => "Name",
+            SortKey::Uid => "UID",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            SortKey::Recent => SortKey::Name,
+            SortKey::Name => SortKey::Uid,
+            SortKey::Uid => SortKey::Recent,
+        }
+    }
+}
+
+/// Encoder-driven text entry: rotate picks the character under the cursor,
+/// press moves on, back finishes. Gap characters are dropped on finish.
+#[derive(Debug, Clone)]
+pub struct TextInput {
+    pub text: Vec<u8>,
+    pub cursor: usize,
+    charset: &'static [u8],
+    max_len: usize,
+}
+
+impl TextInput {
+    pub fn new(initial: &str, charset: &'static [u8], max_len: usize) -> Self {
+        let mut text: Vec<u8> = initial
+            .bytes()
+            .map(|c| {
+                if charset.contains(&c) {
+                    c
+                } else {
+                    c.to_ascii_uppercase()
+                }
+            })
+            .filter(|c| charset.contains(c))
+            .take(max_len)
+            .collect();
+        if text.is_empty() {
+            text.push(INPUT_GAP);
+        }
+        Self {
+            text,
+            cursor: 0,
+            charset,
+            max_len,
+        }
+    }
+
+    pub fn rotate(&mut self, up: bool) {
+        let current = self.text[self.cursor];
+        let pos = self.charset.iter().position(|&c| c == current).unwrap_or(0);
+        let len = self.charset.len();
+        let next = if up {
+            (pos + 1) % len
+        } else {
+            (pos + len - 1) % len
+        };
+        self.text[self.cursor] = self.charset[next];
+    }
+
+    /// Moves to the next character, extending the text at the end and wrapping
+    /// back to the start once it is full.
+    pub fn advance(&mut self) {
+        self.cursor += 1;
+        if self.cursor == self.text.len() {
+            if self.text.len() < self.max_len {
+                self.text.push(INPUT_GAP);
+            } else {
+                self.cursor = 0;
+            }
+        }
+    }
+
+    pub fn finish(&self) -> String {
+        self.text
+            .iter()
+            .filter(|&&c| c != INPUT_GAP)
+            .map(|&c| c as char)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Mode {
+    Browse,
+    Actions(usize),
+    Rename(TextInput),
+    Filter(TextInput),
+    ConfirmDelete,
+}
+
+/// Storage or tag operation requested by the library screen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Open(String),
+    /// Compare with the dump in the viewer.
+    Diff(String),
+    /// Classify the bytes of these dumps, grouped by tag, oldest first.
+    Analyze(Vec<String>),
+    Write(String),
+    Rename {
+        from: String,
+        to: String,
+    },
+    Delete(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    None,
+    Exit,
+    Run(Command),
+}
+
+pub struct Library {
+    /// Dumps in store order.
+    pub entries: Vec<DumpInfo>,
+    /// Indices into `entries` after filtering and sorting.
+    pub visible: Vec<usize>,
+    /// Selected row; the first `HEADER_ROWS` rows are sort and filter.
+    pub selected: usize,
+    pub sort: SortKey,
+    /// Hex prefix of the MSB-first UID.
+    pub filter: String,
+    pub mode: Mode,
+}
+
+impl Library {
+    pub fn new(entries: Vec<DumpInfo>) -> Self {
+        let mut lib = Self {
+            entries,
+            visible: Vec::new(),
+            selected: HEADER_ROWS,
+            sort: SortKey::Recent,
+            filter: String::new(),
+            mode: Mode::Browse,
+        };
+        lib.refresh();
+        lib
+    }
+
+    /// Swaps in a fresh listing, keeping sort, filter and the selected dump if present.
+    pub fn reload(&mut self, entries: Vec<DumpInfo>) {
+        let current = self.selected_entry().map(|e| e.name.clone());
+        self.entries = entries;
+        self.mode = Mode::Browse;
+        self.refresh();
+        if let Some(name) = current {
+            if let Some(row) = self
+                .visible
+                .iter()
+                .position(|&i| self.entries[i].name == name)
+            {
+                self.selected = row + HEADER_ROWS;
+            }
+        }
+    }
+
+    fn refresh(&mut self) {
+        let filter = self.filter.as_bytes();
+        self.visible = (0..self.entries.len())
+            .filter(|&i| uid_hex(&self.entries[i].uid).as_bytes().starts_with(filter))
+            .collect();
+        let entries = &self.entries;
+        match self.sort {
+            SortKey::Recent => self.visible.reverse(),
+            SortKey::Name => self
+                .visible
+                .sort_by(|&a, &b| entries[a].name.cmp(&entries[b].name)),
+            SortKey::Uid => self.visible.sort_by(|&a, &b| {
+                let ua = entries[a].uid.iter().rev();
+                let ub = entries[b].uid.iter().rev();
+                ua.cmp(ub)
+            }),
+        }
+        self.selected = self.selected.min(self.rows() - 1);
+    }
+
+    pub fn rows(&self) -> usize {
+        HEADER_ROWS + self.visible.len()
+    }
+
+    pub fn selected_entry(&self) -> Option<&DumpInfo> {
+        let row = self.selected.checked_sub(HEADER_ROWS)?;
+        self.visible.get(row).map(|&i| &self.entries[i])
+    }
+
+    /// Names of the listed dumps, each tag's together and oldest first.
+    fn listed_by_tag(&self) -> Vec<String> {
+        let mut listed: Vec<&DumpInfo> = self.visible.iter().map(|&i| &self.entries[i]).collect();
+        listed.sort_by(|a, b| (a.uid, a.timestamp, &a.name).cmp(&(b.uid, b.timestamp, &b.name)));
+        listed.into_iter().map(|e| e.name.clone()).collect()
+    }
+
+    pub fn rotate(&mut self, up: bool) {
+        match &mut self.mode {
+            Mode::Browse => {
+                if up {
+                    self.selected = self.selected.saturating_sub(1);
+                } else if self.selected + 1 < self.rows() {
+                    self.selected += 1;
+                }
+            }
+            Mode::Actions(i) => {
+                if up {
+                    *i = i.saturating_sub(1);
+                } else if *i + 1 < ACTIONS.len() {
+                    *i += 1;
+                }
+            }
+            Mode::Rename(input) | Mode::Filter(input) => input.rotate(up),
+            Mode::ConfirmDelete => {}
+        }
+    }
+
+    pub fn press(&mut self) -> Event {
+        match &mut self.mode {
+            Mode::Browse => match self.selected {
+                0 => {
+                    self.sort = self.sort.next();
+                    self.refresh();
+                }
+                1 => self.mode = Mode::Filter(TextInput::new(&self.filter, HEX_CHARS, FILTER_MAX)),
+                _ => {
+                    if self.selected_entry().is_some() {
+                        self.mode = Mode::Actions(0);
+                    }
+                }
+            },
+            Mode::Actions(i) => {
+                let action = *i;
+                let Some(name) = self.selected_entry().map(|e| e.name.clone()) else {
+                    self.mode = Mode::Browse;
+                    return Event::None;
+                };
+                match action {
+                    0 => return Event::Run(Command::Open(name)),
+                    1 => return Event::Run(Command::Diff(name)),
+                    2 => return Event::Run(Command::Analyze(self.listed_by_tag())),
+                    3 => return Event::Run(Command::Write(name)),
+                    4 => self.mode = Mode::Rename(TextInput::new(&name, NAME_CHARS, NAME_MAX)),
+                    _ => self.mode = Mode::ConfirmDelete,
+                }
+            }
+            Mode::Rename(input) | Mode::Filter(input) => input.advance(),
+            Mode::ConfirmDelete => {
+                if let Some(name) = self.selected_entry().map(|e| e.name.clone()) {
+                    self.mode = Mode::Browse;
+                    return Event::Run(Command::Delete(name));
+                }
+            }
+        }
+        Event::None
+    }
+
+    pub fn back(&mut self) -> Event {
+        let mode = core::mem::replace(&mut self.mode, Mode::Browse);
+        match mode {
+            Mode::Browse => return Event::Exit,
+            Mode::Filter(input) => {
+                self.filter = input.finish();
+                self.selected = HEADER_ROWS;
+                self.refresh();
+            }
+            Mode::Rename(input) => {
+                let to = input.finish();
+                if let Some(from) = self.selected_entry().map(|e| e.name.clone()) {
+                    if !to.is_empty() && to != from {
+                        return Event::Run(Command::Rename { from, to });
+                    }
+                }
+            }
+            Mode::Actions(_) | Mode::ConfirmDelete => {}
+        }
+        Event::None
+    }
+}
+
+/// MSB-first UID as hex, the order UIDs are printed on labels.
+pub fn uid_hex(uid: &[u8; 8]) -> heapless::String<16> {
+    let mut out = heapless::String::new();
+    for b in uid.iter().rev() {
+        let _ = write!(out, "{:02X}", b);
+    }
+    out
+}
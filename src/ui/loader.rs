@@ -0,0 +1,140 @@
+//! "Load Serial": a pasted dump parsed as it arrives, between main loop passes
+
+use heapless::Deque;
+use log::info;
+
+use crate::interop::text::{Line, LineError, TextImport};
+use crate::interop::ImportError;
+use crate::protocol::st25tb::{ChipData, SYSTEM_BLOCK};
+use crate::util::quiet::Quiet;
+
+/// Blocks shown in the preview, most recent last.
+pub const PREVIEW_ROWS: usize = 8;
+/// Quiet time after which the paste is taken as complete (or abandoned if
+/// nothing arrived).
+pub const IDLE_TIMEOUT_MS: u64 = 30_000;
+/// Progress screen refresh interval while bytes stream in.
+const REDRAW_MS: u64 = 250;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// `END` received or BTN pressed.
+    Finish,
+    Cancel,
+    /// Nothing received for `IDLE_TIMEOUT_MS`.
+    TimedOut,
+}
+
+pub struct SerialLoad {
+    import: TextImport,
+    pub lines: usize,
+    pub last_error: Option<(usize, LineError)>,
+    pub expected: Option<usize>,
+    pub preview: Deque<(usize, [u8; 4]), PREVIEW_ROWS>,
+    /// Flipper or JSON lines collected for conversion at the end.
+    pub file_lines: usize,
+    outcome: Option<Outcome>,
+    last_input_ms: u64,
+    last_draw_ms: u64,
+    dirty: bool,
+    /// No log lines while the paste comes in, apart from rejected lines.
+    quiet: Quiet,
+}
+
+impl SerialLoad {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            import: TextImport::new(),
+            lines: 0,
+            last_error: None,
+            expected: None,
+            preview: Deque::new(),
+            file_lines: 0,
+            outcome: None,
+            last_input_ms: now_ms,
+            last_draw_ms: 0,
+            dirty: true,
+            quiet: Quiet::new(),
+        }
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.import.blocks
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.import.errors
+    }
+
+    pub fn push(&mut self, byte: u8, now_ms: u64) {
+        // Anything after END waits for the next load
+        if self.outcome.is_some() {
+            return;
+        }
+        self.last_input_ms = now_ms;
+        let Some((line_no, line)) = self.import.push(byte) else {
+            return;
+        };
+        self.lines = line_no;
+        self.dirty = true;
+        match line {
+            Line::End => self.outcome = Some(Outcome::Finish),
+            Line::Error(e) => {
+                self.quiet.lift(|| info!("Line {}: {:?}", line_no, e));
+                self.last_error = Some((line_no, e));
+            }
+            Line::BlockCount(count) => self.expected = Some(count),
+            Line::File => self.file_lines += 1,
+            Line::Block(i) => self.add_preview(i),
+            Line::System => self.add_preview(SYSTEM_BLOCK),
+            Line::Uid | Line::Skipped => {}
+        }
+    }
+
+    fn add_preview(&mut self, index: usize) {
+        if let Some(block) = self.import.block(index) {
+            if self.preview.is_full() {
+                self.preview.pop_front();
+            }
+            let _ = self.preview.push_back((index, block));
+        }
+    }
+
+    pub fn request(&mut self, outcome: Outcome) {
+        self.outcome.get_or_insert(outcome);
+    }
+
+    /// How the load ended, once it has.
+    pub fn poll(&self, now_ms: u64) -> Option<Outcome> {
+        self.outcome.or_else(|| {
+            (now_ms.saturating_sub(self.last_input_ms) >= IDLE_TIMEOUT_MS)
+                .then_some(Outcome::TimedOut)
+        })
+    }
+
+    /// Whether the progress screen is due for a refresh (new lines, or the
+    /// idle countdown ticking); marks it drawn.
+    pub fn take_redraw(&mut self, now_ms: u64) -> bool {
+        let since = now_ms.saturating_sub(self.last_draw_ms);
+        if !(self.dirty && since >= REDRAW_MS || since >= 1000) {
+            return false;
+        }
+        self.dirty = false;
+        self.last_draw_ms = now_ms;
+        true
+    }
+
+    /// Seconds left before the idle timeout.
+    pub fn idle_left(&self, now_ms: u64) -> u64 {
+        IDLE_TIMEOUT_MS.saturating_sub(now_ms.saturating_sub(self.last_input_ms)) / 1000
+    }
+
+    /// Anything worth converting arrived.
+    pub fn has_data(&self) -> bool {
+        self.import.blocks > 0 || self.file_lines > 0
+    }
+
+    pub fn finish(self) -> Result<ChipData, ImportError> {
+        self.import.finish()
+    }
+}
@@ -1,5 +1,12 @@
+pub mod compare;
 pub mod display;
 pub mod editor;
+pub mod history;
+pub mod library;
+pub mod loader;
 
+pub use compare::Compare;
 pub use display::Display;
 pub use editor::ChipEditor;
+pub use library::Library;
+pub use loader::SerialLoad;
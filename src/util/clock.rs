@@ -0,0 +1,23 @@
+//! Wall-clock time. The board has no battery-backed RTC, so the time is unknown
+//! until it is set (e.g. by the host) and is lost on power-off.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use esp_hal::time::Instant;
+
+/// Unix time at boot, 0 while unset.
+static BOOT_TIME: AtomicU32 = AtomicU32::new(0);
+
+fn uptime_secs() -> u32 {
+    Instant::now().duration_since_epoch().as_secs() as u32
+}
+
+pub fn set_unix_time(now: u32) {
+    BOOT_TIME.store(now.saturating_sub(uptime_secs()), Ordering::Relaxed);
+}
+
+pub fn unix_time() -> Option<u32> {
+    match BOOT_TIME.load(Ordering::Relaxed) {
+        0 => None,
+        boot => Some(boot + uptime_secs()),
+    }
+}
@@ -0,0 +1,82 @@
+//! Consistent Overhead Byte Stuffing: removes every zero byte so that zero can
+//! delimit frames on a byte stream.
+
+use alloc::vec::Vec;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_pos = 0;
+    out.push(0);
+    let mut code = 1u8;
+    for &b in data {
+        if b != 0 {
+            out.push(b);
+            code += 1;
+        }
+        if b == 0 || code == 0xFF {
+            out[code_pos] = code;
+            code_pos = out.len();
+            out.push(0);
+            code = 1;
+        }
+    }
+    out[code_pos] = code;
+    out
+}
+
+/// `None` if `data` contains a zero or a block runs past the end.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return None;
+        }
+        let block = &data[i + 1..i + code];
+        if block.contains(&0) {
+            return None;
+        }
+        out.extend_from_slice(block);
+        i += code;
+        if code < 0xFF && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn round_trip() {
+        let mut long = Vec::new();
+        for len in [253, 254, 255, 256, 508, 600] {
+            long.push((1..=len).map(|i| (i % 255 + 1) as u8).collect::<Vec<u8>>());
+        }
+        let cases = [
+            vec![],
+            vec![0],
+            vec![0, 0],
+            vec![1, 0, 2],
+            vec![0x11, 0x22, 0x00],
+        ];
+        for data in cases.iter().chain(&long) {
+            let encoded = encode(data);
+            assert!(!encoded.contains(&0), "zero in {:02X?}", encoded);
+            assert!(encoded.len() <= data.len() + data.len() / 254 + 2);
+            assert_eq!(decode(&encoded).as_ref(), Some(data));
+        }
+        assert_eq!(encode(&[0x11, 0x00, 0x22]), [0x02, 0x11, 0x02, 0x22]);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(decode(&[0x02, 0x11, 0x00]), None);
+        assert_eq!(decode(&[0x05, 0x11, 0x22]), None);
+        assert_eq!(decode(&[0x00]), None);
+    }
+}
@@ -0,0 +1,40 @@
+pub fn nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+pub fn byte(high: u8, low: u8) -> Option<u8> {
+    let h = nibble(high)?;
+    let l = nibble(low)?;
+    Some((h << 4) | l)
+}
+
+/// Parses space-separated (or packed) hex bytes into `out`, returning the count.
+pub fn parse_bytes(s: &str, out: &mut [u8]) -> Option<usize> {
+    let mut count = 0;
+    let mut high: Option<u8> = None;
+    for c in s.bytes() {
+        if c == b' ' || c == b'\t' || c == b':' || c == b'-' {
+            if high.is_some() {
+                return None;
+            }
+            continue;
+        }
+        let n = nibble(c)?;
+        match high.take() {
+            None => high = Some(n),
+            Some(h) => {
+                *out.get_mut(count)? = (h << 4) | n;
+                count += 1;
+            }
+        }
+    }
+    if high.is_some() {
+        return None;
+    }
+    Some(count)
+}
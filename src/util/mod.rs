@@ -0,0 +1,5 @@
+pub mod clock;
+pub mod cobs;
+pub mod crc;
+pub mod hex;
+pub mod quiet;
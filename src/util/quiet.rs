@@ -0,0 +1,41 @@
+//! Clean mode for the USB serial link
+//!
+//! Log text and data share the port. Host protocol frames are told apart by
+//! their framing, but dumps meant to be copied or captured as they are ("Dump
+//! Serial", a "Load Serial" paste, console output) would pick up log lines in
+//! between. A `Quiet` guard turns logging off until it is dropped.
+
+use log::LevelFilter;
+
+pub struct Quiet {
+    level: LevelFilter,
+}
+
+impl Quiet {
+    pub fn new() -> Self {
+        let level = log::max_level();
+        log::set_max_level(LevelFilter::Off);
+        Self { level }
+    }
+
+    /// Runs `f` with logging back at its previous level, for messages the
+    /// user should still see (parse errors in a paste, say).
+    pub fn lift<R>(&self, f: impl FnOnce() -> R) -> R {
+        log::set_max_level(self.level);
+        let result = f();
+        log::set_max_level(LevelFilter::Off);
+        result
+    }
+}
+
+impl Default for Quiet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Quiet {
+    fn drop(&mut self) {
+        log::set_max_level(self.level);
+    }
+}